@@ -1,21 +1,54 @@
 #include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
 #include <inttypes.h>
 #include "types.h"
 #include "values.h"
 #include "runtime.h"
+#include "print.h"
 
+// Bytes read from stdin by peek_byte but not yet consumed by read_byte, oldest first.
+static int *lookahead = NULL;
+static size_t lookahead_len = 0;
+static size_t lookahead_cap = 0;
+
+// Reads bytes from stdin into the lookahead until it holds more than `skip` bytes or stdin runs out.
+static void fill_lookahead(size_t skip)
+{
+  while (lookahead_len <= skip) {
+    int c = getc(in);
+    if (c == EOF) return;
+    if (lookahead_len == lookahead_cap) {
+      size_t cap = lookahead_cap ? 2 * lookahead_cap : 16;
+      int *grown = realloc(lookahead, cap * sizeof(int));
+      if (grown == NULL) error_handler();
+      lookahead = grown;
+      lookahead_cap = cap;
+    }
+    lookahead[lookahead_len++] = c;
+  }
+}
+
+// Returns the next byte from stdin as a tagged integer, or the eof value.
 val_t read_byte(void)
 {
-  char c = getc(in);
+  if (lookahead_len > 0) {
+    int c = lookahead[0];
+    memmove(lookahead, lookahead + 1, --lookahead_len * sizeof(int));
+    return val_wrap_byte(c);
+  }
+  // Keep the result in int, so that the byte 255 is not confused with EOF.
+  int c = getc(in);
   return (c == EOF) ? val_wrap_eof() : val_wrap_byte(c);
 }
 
-val_t peek_byte(void)
+// Returns the byte `skip` bytes after the next one, leaving the bytes in stdin.
+// `skip` is a tagged nonnegative integer.
+val_t peek_byte(val_t skip)
 {
-  char c = getc(in);
-  ungetc(c, in);
-  return (c == EOF) ? val_wrap_eof() : val_wrap_byte(c);
-  
+  size_t n = (size_t) val_unwrap_int(skip);
+  fill_lookahead(n);
+  return (n < lookahead_len) ? val_wrap_byte(lookahead[n]) : val_wrap_eof();
 }
 
 val_t write_byte(val_t c)
@@ -23,3 +56,25 @@ val_t write_byte(val_t c)
   putc((char) val_unwrap_int(c), out);
   return val_wrap_void();
 }
+
+// Writes out the bytes buffered for stdout.
+val_t flush_output(void)
+{
+  fflush(out);
+  return val_wrap_void();
+}
+
+val_t write_string(val_t s)
+{
+  val_str_t *str = val_unwrap_str(s);
+  // The empty string is represented by a null pointer.
+  if (!str) return val_wrap_void();
+
+  uint64_t i;
+  char buffer[4];
+  for (i = 0; i < str->len; ++i) {
+    int n = utf8_encode_char(str->codepoints[i], buffer);
+    fwrite(buffer, 1, n, out);
+  }
+  return val_wrap_void();
+}
@@ -0,0 +1,95 @@
+#include <stdio.h>
+#include "types.h"
+#include "values.h"
+#include "runtime.h"
+
+// Returns true iff the values are structurally equal, i.e. `equal?` in Racket.
+static int equal(val_t x, val_t y)
+{
+  uint64_t i;
+  val_vect_t *v, *w;
+  val_str_t *s, *t;
+
+  if (x == y) return 1;
+  if (val_typeof(x) != val_typeof(y)) return 0;
+
+  switch (val_typeof(x)) {
+  case T_BOX:
+    return equal(val_unwrap_box(x)->val, val_unwrap_box(y)->val);
+  case T_CONS:
+    return equal(val_unwrap_cons(x)->fst, val_unwrap_cons(y)->fst)
+      && equal(val_unwrap_cons(x)->snd, val_unwrap_cons(y)->snd);
+  case T_VECT:
+    v = val_unwrap_vect(x);
+    w = val_unwrap_vect(y);
+    // The empty vector is represented by a null pointer, which is only equal to itself.
+    if (!v || !w || v->len != w->len) return 0;
+    for (i = 0; i < v->len; ++i)
+      if (!equal(v->elems[i], w->elems[i])) return 0;
+    return 1;
+  case T_STR:
+    s = val_unwrap_str(x);
+    t = val_unwrap_str(y);
+    // The empty string is represented by a null pointer, which is only equal to itself.
+    if (!s || !t || s->len != t->len) return 0;
+    for (i = 0; i < s->len; ++i)
+      if (s->codepoints[i] != t->codepoints[i]) return 0;
+    return 1;
+  default:
+    return 0;
+  }
+}
+
+// Returns the first pair in the association list whose car is the key, or false if there is none.
+// It raises an error if the list is improper or has an element which is not a pair.
+static val_t find_pair(val_t key, val_t alist, int structural)
+{
+  val_t pair, k;
+
+  while (alist != val_empty) {
+    if (val_typeof(alist) != T_CONS) error_handler();
+    pair = val_unwrap_cons(alist)->fst;
+    if (val_typeof(pair) != T_CONS) error_handler();
+
+    k = val_unwrap_cons(pair)->fst;
+    if (structural ? equal(key, k) : key == k) return pair;
+    alist = val_unwrap_cons(alist)->snd;
+  }
+  return val_false;
+}
+
+// Returns the first tail of the list whose car is the value, or false if there is none.
+// It raises an error if the list is improper.
+static val_t find_tail(val_t x, val_t list, int structural)
+{
+  val_t y;
+
+  while (list != val_empty) {
+    if (val_typeof(list) != T_CONS) error_handler();
+
+    y = val_unwrap_cons(list)->fst;
+    if (structural ? equal(x, y) : x == y) return list;
+    list = val_unwrap_cons(list)->snd;
+  }
+  return val_false;
+}
+
+val_t member(val_t x, val_t list)
+{
+  return find_tail(x, list, 1);
+}
+
+val_t memq(val_t x, val_t list)
+{
+  return find_tail(x, list, 0);
+}
+
+val_t assoc(val_t key, val_t alist)
+{
+  return find_pair(key, alist, 1);
+}
+
+val_t assq(val_t key, val_t alist)
+{
+  return find_pair(key, alist, 0);
+}
@@ -1,5 +1,7 @@
 #include <stdio.h>
 #include <stdlib.h>
+#include <inttypes.h>
+#include <sys/resource.h>
 #include "values.h"
 #include "print.h"
 #include "runtime.h"
@@ -15,13 +17,92 @@ void error_exit()
   exit(1);
 }
 
-void raise_error()
+// Categories of runtime errors, which must agree with `ErrorCode` in the compiler.
+typedef enum error_code_t {
+  ERR_GENERIC = 0,
+  ERR_INDEX_OUT_OF_RANGE = 1,
+  ERR_NEGATIVE_INDEX = 2,
+  ERR_CODEPOINT_OUT_OF_RANGE = 3,
+  ERR_SURROGATE_CODEPOINT = 4,
+  ERR_STACK_OVERFLOW = 5,
+  ERR_ASSERTION_FAILED = 6,
+} error_code_t;
+
+// Reports the error to stderr, with the details depending on the error code.
+void raise_error(error_code_t code, int64_t a, int64_t b)
+{
+  switch (code) {
+  case ERR_INDEX_OUT_OF_RANGE:
+    fprintf(stderr, "index out of range: index %" PRId64 ", length %" PRId64 "\n", a, b);
+    break;
+  case ERR_NEGATIVE_INDEX:
+    fprintf(stderr, "index must be a nonnegative integer: %" PRId64 "\n", a);
+    break;
+  case ERR_CODEPOINT_OUT_OF_RANGE:
+    fprintf(stderr, "integer->char: %" PRId64 " is out of the range of Unicode code points\n", a);
+    break;
+  case ERR_SURROGATE_CODEPOINT:
+    fprintf(stderr, "integer->char: %" PRId64 " is a surrogate code point\n", a);
+    break;
+  case ERR_STACK_OVERFLOW:
+    fprintf(stderr, "stack overflow: the recursion is too deep\n");
+    break;
+  case ERR_ASSERTION_FAILED:
+    fprintf(stderr, "assertion failed\n");
+    break;
+  default:
+    break;
+  }
+  return error_handler();
+}
+
+// Reports the error raised by the program with the message to stderr.
+void user_error(val_t message)
 {
+  val_str_t *str = val_unwrap_str(message);
+  fprintf(stderr, "error: ");
+  // The empty string is represented by a null pointer.
+  if (str) {
+    uint64_t i;
+    char buffer[4];
+    for (i = 0; i < str->len; ++i) {
+      int n = utf8_encode_char(str->codepoints[i], buffer);
+      fwrite(buffer, 1, n, stderr);
+    }
+  }
+  fprintf(stderr, "\n");
   return error_handler();
 }
 
+// Space left at the end of the stack for the runtime functions, including the error handler.
+#define stack_margin (1024 * 1024)
+
+// Used when the size of the stack is unlimited or unknown.
+#define default_stack_size (8 * 1024 * 1024)
+
+// Returns the lowest address the compiled program may grow the stack down to.
+char *stack_limit(char *stack_top)
+{
+  struct rlimit limit;
+  rlim_t size = default_stack_size;
+  if (getrlimit(RLIMIT_STACK, &limit) == 0 && limit.rlim_cur != RLIM_INFINITY) {
+    size = limit.rlim_cur;
+  }
+
+  uintptr_t top = (uintptr_t)stack_top;
+  // The stack cannot extend below the lowest address, however large the limit is.
+  if (size > top) {
+    size = top;
+  }
+  // Leave at most half of a small stack as the margin, so that the program can still make some calls.
+  rlim_t margin = size / 2 < stack_margin ? size / 2 : stack_margin;
+  return (char *)(top - size + margin);
+}
+
 int main(int argc, char** argv)
 {
+  char stack_top;
+
   in = stdin;
   out = stdout;
   error_handler = &error_exit;
@@ -29,7 +110,7 @@ int main(int argc, char** argv)
 
   val_t result;
 
-  result = entry(heap);
+  result = entry(heap, stack_limit(&stack_top));
 
   print_result(result);
 
@@ -9,6 +9,9 @@ void print_vect(val_vect_t*);
 void print_str(val_str_t*);
 void print_str_char(val_char_t);
 void print_result_interior(val_t);
+void display_value(val_t);
+void display_cons(val_cons_t *);
+void display_vect(val_vect_t *);
 int utf8_encode_char(val_char_t, char *);
 
 void print_result(val_t x)
@@ -68,6 +71,82 @@ void print_result_interior(val_t x)
   }
 }
 
+val_t display(val_t x)
+{
+  display_value(x);
+  return val_wrap_void();
+}
+
+void display_value(val_t x)
+{
+  uint64_t i;
+  val_str_t *s;
+
+  switch (val_typeof(x)) {
+  case T_CHAR:
+    print_codepoint(val_unwrap_char(x));
+    break;
+  case T_STR:
+    s = val_unwrap_str(x);
+    if (!s) break;
+    for (i = 0; i < s->len; ++i)
+      print_codepoint(s->codepoints[i]);
+    break;
+  case T_EMPTY:
+    printf("()");
+    break;
+  case T_BOX:
+    printf("#&");
+    display_value(val_unwrap_box(x)->val);
+    break;
+  case T_CONS:
+    printf("(");
+    display_cons(val_unwrap_cons(x));
+    printf(")");
+    break;
+  case T_VECT:
+    display_vect(val_unwrap_vect(x));
+    break;
+  default:
+    print_result(x);
+  }
+}
+
+void display_vect(val_vect_t *v)
+{
+  uint64_t i;
+
+  if (!v) { printf("#()"); return; }
+
+  printf("#(");
+  for (i = 0; i < v->len; ++i) {
+    display_value(v->elems[i]);
+
+    if (i < v->len - 1)
+      putchar(' ');
+  }
+  printf(")");
+}
+
+void display_cons(val_cons_t *cons)
+{
+  display_value(cons->fst);
+
+  switch (val_typeof(cons->snd)) {
+  case T_EMPTY:
+    // nothing
+    break;
+  case T_CONS:
+    printf(" ");
+    display_cons(val_unwrap_cons(cons->snd));
+    break;
+  default:
+    printf(" . ");
+    display_value(cons->snd);
+    break;
+  }
+}
+
 void print_vect(val_vect_t *v)
 {
   uint64_t i;
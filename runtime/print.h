@@ -6,4 +6,10 @@
 // Function prototype for print_result
 void print_result(val_t x);
 
+// Prints the value in the human-readable form, as Racket's `display` does.
+val_t display(val_t x);
+
+// Writes the UTF-8 encoding of the codepoint to the buffer, and returns the number of bytes written.
+int utf8_encode_char(val_char_t c, char *buffer);
+
 #endif // PRINT_H
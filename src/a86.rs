@@ -1,2 +1,13 @@
+//! An abstract representation of x86-64 assembly programs.
+//!
+//! This file is the root of the `a86` module, whose submodules live in `src/a86/`.
+//! [ast] defines the instructions emitted by the compiler, and [printer] renders them as NASM source.
+//! [peephole] removes redundant instructions from the compiled program,
+//! and [validate] checks its labels before it is printed.
+
 pub mod ast;
+pub mod peephole;
 pub mod printer;
+mod validation;
+
+pub use validation::validate;
@@ -6,26 +6,56 @@ pub enum Statement {
     Data,
     Text,
     Dq { value: i64 },
+    /// Declares a quad word holding the address of the label.
+    DqLabel { label: String },
     Dd { value: i32 },
+    /// Declares the bytes as they are, e.g. the UTF-8 encoding of a string.
+    ///
+    /// It is only supported by the backend for now; the compiler does not emit it yet.
+    #[allow(dead_code)]
+    Db { bytes: Vec<u8> },
     Mov { dest: Operand, src: Operand },
     And { dest: Operand, src: Operand },
     Or { dest: Operand, src: Operand },
     Xor { dest: Operand, src: Operand },
     Sar { dest: Operand, src: Operand },
     Sal { dest: Operand, src: Operand },
+    /// Negates the operand in two's complement.
+    Neg { dest: Operand },
+    /// Flips every bit of the operand.
+    Not { dest: Operand },
     Cmp { dest: Operand, src: Operand },
+    /// Sets the flags by the bitwise and of the operands, like [Statement::Cmp] does by the subtraction.
+    /// `test reg, reg` is the idiomatic way to compare a register with zero.
+    Test { dest: Operand, src: Operand },
     Cmove { dest: Operand, src: Operand },
     Cmovl { dest: Operand, src: Operand },
+    Cmovg { dest: Operand, src: Operand },
     Jmp { label: String },
     JmpRegister(Register),
     Je { label: String },
     Jne { label: String },
     Jg { label: String },
     Jl { label: String },
+    Jge { label: String },
+    /// Jumps if the destination of the last comparison is below the source as unsigned integers, e.g. addresses.
+    Jb { label: String },
+    /// Jumps if the last arithmetic instruction overflowed as a signed integer.
+    Jo { label: String },
     Push { src: Operand },
     Pop { dest: Operand },
     Add { dest: Operand, src: Operand },
     Sub { dest: Operand, src: Operand },
+    /// Multiplies the destination by the source as signed integers, keeping the lower 64 bits.
+    ///
+    /// Multiplying two tagged integers would scale the result by the tag twice,
+    /// so decode one of the operands first.
+    Imul { dest: Operand, src: Operand },
+    /// Sign-extends rax into rdx:rax, preparing the dividend for [Statement::Idiv].
+    Cqo,
+    /// Divides rdx:rax by the operand as signed integers,
+    /// storing the quotient in rax and the remainder in rdx.
+    Idiv { src: Operand },
     Lea { dest: Operand, label: String },
     LeaArithmetic { dest: Operand, expr: String },
     Call { label: String },
@@ -37,6 +67,13 @@ pub enum Operand {
     Immediate(i64),
     Register(Register),
     Offset(Register, i64),
+    /// The memory at `base + index * scale + disp`, where `scale` must be 1, 2, 4, or 8.
+    ScaledIndex {
+        base: Register,
+        index: Register,
+        scale: u8,
+        disp: i64,
+    },
 }
 
 #[allow(dead_code)]
@@ -46,6 +83,8 @@ pub enum Register {
     EAX,
     RBX,
     RDI,
+    RSI,
+    RDX,
     RSP,
     R1,
     R2,
@@ -0,0 +1,392 @@
+//! A peephole optimizer, which removes redundant instructions by looking at adjacent pairs of them.
+//!
+//! A label which may be jumped to is a barrier: two instructions on either side of it are never
+//! treated as adjacent, since the one after it may also be reached from elsewhere.
+//! The referenced labels are collected from the whole program before anything is removed.
+//! The other labels, e.g. the ones put for the source map, are kept but do not separate the instructions.
+//! No label is ever removed.
+
+use std::collections::HashSet;
+
+use super::ast::{Operand, Program, Register, Statement};
+
+/// Returns the program without the redundant instructions, repeating until nothing more is removed.
+pub fn optimize(program: Program) -> Program {
+    let mut statements = program.statements;
+    loop {
+        let referenced = referenced_labels(&statements);
+        let optimized = optimize_once(&statements, &referenced);
+        if optimized.len() == statements.len() {
+            return Program { statements };
+        }
+        statements = optimized;
+    }
+}
+
+/// Returns the labels which may be jumped to or whose addresses are taken.
+fn referenced_labels(statements: &[Statement]) -> HashSet<String> {
+    let mut labels = HashSet::new();
+    for statement in statements {
+        match statement {
+            Statement::Je { label }
+            | Statement::Jne { label }
+            | Statement::Jg { label }
+            | Statement::Jl { label }
+            | Statement::Jge { label }
+            | Statement::Jb { label }
+            | Statement::Jo { label }
+            | Statement::Jmp { label }
+            | Statement::Lea { label, .. }
+            | Statement::Call { label }
+            | Statement::DqLabel { label } => {
+                labels.insert(label.clone());
+            }
+            Statement::Global { name } => {
+                labels.insert(name.clone());
+            }
+            // The expression may mention labels among the other terms.
+            Statement::LeaArithmetic { expr, .. } => {
+                labels.extend(
+                    expr.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .filter(|word| !word.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            // `jmp rax` can only reach the labels whose addresses are taken by the statements above.
+            _ => {}
+        }
+    }
+    labels
+}
+
+fn optimize_once(statements: &[Statement], referenced: &HashSet<String>) -> Vec<Statement> {
+    let mut output: Vec<Statement> = vec![];
+    // The index in `output` of the last instruction, unless a barrier follows it.
+    let mut last: Option<usize> = None;
+
+    for (i, statement) in statements.iter().enumerate() {
+        let prev = last.map(|j| &output[j]);
+
+        match (prev, statement) {
+            (_, Statement::Label { name }) if !referenced.contains(name) => {
+                output.push(statement.clone());
+                continue;
+            }
+
+            // A jump to the label right after it.
+            (Some(Statement::Jmp { label }), Statement::Label { name }) if label == name => {
+                output.remove(last.unwrap());
+                output.push(statement.clone());
+                last = None;
+                continue;
+            }
+
+            // A move to itself, except that a 32-bit one clears the upper half of the register.
+            (_, Statement::Mov { dest, src })
+                if dest == src
+                    && !matches!(dest, Operand::Register(Register::EAX | Register::R9D)) =>
+            {
+                continue
+            }
+
+            (_, Statement::Add { src, .. } | Statement::Sub { src, .. })
+                if *src == Operand::Immediate(0) && !flags_may_be_read(&statements[i + 1..], referenced) =>
+            {
+                continue
+            }
+
+            // The value just moved back.
+            (Some(Statement::Mov { dest: d1, src: s1 }), Statement::Mov { dest: d2, src: s2 })
+                if d1 == s2 && s1 == d2 && !(is_memory(d1) && is_memory(s1)) && !reads(s1, d1) =>
+            {
+                continue
+            }
+
+            // The register overwritten before it is read.
+            (
+                Some(Statement::Mov {
+                    dest: Operand::Register(r1),
+                    ..
+                }),
+                Statement::Mov {
+                    dest: Operand::Register(r2),
+                    src,
+                },
+            ) if r1 == r2 && !reads(src, &Operand::Register(r1.clone())) => {
+                output.remove(last.unwrap());
+            }
+
+            (
+                Some(Statement::Push {
+                    src: Operand::Register(r1),
+                }),
+                Statement::Pop {
+                    dest: Operand::Register(r2),
+                },
+            ) if r1 == r2 => {
+                output.remove(last.unwrap());
+                last = None;
+                continue;
+            }
+
+            _ => {}
+        }
+
+        output.push(statement.clone());
+        last = match statement {
+            Statement::Label { .. } => None,
+            _ => Some(output.len() - 1),
+        };
+    }
+    output
+}
+
+/// Returns whether the flags may be read before they are set again,
+/// scanning through the instructions which leave them alone and the unreferenced labels.
+/// A referenced label or a jump may lead to another path reading the flags.
+fn flags_may_be_read(statements: &[Statement], referenced: &HashSet<String>) -> bool {
+    for statement in statements {
+        match statement {
+            Statement::Label { name } if !referenced.contains(name) => {}
+            Statement::Mov { .. }
+            | Statement::Not { .. }
+            | Statement::Push { .. }
+            | Statement::Pop { .. }
+            | Statement::Lea { .. }
+            | Statement::LeaArithmetic { .. }
+            | Statement::Cqo => {}
+
+            // These set all of the flags read by the conditional instructions.
+            Statement::Cmp { .. }
+            | Statement::Test { .. }
+            | Statement::Add { .. }
+            | Statement::Sub { .. }
+            | Statement::And { .. }
+            | Statement::Or { .. }
+            | Statement::Xor { .. }
+            | Statement::Neg { .. } => return false,
+
+            // The flags are not preserved across calls.
+            Statement::Call { .. } | Statement::Ret => return false,
+
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn is_memory(operand: &Operand) -> bool {
+    matches!(operand, Operand::Offset(..) | Operand::ScaledIndex { .. })
+}
+
+/// Returns whether evaluating the operand reads the register operand, including its aliases.
+fn reads(operand: &Operand, register: &Operand) -> bool {
+    let Operand::Register(register) = register else {
+        return false;
+    };
+    match operand {
+        Operand::Immediate(_) => false,
+        Operand::Register(r) => overlaps(r, register),
+        Operand::Offset(base, _) => overlaps(base, register),
+        Operand::ScaledIndex { base, index, .. } => {
+            overlaps(base, register) || overlaps(index, register)
+        }
+    }
+}
+
+/// Returns whether the registers share any bits, e.g. rax and eax.
+fn overlaps(a: &Register, b: &Register) -> bool {
+    fn full(register: &Register) -> &Register {
+        match register {
+            Register::EAX => &Register::RAX,
+            Register::R9D => &Register::R9,
+            register => register,
+        }
+    }
+    full(a) == full(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAX: Operand = Operand::Register(Register::RAX);
+    const R8: Operand = Operand::Register(Register::R8);
+    const R9: Operand = Operand::Register(Register::R9);
+
+    fn label(name: &str) -> Statement {
+        Statement::Label {
+            name: name.to_string(),
+        }
+    }
+
+    fn optimized(statements: Vec<Statement>) -> Vec<Statement> {
+        optimize(Program { statements }).statements
+    }
+
+    #[test]
+    fn removes_redundant_moves() {
+        let statements = vec![
+            Statement::Mov { dest: RAX, src: R9 },
+            Statement::Mov { dest: R9, src: RAX },
+            Statement::Mov { dest: R8, src: R8 },
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Immediate(1),
+            },
+            label("unreferenced"),
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Offset(Register::RSP, 0),
+            },
+            Statement::Push { src: RAX },
+            Statement::Pop { dest: RAX },
+            Statement::Add {
+                dest: Operand::Register(Register::RSP),
+                src: Operand::Immediate(0),
+            },
+            Statement::Ret,
+        ];
+        // rax is overwritten by the last move in the end.
+        let expected = vec![
+            label("unreferenced"),
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Offset(Register::RSP, 0),
+            },
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements), expected);
+    }
+
+    #[test]
+    fn keeps_moves_read_by_the_next() {
+        let statements = vec![
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Offset(Register::RSP, 8),
+            },
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Offset(Register::RAX, 0),
+            },
+            Statement::Mov {
+                dest: Operand::Register(Register::EAX),
+                src: Operand::Immediate(1),
+            },
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::ScaledIndex {
+                    base: Register::R8,
+                    index: Register::RAX,
+                    scale: 4,
+                    disp: 8,
+                },
+            },
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements.clone()), statements);
+    }
+
+    #[test]
+    fn labels_jumped_to_are_barriers() {
+        // The loop counts r9 down to zero, copying it to rax on each iteration.
+        // Removing `mov r9, rax` as the inverse of `mov rax, r9` would be wrong,
+        // since rax has been decremented when the back edge reaches the label.
+        let statements = vec![
+            Statement::Mov { dest: RAX, src: R9 },
+            label("loop"),
+            Statement::Mov { dest: R9, src: RAX },
+            Statement::Sub {
+                dest: RAX,
+                src: Operand::Immediate(1),
+            },
+            Statement::Jne {
+                label: "loop".to_string(),
+            },
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements.clone()), statements);
+    }
+
+    #[test]
+    fn keeps_additions_of_zero_whose_flags_are_read() {
+        let add_zero = Statement::Add {
+            dest: RAX,
+            src: Operand::Immediate(0),
+        };
+        let jne = Statement::Jne {
+            label: "end".to_string(),
+        };
+
+        // The move leaves the flags set by the addition for the jump.
+        let statements = vec![
+            add_zero.clone(),
+            Statement::Mov { dest: R9, src: RAX },
+            jne.clone(),
+            label("end"),
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements.clone()), statements);
+
+        // Falling through the label, the jump after it reads the flags set by the addition.
+        let statements = vec![
+            Statement::Jmp {
+                label: "start".to_string(),
+            },
+            add_zero,
+            label("start"),
+            jne,
+            label("end"),
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements.clone()), statements);
+    }
+
+    #[test]
+    fn removes_jumps_to_the_next_label() {
+        let statements = vec![
+            Statement::Je {
+                label: "end".to_string(),
+            },
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Immediate(1),
+            },
+            Statement::Jmp {
+                label: "end".to_string(),
+            },
+            label("unreferenced"),
+            label("end"),
+            Statement::Add {
+                dest: RAX,
+                src: Operand::Immediate(0),
+            },
+            Statement::Jne {
+                label: "end".to_string(),
+            },
+            Statement::Ret,
+        ];
+        let expected = vec![
+            Statement::Je {
+                label: "end".to_string(),
+            },
+            Statement::Mov {
+                dest: RAX,
+                src: Operand::Immediate(1),
+            },
+            label("unreferenced"),
+            label("end"),
+            // The flags set by the addition are read by the jump.
+            Statement::Add {
+                dest: RAX,
+                src: Operand::Immediate(0),
+            },
+            Statement::Jne {
+                label: "end".to_string(),
+            },
+            Statement::Ret,
+        ];
+        assert_eq!(optimized(statements), expected);
+    }
+}
@@ -1,61 +1,94 @@
+use std::io::Write;
+
 use super::ast::*;
 
 pub struct CompilationContext {
     pub platform: Platform,
 }
 
-#[allow(dead_code)]
+/// The platform for which the assembly is printed, which determines the naming of the labels.
+#[derive(Debug, Clone, Copy)]
 pub enum Platform {
     Linux,
     MacOS,
 }
 
 pub fn print(program: &Program, context: &CompilationContext) -> String {
-    let mut output = String::new();
+    let mut output = Vec::new();
+    write(program, context, &mut output).expect("writing to a Vec never fails");
+    String::from_utf8(output).expect("the printed assembly is valid UTF-8")
+}
+
+/// The lines which [write] prints before the statements.
+const HEADER: [&str; 2] = ["\tdefault rel", "\tsection .text"];
 
-    output.push_str("\tdefault rel\n");
-    output.push_str("\tsection .text\n");
+/// Writes the assembly to the writer statement by statement, without building the whole output in memory.
+pub fn write<W: Write>(
+    program: &Program,
+    context: &CompilationContext,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    for line in HEADER {
+        writeln!(writer, "{}", line)?;
+    }
 
     for statement in &program.statements {
-        output.push_str(&print_statement(&statement, &context));
-        output.push_str("\n");
+        writeln!(writer, "{}", print_statement(statement, context))?;
     }
-    output
+    Ok(())
+}
+
+/// Returns the 1-based line on which [write] prints the statement at the index,
+/// following the lines of the header.
+pub fn line_of_statement(index: usize) -> usize {
+    HEADER.len() + index + 1
 }
 
 fn print_statement(statement: &Statement, context: &CompilationContext) -> String {
     match statement {
-        Statement::Global { name } => format!("\tglobal {}", name),
-        Statement::Extern { name } => format!("\textern {}", name),
+        Statement::Global { name } => format!("\tglobal {}", print_label(name, context)),
+        Statement::Extern { name } => format!("\textern {}", print_label(name, context)),
         Statement::Label { name } => format!("{}:", print_label(name, context)),
         Statement::Data => "\tsection .data".to_string(),
         Statement::Text => "\tsection .text".to_string(),
         Statement::Dq { value } => format!("\tdq {}", value),
+        Statement::DqLabel { label } => format!("\tdq {}", print_label(label, context)),
         Statement::Dd { value } => format!("\tdd {}", value),
+        Statement::Db { bytes } => print_db(bytes),
         Statement::Mov { dest, src } => print_mov(dest, src),
         Statement::And { dest, src } => print_and(dest, src),
         Statement::Or { dest, src } => print_or(dest, src),
         Statement::Xor { dest, src } => print_xor(dest, src),
         Statement::Sar { dest, src } => print_sar(dest, src),
         Statement::Sal { dest, src } => print_sal(dest, src),
+        Statement::Neg { dest } => format!("\tneg {}", print_operand(dest)),
+        Statement::Not { dest } => format!("\tnot {}", print_operand(dest)),
         Statement::Cmp { dest, src } => print_cmp(dest, src),
+        Statement::Test { dest, src } => print_test(dest, src),
         Statement::Cmove { dest, src } => print_cmove(dest, src),
         Statement::Cmovl { dest, src } => print_cmovl(dest, src),
+        Statement::Cmovg { dest, src } => print_cmovg(dest, src),
         Statement::Je { label } => format!("\tje {}", print_label(label, context)),
         Statement::Jne { label } => format!("\tjne {}", print_label(label, context)),
         Statement::Jg { label } => format!("\tjg {}", print_label(label, context)),
         Statement::Jl { label } => format!("\tjl {}", print_label(label, context)),
+        Statement::Jge { label } => format!("\tjge {}", print_label(label, context)),
+        Statement::Jb { label } => format!("\tjb {}", print_label(label, context)),
+        Statement::Jo { label } => format!("\tjo {}", print_label(label, context)),
         Statement::Jmp { label } => format!("\tjmp {}", print_label(label, context)),
         Statement::JmpRegister(register) => format!("\tjmp {}", print_register(register)),
         Statement::Push { src } => format!("\tpush {}", print_operand(src)),
         Statement::Pop { dest } => format!("\tpop {}", print_operand(dest)),
         Statement::Add { dest, src } => print_add(dest, src),
         Statement::Sub { dest, src } => print_sub(dest, src),
+        Statement::Imul { dest, src } => print_imul(dest, src),
         Statement::Lea { dest, label } => print_lea(dest, label, context),
         Statement::LeaArithmetic { dest, expr } => {
             format!("\tlea {}, {}", print_operand(dest), expr)
         }
         Statement::Call { label } => print_call(label, context),
+        Statement::Cqo => "\tcqo".to_string(),
+        Statement::Idiv { src } => format!("\tidiv {}", print_operand(src)),
         Statement::Ret => "\tret".to_string(),
     }
 }
@@ -88,6 +121,10 @@ fn print_cmp(dest: &Operand, src: &Operand) -> String {
     format!("\tcmp {}, {}", print_operand(dest), print_operand(src))
 }
 
+fn print_test(dest: &Operand, src: &Operand) -> String {
+    format!("\ttest {}, {}", print_operand(dest), print_operand(src))
+}
+
 fn print_cmove(dest: &Operand, src: &Operand) -> String {
     format!("\tcmove {}, {}", print_operand(dest), print_operand(src))
 }
@@ -96,6 +133,10 @@ fn print_cmovl(dest: &Operand, src: &Operand) -> String {
     format!("\tcmovl {}, {}", print_operand(dest), print_operand(src))
 }
 
+fn print_cmovg(dest: &Operand, src: &Operand) -> String {
+    format!("\tcmovg {}, {}", print_operand(dest), print_operand(src))
+}
+
 fn print_add(dest: &Operand, src: &Operand) -> String {
     format!("\tadd {}, {}", print_operand(dest), print_operand(src))
 }
@@ -104,6 +145,10 @@ fn print_sub(dest: &Operand, src: &Operand) -> String {
     format!("\tsub {}, {}", print_operand(dest), print_operand(src))
 }
 
+fn print_imul(dest: &Operand, src: &Operand) -> String {
+    format!("\timul {}, {}", print_operand(dest), print_operand(src))
+}
+
 fn print_lea(dest: &Operand, label: &String, context: &CompilationContext) -> String {
     format!(
         "\tlea {}, {}",
@@ -116,6 +161,33 @@ fn print_call(label: &String, context: &CompilationContext) -> String {
     format!("\tcall {}", print_label(label, &context))
 }
 
+/// Prints the runs of printable ASCII characters quoted, and the other bytes as numbers,
+/// e.g. `db "a", 34, "b", 10` for `a"b` and a newline.
+/// NASM does not process escapes in double quotes, so a double quote itself is a number.
+fn print_db(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "\tdb \"\"".to_string();
+    }
+
+    let mut items: Vec<String> = vec![];
+    let mut quoted = String::new();
+    for &byte in bytes {
+        if (0x20..0x7f).contains(&byte) && byte != b'"' {
+            quoted.push(byte as char);
+            continue;
+        }
+        if !quoted.is_empty() {
+            items.push(format!("\"{}\"", quoted));
+            quoted.clear();
+        }
+        items.push(byte.to_string());
+    }
+    if !quoted.is_empty() {
+        items.push(format!("\"{}\"", quoted));
+    }
+    format!("\tdb {}", items.join(", "))
+}
+
 fn print_label(label: &String, context: &CompilationContext) -> String {
     match context.platform {
         Platform::MacOS => format!("_{}", label),
@@ -137,6 +209,20 @@ fn print_operand(operand: &Operand) -> String {
                 format!("[{} - {}]", print_register(register), offset.abs())
             }
         }
+
+        Operand::ScaledIndex {
+            base,
+            index,
+            scale,
+            disp,
+        } => {
+            let address = format!("{} + {}*{}", print_register(base), print_register(index), scale);
+            if disp >= &0 {
+                format!("[{} + {}]", address, disp)
+            } else {
+                format!("[{} - {}]", address, disp.abs())
+            }
+        }
     }
 }
 
@@ -146,6 +232,8 @@ fn print_register(register: &Register) -> String {
         Register::EAX => "eax".to_string(),
         Register::RBX => "rbx".to_string(),
         Register::RDI => "rdi".to_string(),
+        Register::RSI => "rsi".to_string(),
+        Register::RDX => "rdx".to_string(),
         Register::RSP => "rsp".to_string(),
         Register::R1 => "r1".to_string(),
         Register::R2 => "r2".to_string(),
@@ -165,3 +253,131 @@ fn print_register(register: &Register) -> String {
         Register::R9D => "r9d".to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_index_operands() {
+        let operand = Operand::ScaledIndex {
+            base: Register::R8,
+            index: Register::RAX,
+            scale: 4,
+            disp: 8,
+        };
+        assert_eq!(print_operand(&operand), "[r8 + rax*4 + 8]");
+
+        let operand = Operand::ScaledIndex {
+            base: Register::RSP,
+            index: Register::R10,
+            scale: 8,
+            disp: -16,
+        };
+        assert_eq!(print_operand(&operand), "[rsp + r10*8 - 16]");
+
+        let statement = Statement::Mov {
+            dest: Operand::Register(Register::EAX),
+            src: Operand::ScaledIndex {
+                base: Register::R8,
+                index: Register::RAX,
+                scale: 4,
+                disp: 0,
+            },
+        };
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        assert_eq!(print_statement(&statement, &context), "\tmov eax, [r8 + rax*4 + 0]");
+    }
+
+    #[test]
+    fn label_data() {
+        let statement = Statement::DqLabel {
+            label: "case_1".to_string(),
+        };
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        assert_eq!(print_statement(&statement, &context), "\tdq case_1");
+        let context = CompilationContext {
+            platform: Platform::MacOS,
+        };
+        assert_eq!(print_statement(&statement, &context), "\tdq _case_1");
+    }
+
+    #[test]
+    fn byte_data() {
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        let db = |bytes: &[u8]| print_statement(&Statement::Db { bytes: bytes.to_vec() }, &context);
+        assert_eq!(db(b"hello"), "\tdb \"hello\"");
+        assert_eq!(db(b"say \"hi\"\n"), "\tdb \"say \", 34, \"hi\", 34, 10");
+        assert_eq!(db("λ".as_bytes()), "\tdb 206, 187");
+        assert_eq!(db(b"\0a"), "\tdb 0, \"a\"");
+        assert_eq!(db(b""), "\tdb \"\"");
+    }
+
+    #[test]
+    fn unsigned_jump() {
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        let statement = Statement::Jb {
+            label: "stack_overflow_err".to_string(),
+        };
+        assert_eq!(print_statement(&statement, &context), "\tjb stack_overflow_err");
+    }
+
+    #[test]
+    fn test_instruction() {
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        let statement = Statement::Test {
+            dest: Operand::Register(Register::R10),
+            src: Operand::Register(Register::R10),
+        };
+        assert_eq!(print_statement(&statement, &context), "\ttest r10, r10");
+
+        let statement = Statement::Test {
+            dest: Operand::Register(Register::RAX),
+            src: Operand::Immediate(15),
+        };
+        assert_eq!(print_statement(&statement, &context), "\ttest rax, 15");
+    }
+
+    #[test]
+    fn unary_instructions() {
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        let statement = Statement::Neg {
+            dest: Operand::Register(Register::RAX),
+        };
+        assert_eq!(print_statement(&statement, &context), "\tneg rax");
+
+        let statement = Statement::Not {
+            dest: Operand::Offset(Register::RSP, 8),
+        };
+        assert_eq!(print_statement(&statement, &context), "\tnot [rsp + 8]");
+    }
+
+    #[test]
+    fn imul_instruction() {
+        let context = CompilationContext {
+            platform: Platform::Linux,
+        };
+        let statement = Statement::Imul {
+            dest: Operand::Register(Register::RAX),
+            src: Operand::Register(Register::R8),
+        };
+        assert_eq!(print_statement(&statement, &context), "\timul rax, r8");
+
+        let statement = Statement::Jo {
+            label: "err".to_string(),
+        };
+        assert_eq!(print_statement(&statement, &context), "\tjo err");
+    }
+}
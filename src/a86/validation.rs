@@ -0,0 +1,103 @@
+//! Checks on the labels of a program, which would otherwise only be caught by the assembler.
+
+use std::collections::HashSet;
+
+use super::ast::{Program, Statement};
+
+/// Returns the errors for the labels referenced but never defined, and the labels defined more than once.
+///
+/// The labels are defined by `Label` or declared by `Extern`, and referenced by the jumps, `lea`, `call`,
+/// `dq`, and `global`.
+/// The labels in the expression of `LeaArithmetic` are not checked, since it is an opaque string.
+pub fn validate(program: &Program) -> Result<(), Vec<String>> {
+    let mut errors = vec![];
+
+    let mut defined = HashSet::new();
+    for statement in &program.statements {
+        if let Statement::Label { name } | Statement::Extern { name } = statement {
+            if !defined.insert(name) {
+                errors.push(format!("Duplicate label `{}`", name));
+            }
+        }
+    }
+
+    let mut reported = HashSet::new();
+    for statement in &program.statements {
+        let label = match statement {
+            Statement::Je { label }
+            | Statement::Jne { label }
+            | Statement::Jg { label }
+            | Statement::Jl { label }
+            | Statement::Jge { label }
+            | Statement::Jb { label }
+            | Statement::Jo { label }
+            | Statement::Jmp { label }
+            | Statement::Lea { label, .. }
+            | Statement::Call { label }
+            | Statement::DqLabel { label } => label,
+            Statement::Global { name } => name,
+            _ => continue,
+        };
+        if !defined.contains(label) && reported.insert(label) {
+            errors.push(format!("Undefined label `{}`", label));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str) -> Statement {
+        Statement::Label {
+            name: name.to_string(),
+        }
+    }
+
+    fn jmp(label: &str) -> Statement {
+        Statement::Jmp {
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn dangling_jumps() {
+        let program = Program {
+            statements: vec![
+                Statement::Extern {
+                    name: "read_byte".to_string(),
+                },
+                label("entry"),
+                Statement::Call {
+                    label: "read_byte".to_string(),
+                },
+                jmp("entry"),
+                jmp("nowhere"),
+                Statement::Je {
+                    label: "nowhere".to_string(),
+                },
+                Statement::Ret,
+            ],
+        };
+        assert_eq!(validate(&program), Err(vec!["Undefined label `nowhere`".to_string()]));
+    }
+
+    #[test]
+    fn duplicate_labels() {
+        let program = Program {
+            statements: vec![label("entry"), jmp("entry"), label("entry"), Statement::Ret],
+        };
+        assert_eq!(validate(&program), Err(vec!["Duplicate label `entry`".to_string()]));
+
+        let program = Program {
+            statements: vec![label("entry"), jmp("entry")],
+        };
+        assert_eq!(validate(&program), Ok(()));
+    }
+}
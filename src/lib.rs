@@ -1,17 +1,76 @@
 mod a86;
 mod mylang;
 
+use std::io::Write;
+
+pub use a86::printer::Platform;
+pub use mylang::analysis::Warning;
+pub use mylang::compiler::CompileOptions;
 pub use mylang::parse;
+pub use mylang::CompileError;
+pub use mylang::InvalidTokenError;
 pub use mylang::ParserError;
+pub use mylang::lexer::{tokenize, Token, TokenKind};
+pub use mylang::data_type;
 pub use mylang::document::Position;
 
-pub fn compile(source: &str) -> Result<String, ParserError> {
-    parse(source)
-        .map(|ast| mylang::compiler::compile(ast))
-        .map(|a86_program| {
-            let context = a86::printer::CompilationContext {
-                platform: a86::printer::Platform::Linux,
-            };
-            a86::printer::print(&a86_program, &context)
-        })
+pub fn compile(source: &str) -> Result<String, CompileError> {
+    compile_with_options(source, &CompileOptions::default())
+}
+
+/// Compiles the source code to the assembly for Linux, changing the compilation by the options.
+pub fn compile_with_options(source: &str, options: &CompileOptions) -> Result<String, CompileError> {
+    let a86_program = compile_to_a86(source, options)?;
+    let context = a86::printer::CompilationContext {
+        platform: Platform::Linux,
+    };
+    Ok(a86::printer::print(&a86_program, &context))
+}
+
+/// Compiles the source code and writes the assembly for the given platform to the writer,
+/// changing the compilation by the options.
+pub fn compile_to_writer<W: Write>(
+    source: &str,
+    platform: Platform,
+    options: &CompileOptions,
+    writer: &mut W,
+) -> Result<(), CompileError> {
+    let a86_program = compile_to_a86(source, options)?;
+    let context = a86::printer::CompilationContext { platform };
+    a86::printer::write(&a86_program, &context, writer)
+        .map_err(|err| CompileError::WriteError(err.to_string()))
+}
+
+/// Compiles the source code to the assembly for the given platform, along with the source map,
+/// which maps the 1-based lines of the assembly to the positions of the forms in the source code.
+///
+/// Each line in the source map is a label put before the code of the form, so that a debugger can break at it.
+pub fn compile_with_source_map(
+    source: &str,
+    platform: Platform,
+    options: &CompileOptions,
+) -> Result<(String, Vec<(usize, Position)>), CompileError> {
+    let ast = parse(source)?;
+    let (a86_program, source_map) = mylang::compiler::compile_with_source_map(ast, options)?;
+    let context = a86::printer::CompilationContext { platform };
+    let source_map = source_map
+        .into_iter()
+        .map(|(index, position)| (a86::printer::line_of_statement(index), position))
+        .collect();
+    Ok((a86::printer::print(&a86_program, &context), source_map))
+}
+
+/// Returns the warnings for the source code, such as unreachable match arms.
+/// Warnings do not prevent the compilation.
+pub fn analyze(source: &str) -> Result<Vec<Warning>, ParserError> {
+    let ast = parse(source)?;
+    Ok(mylang::analysis::analyze(&ast))
+}
+
+fn compile_to_a86(
+    source: &str,
+    options: &CompileOptions,
+) -> Result<a86::ast::Program, CompileError> {
+    let ast = parse(source)?;
+    mylang::compiler::compile(ast, options)
 }
@@ -1,12 +1,137 @@
 use std::io::Read;
 
-use compiler_course::compile;
+use compiler_course::{
+    analyze, compile_to_writer, compile_with_source_map, parse, CompileOptions, Platform,
+};
 
-/// Reads source code from stdin and prints the compiled assembly to stdout.
+const USAGE: &str = "Usage: compiler-course [--platform linux|macos] [--emit asm|ast] \
+[--source-map MAP_FILE] [--no-tail-calls] [--check-heap-pointers] [--no-peephole] [FILE]";
+
+/// What the binary prints.
+enum Emit {
+    /// The compiled assembly.
+    Asm,
+    /// The parsed AST in the debug format.
+    Ast,
+}
+
+struct Options {
+    platform: Platform,
+    emit: Emit,
+    /// The file to write the source map to, each line of which is a line of the assembly and an offset in the source code.
+    source_map: Option<String>,
+    /// The file to read the source code from. Reads from stdin if not given.
+    input: Option<String>,
+    compile: CompileOptions,
+}
+
+/// Compiles the source code in the given file (or stdin) and prints the assembly to stdout.
+/// Warnings are printed to stderr.
+/// If the compilation fails, prints the error to stderr and exits with status 1.
 fn main() {
-    let mut source = String::new();
-    std::io::stdin().read_to_string(&mut source).unwrap();
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|msg| {
+        eprintln!("{}\n{}", msg, USAGE);
+        std::process::exit(1);
+    });
+
+    let source = read_source(&options.input).unwrap_or_else(|err| {
+        eprintln!("Failed to read the source code: {}", err);
+        std::process::exit(1);
+    });
+
+    for warning in analyze(&source).unwrap_or_default() {
+        eprintln!("{}", warning);
+    }
+
+    let result = match options.emit {
+        Emit::Asm => match &options.source_map {
+            Some(path) => write_with_source_map(&source, options.platform, &options.compile, path),
+            None => compile_to_writer(
+                &source,
+                options.platform,
+                &options.compile,
+                &mut std::io::stdout().lock(),
+            ),
+        },
+        Emit::Ast => parse(&source)
+            .map(|ast| println!("{:#?}", ast))
+            .map_err(Into::into),
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Prints the assembly to stdout, and writes the source map to the file.
+fn write_with_source_map(
+    source: &str,
+    platform: Platform,
+    compile_options: &CompileOptions,
+    path: &str,
+) -> Result<(), compiler_course::CompileError> {
+    let (asm, source_map) = compile_with_source_map(source, platform, compile_options)?;
+    let lines: String = source_map
+        .iter()
+        .map(|(line, position)| format!("{} {}\n", line, position))
+        .collect();
+    std::fs::write(path, lines)
+        .map_err(|err| compiler_course::CompileError::WriteError(err.to_string()))?;
+    print!("{}", asm);
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        platform: Platform::Linux,
+        emit: Emit::Asm,
+        source_map: None,
+        input: None,
+        compile: CompileOptions::default(),
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                options.platform = match args.next().as_deref() {
+                    Some("linux") => Platform::Linux,
+                    Some("macos") => Platform::MacOS,
+                    _ => return Err("`--platform` must be either `linux` or `macos`.".to_string()),
+                }
+            }
+            "--emit" => {
+                options.emit = match args.next().as_deref() {
+                    Some("asm") => Emit::Asm,
+                    Some("ast") => Emit::Ast,
+                    _ => return Err("`--emit` must be either `asm` or `ast`.".to_string()),
+                }
+            }
+            "--source-map" => {
+                options.source_map = Some(
+                    args.next()
+                        .ok_or("`--source-map` must be followed by the file to write to.")?,
+                )
+            }
+            "--no-tail-calls" => options.compile.tail_calls = false,
+            "--check-heap-pointers" => options.compile.check_heap_pointers = true,
+            "--no-peephole" => options.compile.peephole = false,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option `{}`.", flag)),
+            _ if options.input.is_some() => {
+                return Err("Only one input file can be given.".to_string())
+            }
+            _ => options.input = Some(arg),
+        }
+    }
+    Ok(options)
+}
 
-    let program = compile(&source).unwrap();
-    println!("{}", program);
+fn read_source(input: &Option<String>) -> std::io::Result<String> {
+    match input {
+        Some(path) => std::fs::read_to_string(path),
+        None => {
+            let mut source = String::new();
+            std::io::stdin().read_to_string(&mut source)?;
+            Ok(source)
+        }
+    }
 }
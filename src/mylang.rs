@@ -1,13 +1,15 @@
+pub mod analysis;
 pub mod ast;
 pub mod compiler;
-mod data_type;
+pub mod data_type;
 pub mod document;
 mod error;
 pub mod lexer;
 pub mod parser;
 pub mod s_expression;
+pub mod type_check;
 
-pub use error::ParserError;
+pub use error::{CompileError, InvalidTokenError, ParserError};
 
 pub fn parse(source: &str) -> Result<ast::Program, ParserError> {
     let tokens = lexer::tokenize(source)?;
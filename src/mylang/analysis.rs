@@ -0,0 +1,94 @@
+//! Static analyses which find suspicious code that still compiles, reported as warnings.
+
+use super::ast::{App, Arm, Case, Expr, If, Lambda, Let, Match, Program};
+use super::document::Position;
+
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub msg: String,
+    /// The position of the suspicious code, if it originates from the source code.
+    pub position: Option<Position>,
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.position {
+            Some(position) => write!(f, "Warning at offset {}: {}", position.offset, self.msg),
+            None => write!(f, "Warning: {}", self.msg),
+        }
+    }
+}
+
+/// Returns the warnings for the program, in the order of appearance.
+pub fn analyze(program: &Program) -> Vec<Warning> {
+    let mut warnings = vec![];
+    for definition in &program.function_definitions {
+        analyze_expr(&definition.body, &mut warnings);
+    }
+    analyze_expr(&program.expr, &mut warnings);
+    warnings
+}
+
+fn analyze_expr(expr: &Expr, warnings: &mut Vec<Warning>) {
+    match expr {
+        Expr::Eof | Expr::Lit(_) | Expr::Prim0(..) | Expr::Variable(_) => {}
+        Expr::Prim1(_, e, _) => analyze_expr(e, warnings),
+        Expr::Prim2(_, e1, e2, _) | Expr::Begin(e1, e2) => {
+            analyze_expr(e1, warnings);
+            analyze_expr(e2, warnings);
+        }
+        Expr::Prim3(_, e1, e2, e3, _) => {
+            analyze_expr(e1, warnings);
+            analyze_expr(e2, warnings);
+            analyze_expr(e3, warnings);
+        }
+        Expr::Let(Let { binding, body }) => {
+            analyze_expr(&binding.rhs, warnings);
+            analyze_expr(body, warnings);
+        }
+        Expr::App(App { function, args, .. }) => {
+            analyze_expr(function, warnings);
+            for arg in args {
+                analyze_expr(arg, warnings);
+            }
+        }
+        Expr::If(If { cond, then, els }) => {
+            analyze_expr(cond, warnings);
+            analyze_expr(then, warnings);
+            analyze_expr(els, warnings);
+        }
+        Expr::Match(Match { expr, arms }) => {
+            analyze_expr(expr, warnings);
+            warnings.extend(unreachable_arms(arms));
+            for arm in arms {
+                for (_, test) in arm.pattern.predicates() {
+                    analyze_expr(test, warnings);
+                }
+                analyze_expr(&arm.body, warnings);
+            }
+        }
+        Expr::Case(Case { expr, clauses, els }) => {
+            analyze_expr(expr, warnings);
+            for clause in clauses {
+                analyze_expr(&clause.body, warnings);
+            }
+            analyze_expr(els, warnings);
+        }
+        Expr::Lambda(Lambda { body, .. }) => analyze_expr(body, warnings),
+    }
+}
+
+/// Returns the warnings for the arms following an arm which always matches,
+/// since the arms are tried in order.
+fn unreachable_arms(arms: &[Arm]) -> Vec<Warning> {
+    let Some(i) = arms.iter().position(|arm| arm.pattern.is_irrefutable()) else {
+        return vec![];
+    };
+    arms[i + 1..]
+        .iter()
+        .map(|arm| Warning {
+            msg: "This match arm is unreachable, since a previous arm always matches.".to_string(),
+            position: arm.position.clone(),
+        })
+        .collect()
+}
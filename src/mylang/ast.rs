@@ -1,20 +1,39 @@
+use super::document::Position;
+
+/// The primitive applications and function applications carry the position of the head of the form
+/// in the source code, if the node originates from one.
 #[derive(Debug, Clone)]
 pub enum Expr {
     Eof,
     Lit(Lit),
-    Prim0(Op0),
-    Prim1(Op1, Box<Expr>),
-    Prim2(Op2, Box<Expr>, Box<Expr>),
-    Prim3(Op3, Box<Expr>, Box<Expr>, Box<Expr>),
+    Prim0(Op0, Option<Position>),
+    Prim1(Op1, Box<Expr>, Option<Position>),
+    Prim2(Op2, Box<Expr>, Box<Expr>, Option<Position>),
+    Prim3(Op3, Box<Expr>, Box<Expr>, Box<Expr>, Option<Position>),
     Begin(Box<Expr>, Box<Expr>),
     Variable(Identifier),
     Let(Let),
     App(App),
     If(If),
     Match(Match),
+    Case(Case),
     Lambda(Lambda),
 }
 
+impl Expr {
+    /// Returns the position of the form in the source code, if it is an application originating from one.
+    pub fn position(&self) -> Option<&Position> {
+        match self {
+            Expr::Prim0(_, position)
+            | Expr::Prim1(_, _, position)
+            | Expr::Prim2(_, _, _, position)
+            | Expr::Prim3(_, _, _, _, position) => position.as_ref(),
+            Expr::App(app) => app.position.as_ref(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Let {
     pub binding: Binding,
@@ -27,7 +46,7 @@ pub struct Binding {
     pub rhs: Box<Expr>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 pub struct Identifier(pub String);
 
 impl Identifier {
@@ -41,6 +60,7 @@ impl Identifier {
 pub struct App {
     pub function: Box<Expr>,
     pub args: Vec<Expr>,
+    pub position: Option<Position>,
 }
 
 #[derive(Debug, Clone)]
@@ -56,10 +76,27 @@ pub struct Match {
     pub arms: Vec<Arm>,
 }
 
+/// Evaluates the body of the first clause whose datums include the value of the expression,
+/// compared as by `equal?`, or the body of the `else` clause if none does.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub expr: Box<Expr>,
+    pub clauses: Vec<CaseClause>,
+    /// The body of the `else` clause, which is void if the clause is omitted.
+    pub els: Box<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseClause {
+    pub datums: Vec<Lit>,
+    pub body: Box<Expr>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Arm {
     pub pattern: Pattern,
     pub body: Box<Expr>,
+    pub position: Option<Position>,
 }
 
 #[derive(Debug, Clone)]
@@ -70,6 +107,49 @@ pub enum Pattern {
     Cons(Box<Pattern>, Box<Pattern>),
     Box(Box<Pattern>),
     And(Box<Pattern>, Box<Pattern>),
+    /// Matches if the test does not evaluate to false,
+    /// where the value to match is bound to the variable only within the test.
+    Predicate(Identifier, Box<Expr>),
+}
+
+impl Pattern {
+    /// Returns true if the pattern matches any value.
+    pub fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Variable(_) => true,
+            Pattern::Lit(_) | Pattern::Cons(..) | Pattern::Box(_) | Pattern::Predicate(..) => false,
+            Pattern::And(left, right) => left.is_irrefutable() && right.is_irrefutable(),
+        }
+    }
+
+    /// Returns the variables bound by the pattern.
+    pub fn variables(&self) -> Vec<Identifier> {
+        match self {
+            Pattern::Variable(identifier) => vec![identifier.clone()],
+            Pattern::Wildcard | Pattern::Lit(_) | Pattern::Predicate(..) => vec![],
+            Pattern::Box(pattern) => pattern.variables(),
+            Pattern::Cons(left, right) | Pattern::And(left, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+        }
+    }
+
+    /// Returns the tests of the predicate patterns in the pattern,
+    /// along with the variables bound to the value to match.
+    pub fn predicates(&self) -> Vec<(&Identifier, &Expr)> {
+        match self {
+            Pattern::Predicate(variable, test) => vec![(variable, test)],
+            Pattern::Wildcard | Pattern::Variable(_) | Pattern::Lit(_) => vec![],
+            Pattern::Box(pattern) => pattern.predicates(),
+            Pattern::Cons(left, right) | Pattern::And(left, right) => {
+                let mut predicates = left.predicates();
+                predicates.extend(right.predicates());
+                predicates
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -79,19 +159,34 @@ pub enum Lit {
     Char(char),
     String(String),
     EmptyList,
+    /// The void value, which has no literal syntax but is produced by desugaring, e.g. a missing `else` branch.
+    Void,
 }
 
 #[derive(Debug, Clone)]
 pub enum Op0 {
     ReadByte,
-    PeekByte,
+    /// Writes a newline character to stdout.
+    Newline,
+    /// Returns the eof object.
+    EofObject,
+    /// Writes out the bytes buffered for stdout, and returns void.
+    FlushOutput,
 }
 
 #[derive(Debug, Clone)]
 pub enum Op1 {
+    /// Returns the argument as it is.
+    Identity,
     Add1,
     Sub1,
+    /// Returns the integer with the opposite sign.
+    Negate,
+    /// Returns the integer with every bit flipped in two's complement, i.e. `-n - 1`.
+    BitNot,
     IsZero,
+    IsPositive,
+    IsNegative,
     IsChar,
     IsEof,
     IsBox,
@@ -100,22 +195,65 @@ pub enum Op1 {
     IsString,
     IntToChar,
     CharToInt,
+    /// Returns the character whose code point is one greater than the given character.
+    CharNext,
+    /// Returns the character whose code point is one less than the given character.
+    CharPrev,
+    /// Returns the byte the given number of bytes ahead in stdin without consuming it, or the eof object.
+    PeekByte,
     WriteByte,
+    /// Writes the characters of the string to stdout, encoded in UTF-8.
+    WriteString,
+    /// Prints the value in the human-readable form.
+    Display,
+    /// Raises an assertion error if the value is `#f`, and returns void otherwise.
+    Assert,
+    /// Raises an error with the string as the message.
+    Error,
     Box,
     Unbox,
     Car,
     Cdr,
+    /// Returns a new mutable string with the same characters as the given string.
+    StringCopy,
+    /// Returns the number of characters in the string.
+    StringLength,
+    /// Returns the number of bytes to encode the string in UTF-8.
+    StringUtf8Length,
+    /// Returns a new string with the ASCII lowercase letters converted to uppercase.
+    StringUpcase,
+    /// Returns a new string with the ASCII uppercase letters converted to lowercase.
+    StringDowncase,
 }
 
 #[derive(Debug, Clone)]
 pub enum Op2 {
     Add,
     Sub,
+    Mul,
+    /// Returns the remainder of dividing the first operand by the second, which has the same sign as the second.
+    Modulo,
     /// Returns true if the first operand is less than the second operand. 
     LessThan,
     IntEq,
+    /// Returns true if the operands are the same character.
+    CharEq,
+    /// Returns true if the code point of the first operand is less than that of the second operand.
+    CharLessThan,
+    /// Returns true if the code point of the first operand is greater than that of the second operand.
+    CharGreaterThan,
     Eq,
     Cons,
+    /// Returns the first pair in the association list whose car is structurally equal to the key, or false if there is none.
+    /// The first operand is the key, and the second operand is the list.
+    Assoc,
+    /// Same as [Op2::Assoc], but compares the keys by `eq?`.
+    Assq,
+    /// Returns the first tail of the list whose car is structurally equal to the value, or false if there is none.
+    /// The first operand is the value, and the second operand is the list.
+    Member,
+    /// Same as [Op2::Member], but compares the elements by `eq?`.
+    Memq,
     /// Returns a new vector of the given length, with each element initialized to the given value.
     /// The first operand is the length of the vector, and the second operand is the initial value.
     MakeVector,
@@ -128,6 +266,8 @@ pub enum Op2 {
     /// Returns the character in the string at the given index.
     /// The first operand is the string, and the second operand is the index.
     StringRef,
+    /// Returns true if the strings have the same characters.
+    StringEq,
 }
 
 #[derive(Debug, Clone)]
@@ -135,6 +275,13 @@ pub enum Op3 {
     /// Sets the element of the vector at the given index to the given value.
     /// The first operand is the vector, the second operand is the index, and the third operand is the new value.
     VectorSet,
+    /// Sets the character of the string at the given index to the given character.
+    /// The first operand is the string, the second operand is the index, and the third operand is the new character.
+    /// String literals are immutable, so the string must have been created at runtime, e.g. by `make-string`.
+    StringSet,
+    /// Returns a new string with the characters of the string from the start index up to, but not including, the end index.
+    /// The first operand is the string, the second operand is the start index, and the third operand is the end index.
+    Substring,
 }
 
 #[derive(Debug, Clone)]
@@ -1,4 +1,25 @@
-use crate::a86::ast::{Operand, Program, Register, Statement};
+//! # Register usage
+//!
+//! Each register plays a fixed role throughout the generated code:
+//!
+//! * `rax` holds the value of the expression just evaluated.
+//! * `rbx` points to the next free address of the heap.
+//! * `r12` holds the lowest address the stack may grow to, as passed by the runtime.
+//! * `r8` holds the first operand of binary primitives, while rax holds the second one.
+//! * `r9` and `r10` are scratch registers for the helpers.
+//! * `rdi` passes the argument to the runtime functions,
+//!   and `rsi` and `rdx` pass the details of a runtime error to the error handlers.
+//!
+//! Every helper documents the registers it clobbers besides rax, e.g. "This clobbers r9.",
+//! which is checked for the common helpers by the tests below.
+//! A value which must survive a helper clobbering its register should be pushed to the stack instead.
+
+use std::collections::HashMap;
+
+use crate::a86::{
+    ast::{Operand, Program, Register, Statement},
+    peephole, validate,
+};
 
 use self::{
     error::compile_error_handler,
@@ -12,10 +33,11 @@ use self::{
     variable::VariablesTable, static_data::compile_data_section,
 };
 
-use super::ast;
+use super::{ast, document::Position, error::CompileError, type_check};
 
 mod arithmetic;
 mod box_type;
+mod case;
 mod cons;
 mod error;
 mod expr;
@@ -31,14 +53,77 @@ mod variable;
 mod vector;
 mod static_data;
 
+/// Options to change how the program is compiled.
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    /// Whether to compile calls in tail position as jumps, so that they do not grow the stack.
+    /// Disabling it keeps a frame for every call, which may help to inspect the call stack.
+    pub tail_calls: bool,
+
+    /// Whether `unbox`, `car` and `cdr` check that the pointer lies in the memory allocated in the heap,
+    /// raising an error otherwise. It costs a few instructions on every access, so it is meant for debugging.
+    pub check_heap_pointers: bool,
+
+    /// Whether to remove redundant instructions, such as a move overwritten by the next one, by the peephole optimizer.
+    pub peephole: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            tail_calls: true,
+            check_heap_pointers: false,
+            peephole: true,
+        }
+    }
+}
+
 const RBX: Operand = Operand::Register(Register::RBX);
 const RDI: Operand = Operand::Register(Register::RDI);
+const RSI: Operand = Operand::Register(Register::RSI);
 const RSP: Operand = Operand::Register(Register::RSP);
-const R15: Operand = Operand::Register(Register::R15);
+const R12: Operand = Operand::Register(Register::R12);
 
-pub fn compile(program: ast::Program) -> Program {
+pub fn compile(program: ast::Program, options: &CompileOptions) -> Result<Program, CompileError> {
     let string_literals = all_string_literals(&program);
-    let mut compiler = Compiler::new(string_literals);
+    let mut compiler = Compiler::new(string_literals, options.clone());
+    compile_program(program, &mut compiler)
+}
+
+/// Compiles the program along with the source map,
+/// which maps the indices of the statements to the positions of the forms in the source code.
+///
+/// A label is put before the code of each application whose position is known,
+/// and the source map points to these labels.
+/// Thus the code of a form runs from its label up to the end of its innermost enclosing form.
+pub fn compile_with_source_map(
+    program: ast::Program,
+    options: &CompileOptions,
+) -> Result<(Program, Vec<(usize, Position)>), CompileError> {
+    let string_literals = all_string_literals(&program);
+    let mut compiler = Compiler::new(string_literals, options.clone());
+    compiler.enable_source_labels();
+    let program = compile_program(program, &mut compiler)?;
+
+    let positions: HashMap<&String, &Position> = compiler
+        .source_labels()
+        .iter()
+        .map(|(label, position)| (label, position))
+        .collect();
+    let source_map = program
+        .statements
+        .iter()
+        .enumerate()
+        .filter_map(|(i, statement)| match statement {
+            Statement::Label { name } => positions.get(name).map(|&position| (i, position.clone())),
+            _ => None,
+        })
+        .collect();
+    Ok((program, source_map))
+}
+
+fn compile_program(program: ast::Program, compiler: &mut Compiler) -> Result<Program, CompileError> {
+    type_check::check(&program)?;
 
     let mut statements = vec![Statement::Global {
         name: "entry".to_string(),
@@ -49,23 +134,31 @@ pub fn compile(program: ast::Program) -> Program {
     });
 
     // Stash callee-saved registers.
+    // Only rbx and r12 need to be saved, as the other callee-saved registers are never written.
     statements.push(Statement::Push { src: RBX });
-    statements.push(Statement::Push { src: R15 });
+    statements.push(Statement::Push { src: R12 });
 
+    // The arguments are moved out of rdi and rsi before anything else,
+    // since they are also the argument registers of the external calls, e.g. `write_byte`.
+    // Neither is read afterwards: rbx is the only heap pointer, and r12 the only stack limit.
     statements.push(Statement::Mov {
         dest: RBX,
         src: RDI, // The runtime must allocate the heap memory and pass its address via rdi.
     });
+    statements.push(Statement::Mov {
+        dest: R12,
+        src: RSI, // The runtime must pass the lowest address the stack may grow to via rsi.
+    });
 
-    statements.extend(compile_closures_for_defines(&program));
+    statements.extend(compile_closures_for_defines(&program)?);
     let env = VariablesTable::new().extended(defined_ids(&program));
 
     statements.extend(compile_expr(
         program.expr.clone(),
-        &mut compiler,
+        compiler,
         &env,
         false,
-    ));
+    )?);
 
     // Pop function definitions
     statements.push(Statement::Add {
@@ -74,16 +167,215 @@ pub fn compile(program: ast::Program) -> Program {
     });
 
     // Restore callee-saved registers.
-    statements.push(Statement::Pop { dest: R15 });
+    statements.push(Statement::Pop { dest: R12 });
     statements.push(Statement::Pop { dest: RBX });
 
     statements.push(Statement::Ret);
 
-    statements.extend(compile_defines(&program, &mut compiler));
-    statements.extend(compile_lambda_definitions(&program, &mut compiler));
+    statements.extend(compile_defines(&program, compiler)?);
+    statements.extend(compile_lambda_definitions(&program, compiler)?);
 
     statements.extend(compile_error_handler());
-    statements.extend(compile_data_section(&compiler));
+    statements.extend(compile_data_section(&program, compiler));
+
+    let mut program = Program { statements };
+    if compiler.options().peephole {
+        program = peephole::optimize(program);
+    }
+    validate(&program).map_err(CompileError::InvalidAssembly)?;
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mylang::parse;
+
+    /// Registers which `entry` must preserve for the runtime, as required by the System V ABI.
+    const CALLEE_SAVED: [Register; 5] = [
+        Register::RBX,
+        Register::R12,
+        Register::R13,
+        Register::R14,
+        Register::R15,
+    ];
+
+    fn written_register(statement: &Statement) -> Option<&Register> {
+        match statement {
+            Statement::Mov { dest, .. }
+            | Statement::And { dest, .. }
+            | Statement::Or { dest, .. }
+            | Statement::Xor { dest, .. }
+            | Statement::Sar { dest, .. }
+            | Statement::Sal { dest, .. }
+            | Statement::Neg { dest }
+            | Statement::Not { dest }
+            | Statement::Cmove { dest, .. }
+            | Statement::Cmovl { dest, .. }
+            | Statement::Cmovg { dest, .. }
+            | Statement::Add { dest, .. }
+            | Statement::Sub { dest, .. }
+            | Statement::Imul { dest, .. }
+            | Statement::Pop { dest }
+            | Statement::Lea { dest, .. }
+            | Statement::LeaArithmetic { dest, .. } => match dest {
+                Operand::Register(register) => Some(register),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn callee_saved_registers_are_restored() {
+        let source = "
+            (define (f x) (write-byte x))
+            (begin (f (read-byte)) (display (cons (peek-byte) (box 1))))";
+        let program = compile(parse(source).unwrap(), &CompileOptions::default()).unwrap();
+        let statements = &program.statements;
+
+        let entry = statements
+            .iter()
+            .position(|s| *s == Statement::Label { name: "entry".to_string() })
+            .unwrap();
+        let ret = entry + statements[entry..].iter().position(|s| *s == Statement::Ret).unwrap();
+
+        let saved: Vec<&Register> = statements[entry + 1..]
+            .iter()
+            .map_while(|s| match s {
+                Statement::Push { src: Operand::Register(register) } => Some(register),
+                _ => None,
+            })
+            .collect();
+        let restored: Vec<&Register> = statements[..ret]
+            .iter()
+            .rev()
+            .map_while(|s| match s {
+                Statement::Pop { dest: Operand::Register(register) } => Some(register),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(saved, restored);
+
+        for statement in statements {
+            if let Some(register) = written_register(statement) {
+                if CALLEE_SAVED.contains(register) {
+                    assert!(
+                        saved.contains(&register),
+                        "{:?} writes to a callee-saved register which is not saved",
+                        statement
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arguments_of_entry_are_read_only_at_entry() {
+        let source = "
+            (define (f x) (begin (write-byte x) (box x)))
+            (begin (write-string \"ab\") (unbox (f (string-length (make-string 3 #\\a)))))";
+        let program = compile(parse(source).unwrap(), &CompileOptions::default()).unwrap();
+        let statements = &program.statements;
+
+        let entry = statements
+            .iter()
+            .position(|s| *s == Statement::Label { name: "entry".to_string() })
+            .unwrap();
+        let is_argument = |operand: &Operand| match operand {
+            Operand::Register(register) | Operand::Offset(register, _) => {
+                matches!(register, Register::RDI | Register::RSI)
+            }
+            _ => false,
+        };
+        let reads: Vec<usize> = statements
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| match s {
+                Statement::Mov { dest, src } => {
+                    is_argument(src) || matches!(dest, Operand::Offset(Register::RDI | Register::RSI, _))
+                }
+                Statement::Add { src, .. }
+                | Statement::Sub { src, .. }
+                | Statement::Cmp { src, .. }
+                | Statement::Push { src } => is_argument(src),
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect();
+
+        // Only `mov rbx, rdi` and `mov r12, rsi` right after the callee-saved registers are pushed.
+        assert_eq!(reads, vec![entry + 3, entry + 4]);
+        assert_eq!(statements[entry + 3], Statement::Mov { dest: RBX, src: RDI });
+        assert_eq!(statements[entry + 4], Statement::Mov { dest: R12, src: RSI });
+    }
+
+    /// Returns the registers written by the instructions.
+    fn clobbered_registers(statements: &[Statement]) -> Vec<&Register> {
+        let mut registers = vec![];
+        for register in statements.iter().filter_map(written_register) {
+            if !registers.contains(&register) {
+                registers.push(register);
+            }
+        }
+        registers
+    }
+
+    #[test]
+    fn helpers_clobber_only_documented_registers() {
+        use self::{helper::*, types::*};
+        use crate::mylang::data_type::{CHAR_TYPE, INT_TYPE};
+
+        let options = CompileOptions {
+            check_heap_pointers: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new(Default::default(), options);
+
+        let cases = [
+            (assert_int(Register::RAX), vec![Register::R9]),
+            (assert_char(Register::R8), vec![Register::R9]),
+            (assert_natural_number(Register::R8), vec![Register::R9]),
+            (assert_byte(Register::RAX), vec![Register::R9]),
+            (assert_index(Register::R10), vec![Register::R9, Register::RSI]),
+            (
+                assert_in_bounds(Register::R10, Register::R9),
+                vec![Register::RSI, Register::RDX, Register::R9],
+            ),
+            (assert_heap_pointer(Register::RAX, &compiler), vec![Register::R9]),
+            (cast_type(Register::R8, &INT_TYPE, &CHAR_TYPE), vec![Register::R8]),
+            (if_equal(), vec![Register::RAX, Register::R9]),
+            (if_less_than(), vec![Register::RAX, Register::R9]),
+            (if_greater(), vec![Register::RAX, Register::R9]),
+        ];
+
+        for (statements, documented) in &cases {
+            for register in clobbered_registers(statements) {
+                assert!(
+                    documented.contains(register),
+                    "{:?} clobbers {:?}, which is not documented",
+                    statements,
+                    register
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn defines_capturing_nothing_use_no_heap() {
+        let source = "
+            (define (f x) (add1 x))
+            (define (g x) (sub1 x))
+            (g (f 1))";
+        let program = compile(parse(source).unwrap(), &CompileOptions::default()).unwrap();
 
-    Program { statements }
+        for statement in &program.statements {
+            let writes_heap = match statement {
+                Statement::Mov { dest: Operand::Offset(Register::RBX, _), .. } => true,
+                Statement::Mov { src: RDI, .. } | Statement::Pop { .. } => false,
+                _ => written_register(statement) == Some(&Register::RBX),
+            };
+            assert!(!writes_heap, "{:?} touches the heap", statement);
+        }
+    }
 }
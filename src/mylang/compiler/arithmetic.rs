@@ -1,15 +1,20 @@
 use crate::{
     a86::ast::{Operand, Register, Statement},
-    mylang::data_type::Value,
+    mylang::data_type::{Value, INT_TYPE},
 };
 
 use super::{
-    helper::{if_equal, if_less_than},
+    error::ERR_LABEL,
+    helper::{if_equal, if_greater, if_less_than},
+    state::Compiler,
     types::assert_int,
 };
 
 const RAX: Operand = Operand::Register(Register::RAX);
+const RDX: Operand = Operand::Register(Register::RDX);
 const R8: Operand = Operand::Register(Register::R8);
+const R9: Operand = Operand::Register(Register::R9);
+const R10: Operand = Operand::Register(Register::R10);
 
 /// Returns instructions which adds two integers in rax and r8.
 pub fn compile_add() -> Vec<Statement> {
@@ -28,6 +33,100 @@ pub fn compile_sub() -> Vec<Statement> {
     statements
 }
 
+/// Returns instructions which multiplies two integers in rax and r8.
+/// It raises an error if the product is out of the range of integers, instead of wrapping around.
+///
+/// The tagged product overflows 64 bits exactly when the product overflows the integers,
+/// since only the tagged operand is scaled by the tag.
+pub fn compile_mul() -> Vec<Statement> {
+    let mut statements = assert_int(Register::RAX);
+    statements.extend(assert_int(Register::R8));
+    // Decode one of the operands, so that the product is tagged only once.
+    statements.push(Statement::Sar {
+        dest: RAX,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+    statements.push(Statement::Imul { dest: RAX, src: R8 });
+    statements.push(Statement::Jo {
+        label: ERR_LABEL.to_string(),
+    });
+    statements
+}
+
+/// Returns instructions which returns `r8 modulo rax`, whose sign is the same as rax.
+/// It raises an error if rax is zero.
+///
+/// This clobbers r9, r10, and rdx.
+pub fn compile_modulo(compiler: &mut Compiler) -> Vec<Statement> {
+    let id = compiler.new_label_id();
+    let adjust_label = format!("modulo_adjust_{}", id);
+    let done_label = format!("modulo_done_{}", id);
+
+    let mut statements = assert_int(Register::RAX);
+    statements.extend(assert_int(Register::R8));
+    statements.extend([
+        Statement::Cmp {
+            dest: RAX,
+            src: Operand::from(Value::Int(0)),
+        },
+        Statement::Je {
+            label: ERR_LABEL.to_string(),
+        },
+        // Divide the tagged integers as they are.
+        // Since both are shifted by the same amount, the remainder is also a tagged integer.
+        Statement::Mov { dest: R9, src: RAX },
+        Statement::Mov { dest: RAX, src: R8 },
+        Statement::Cqo,
+        Statement::Idiv { src: R9 },
+        // The remainder has the same sign as the dividend.
+        Statement::Mov { dest: RAX, src: RDX },
+        Statement::Test {
+            dest: RAX,
+            src: RAX,
+        },
+        Statement::Je {
+            label: done_label.clone(),
+        },
+        // Add the divisor if the signs of the remainder and the divisor differ.
+        Statement::Mov { dest: R10, src: RAX },
+        Statement::Xor { dest: R10, src: R9 },
+        Statement::Test {
+            dest: R10,
+            src: R10,
+        },
+        Statement::Jl {
+            label: adjust_label.clone(),
+        },
+        Statement::Jmp {
+            label: done_label.clone(),
+        },
+        Statement::Label { name: adjust_label },
+        Statement::Add { dest: RAX, src: R9 },
+        Statement::Label { name: done_label },
+    ]);
+    statements
+}
+
+/// Returns instructions which sets rax to `rax modulo divisor`,
+/// if the divisor is a positive power of two small enough for the mask to fit in an immediate.
+///
+/// Then the result is just the lower bits of rax, which are nonnegative as the divisor is,
+/// and the bits below the tag stay zero for any integer.
+pub fn compile_modulo_by_power_of_two(divisor: i64) -> Option<Vec<Statement>> {
+    if divisor <= 0 || divisor & (divisor - 1) != 0 {
+        return None;
+    }
+    let mask = Value::Int(divisor - 1).encode();
+    i32::try_from(mask).ok()?;
+
+    let mut statements = assert_int(Register::RAX);
+    statements.push(Statement::And {
+        dest: RAX,
+        src: Operand::Immediate(mask),
+    });
+    Some(statements)
+}
+
 /// Returns instructions which adds 1 to an integer in rax.
 pub fn compile_add1() -> Vec<Statement> {
     let mut statements = assert_int(Register::RAX);
@@ -48,6 +147,26 @@ pub fn compile_sub1() -> Vec<Statement> {
     statements
 }
 
+/// Returns instructions which negates an integer in rax.
+pub fn compile_negate() -> Vec<Statement> {
+    let mut statements = assert_int(Register::RAX);
+    statements.push(Statement::Neg { dest: RAX });
+    statements
+}
+
+/// Returns instructions which flips every bit of an integer in rax.
+pub fn compile_bit_not() -> Vec<Statement> {
+    let mut statements = assert_int(Register::RAX);
+    // Flipping the tagged integer flips the bits of the integer, but also sets the tag bits,
+    // so clear them to tag the result again.
+    statements.push(Statement::Not { dest: RAX });
+    statements.push(Statement::And {
+        dest: RAX,
+        src: Operand::Immediate(!(INT_TYPE.mask() as i64)),
+    });
+    statements
+}
+
 /// Returns instructions which sets rax to true if rax is zero.
 /// It raises an error if rax is not integer.
 pub fn compile_is_zero() -> Vec<Statement> {
@@ -60,6 +179,30 @@ pub fn compile_is_zero() -> Vec<Statement> {
     statements
 }
 
+/// Returns instructions which sets rax to true if rax is greater than zero.
+/// It raises an error if rax is not integer.
+pub fn compile_is_positive() -> Vec<Statement> {
+    let mut statements = assert_int(Register::RAX);
+    statements.push(Statement::Cmp {
+        dest: RAX,
+        src: Operand::from(Value::Int(0)),
+    });
+    statements.extend(if_greater());
+    statements
+}
+
+/// Returns instructions which sets rax to true if rax is less than zero.
+/// It raises an error if rax is not integer.
+pub fn compile_is_negative() -> Vec<Statement> {
+    let mut statements = assert_int(Register::RAX);
+    statements.push(Statement::Cmp {
+        dest: RAX,
+        src: Operand::from(Value::Int(0)),
+    });
+    statements.extend(if_less_than());
+    statements
+}
+
 /// Returns instructions which sets rax to true if rax and r8 are equal integers.
 /// It raises an error if rax or r8 is not integer.
 pub fn compile_int_equal() -> Vec<Statement> {
@@ -70,12 +213,13 @@ pub fn compile_int_equal() -> Vec<Statement> {
     statements
 }
 
-/// Returns instructions which sets rax to true if rax is less than r8.
+/// Returns instructions which sets rax to true if r8 is less than rax.
 /// It raises an error if rax or r8 is not integer.
 pub fn compile_less_than() -> Vec<Statement> {
     let mut statements = assert_int(Register::RAX);
     statements.extend(assert_int(Register::R8));
-    statements.push(Statement::Cmp { dest: RAX, src: R8 });
+    // The first operand is in r8 and the second one is in rax.
+    statements.push(Statement::Cmp { dest: R8, src: RAX });
     statements.extend(if_less_than());
     statements
 }
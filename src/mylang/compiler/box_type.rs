@@ -3,7 +3,10 @@ use crate::{
     mylang::data_type::BOX_TYPE,
 };
 
-use super::types::assert_box;
+use super::{
+    state::Compiler,
+    types::{assert_box, assert_heap_pointer},
+};
 
 const RAX: Operand = Operand::Register(Register::RAX);
 const RBX: Operand = Operand::Register(Register::RBX);
@@ -32,12 +35,13 @@ pub fn compile_box() -> Vec<Statement> {
     ]
 }
 
-pub fn compile_unbox() -> Vec<Statement> {
+pub fn compile_unbox(compiler: &Compiler) -> Vec<Statement> {
     let mut statements = assert_box(Register::RAX);
     statements.push(Statement::Xor {
         dest: RAX,
         src: Operand::Immediate(BOX_TYPE.tag.0 as i64),
     });
+    statements.extend(assert_heap_pointer(Register::RAX, compiler));
     statements.push(Statement::Mov {
         dest: RAX,
         src: Operand::Offset(Register::RAX, 0),
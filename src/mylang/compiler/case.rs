@@ -0,0 +1,171 @@
+//! `case` jumps to the clause listing the value, either through a chain of comparisons with the datums,
+//! or through a table of the addresses of the clauses if the datums are dense integers.
+
+use crate::{
+    a86::ast::{Operand, Register, Statement},
+    mylang::{
+        ast,
+        data_type::{Value, INT_TYPE},
+        error::CompileError,
+    },
+};
+
+use super::{
+    expr::{compile_expr, int_value},
+    pattern_match::compare_literal,
+    state::Compiler,
+    variable::VariablesTable,
+};
+
+const RAX: Operand = Operand::Register(Register::RAX);
+const R9: Operand = Operand::Register(Register::R9);
+const R10: Operand = Operand::Register(Register::R10);
+
+/// The fewest datums for which a jump table is used, since a few comparisons are as fast as the table.
+const MIN_TABLE_DATUMS: usize = 4;
+
+/// The most entries of a jump table, so that a few distant datums do not make a huge table.
+const MAX_TABLE_LENGTH: usize = 1024;
+
+pub fn compile_case(
+    case: ast::Case,
+    compiler: &mut Compiler,
+    env: &VariablesTable,
+    is_tail_expr: bool,
+) -> Result<Vec<Statement>, CompileError> {
+    let id = compiler.new_label_id();
+    let else_label = format!("case_else_{}", id);
+    let end_label = format!("case_end_{}", id);
+    let clause_labels: Vec<String> = (0..case.clauses.len())
+        .map(|i| format!("case_{}_{}", id, i))
+        .collect();
+
+    let mut statements = compile_expr(*case.expr, compiler, env, false)?;
+    match jump_table(&case.clauses) {
+        Some(table) => {
+            let targets = table
+                .entries
+                .iter()
+                .map(|entry| entry.map_or(&else_label, |i| &clause_labels[i]).clone())
+                .collect();
+            let table_label = format!("case_table_{}", id);
+            statements.extend(jump_through_table(&table, &table_label, &else_label));
+            compiler.add_jump_table(table_label, targets);
+        }
+        None => {
+            for (clause, label) in case.clauses.iter().zip(&clause_labels) {
+                for datum in &clause.datums {
+                    statements.extend(compare_literal(datum.clone(), compiler)?);
+                    statements.push(Statement::Je {
+                        label: label.clone(),
+                    });
+                }
+            }
+            statements.push(Statement::Jmp {
+                label: else_label.clone(),
+            });
+        }
+    }
+
+    for (clause, label) in case.clauses.into_iter().zip(clause_labels) {
+        statements.push(Statement::Label { name: label });
+        statements.extend(compile_expr(*clause.body, compiler, env, is_tail_expr)?);
+        statements.push(Statement::Jmp {
+            label: end_label.clone(),
+        });
+    }
+    statements.push(Statement::Label { name: else_label });
+    statements.extend(compile_expr(*case.els, compiler, env, is_tail_expr)?);
+    statements.push(Statement::Label { name: end_label });
+
+    Ok(statements)
+}
+
+/// The clauses to jump to for the consecutive integers from the smallest datum.
+struct JumpTable {
+    min: i64,
+    /// The index of the clause for each integer, or `None` for the `else` clause.
+    entries: Vec<Option<usize>>,
+}
+
+/// Returns the jump table if all datums are integers, and they fill at least half of the table.
+/// A datum listed in more than one clause jumps to the first one, as the comparisons do.
+fn jump_table(clauses: &[ast::CaseClause]) -> Option<JumpTable> {
+    let mut datums = vec![];
+    for (i, clause) in clauses.iter().enumerate() {
+        for datum in &clause.datums {
+            match datum {
+                // An integer out of range is left to the comparisons, which report it.
+                ast::Lit::Int(n) if int_value(*n).is_ok() => datums.push((*n, i)),
+                _ => return None,
+            }
+        }
+    }
+
+    let min = datums.iter().map(|(n, _)| *n).min()?;
+    let max = datums.iter().map(|(n, _)| *n).max()?;
+    let length = usize::try_from(max - min).ok()?.checked_add(1)?;
+    let is_dense = datums.len() >= MIN_TABLE_DATUMS && length <= 2 * datums.len();
+    // The smallest datum is subtracted as an immediate, which must fit in 32 bits.
+    let fits = i32::try_from(Value::Int(min).encode()).is_ok();
+    if !is_dense || !fits || length > MAX_TABLE_LENGTH {
+        return None;
+    }
+
+    let mut entries = vec![None; length];
+    for (n, i) in datums {
+        entries[(n - min) as usize].get_or_insert(i);
+    }
+    Some(JumpTable { min, entries })
+}
+
+/// Returns instructions which jump to the address in the table indexed by the integer in rax,
+/// or to the `else` clause if rax is not an integer in the range of the table.
+///
+/// This clobbers r9 and r10.
+fn jump_through_table(table: &JumpTable, table_label: &str, else_label: &str) -> Vec<Statement> {
+    vec![
+        Statement::Test {
+            dest: RAX,
+            src: Operand::Immediate(INT_TYPE.mask() as i64),
+        },
+        Statement::Jne {
+            label: else_label.to_string(),
+        },
+        // Set r9 to the tagged index into the table.
+        Statement::Mov { dest: R9, src: RAX },
+        Statement::Sub {
+            dest: R9,
+            src: Operand::from(Value::Int(table.min)),
+        },
+        Statement::Jl {
+            label: else_label.to_string(),
+        },
+        Statement::Cmp {
+            dest: R9,
+            src: Operand::from(Value::Int(table.entries.len() as i64)),
+        },
+        Statement::Jge {
+            label: else_label.to_string(),
+        },
+        // Scale the tagged index to the offset of the 8-byte entry.
+        Statement::Sar {
+            dest: R9,
+            src: Operand::Immediate(INT_TYPE.shift as i64 - 3),
+        },
+        Statement::Lea {
+            dest: R10,
+            label: table_label.to_string(),
+        },
+        Statement::Mov {
+            dest: R10,
+            src: Operand::ScaledIndex {
+                base: Register::R10,
+                index: Register::R9,
+                scale: 1,
+                disp: 0,
+            },
+        },
+        Statement::JmpRegister(Register::R10),
+    ]
+}
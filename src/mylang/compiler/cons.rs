@@ -3,7 +3,10 @@ use crate::{
     mylang::data_type::CONS_TYPE,
 };
 
-use super::types::assert_cons;
+use super::{
+    state::Compiler,
+    types::{assert_cons, assert_heap_pointer},
+};
 
 const RAX: Operand = Operand::Register(Register::RAX);
 const R8: Operand = Operand::Register(Register::R8);
@@ -39,12 +42,13 @@ pub fn compile_cons() -> Vec<Statement> {
     ]
 }
 
-pub fn compile_car() -> Vec<Statement> {
+pub fn compile_car(compiler: &Compiler) -> Vec<Statement> {
     let mut statements = assert_cons(Register::RAX);
     statements.push(Statement::Xor {
         dest: RAX,
         src: Operand::Immediate(CONS_TYPE.tag.0 as i64),
     });
+    statements.extend(assert_heap_pointer(Register::RAX, compiler));
     statements.push(Statement::Mov {
         dest: RAX,
         src: Operand::Offset(Register::RAX, 8),
@@ -52,12 +56,13 @@ pub fn compile_car() -> Vec<Statement> {
     statements
 }
 
-pub fn compile_cdr() -> Vec<Statement> {
+pub fn compile_cdr(compiler: &Compiler) -> Vec<Statement> {
     let mut statements = assert_cons(Register::RAX);
     statements.push(Statement::Xor {
         dest: RAX,
         src: Operand::Immediate(CONS_TYPE.tag.0 as i64),
     });
+    statements.extend(assert_heap_pointer(Register::RAX, compiler));
     statements.push(Statement::Mov {
         dest: RAX,
         src: Operand::Offset(Register::RAX, 0),
@@ -1,16 +1,69 @@
-use crate::a86::ast::Statement;
+use crate::a86::ast::{Operand, Register, Statement};
 
 use super::external_call::compile_raise_error;
 
 /// Jump to this label to raise an error.
 pub const ERR_LABEL: &str = "err";
 
+/// Jump to this label to raise an error for an index out of the bounds of a vector or a string.
+/// The raw index and length must be set in rsi and rdx respectively.
+pub const BOUNDS_ERR_LABEL: &str = "bounds_err";
+
+/// Jump to this label to raise an error for a negative index.
+/// The raw index must be set in rsi.
+pub const NEGATIVE_INDEX_ERR_LABEL: &str = "negative_index_err";
+
+/// Jump to this label to raise an error for an integer outside the range of Unicode code points.
+/// The raw integer must be set in rsi.
+pub const CODEPOINT_RANGE_ERR_LABEL: &str = "codepoint_range_err";
+
+/// Jump to this label to raise an error for a surrogate code point, which is not a valid character.
+/// The raw integer must be set in rsi.
+pub const SURROGATE_ERR_LABEL: &str = "surrogate_err";
+
+/// Jump to this label to raise an error when the stack grows beyond the limit set by the runtime.
+pub const STACK_OVERFLOW_ERR_LABEL: &str = "stack_overflow_err";
+
+/// Jump to this label to raise an error for a failed `assert`.
+pub const ASSERTION_ERR_LABEL: &str = "assertion_err";
+
+/// Categories of runtime errors passed to the runtime function `raise_error`.
+///
+/// These must agree with `error_code_t` in the runtime.
+#[derive(Debug, Clone, Copy)]
+enum ErrorCode {
+    Generic = 0,
+    IndexOutOfRange = 1,
+    NegativeIndex = 2,
+    CodepointOutOfRange = 3,
+    SurrogateCodepoint = 4,
+    StackOverflow = 5,
+    AssertionFailed = 6,
+}
+
 /// Put these instructions once at the end of the program.
-/// Jump to [ERR_LABEL] whenever you want to raise an error.
+/// Jump to [ERR_LABEL] or the other error labels whenever you want to raise an error.
 pub fn compile_error_handler() -> Vec<Statement> {
-    let mut statements = vec![Statement::Label {
-        name: ERR_LABEL.to_string(),
-    }];
-    statements.extend(compile_raise_error());
+    let handlers = [
+        (ERR_LABEL, ErrorCode::Generic),
+        (BOUNDS_ERR_LABEL, ErrorCode::IndexOutOfRange),
+        (NEGATIVE_INDEX_ERR_LABEL, ErrorCode::NegativeIndex),
+        (CODEPOINT_RANGE_ERR_LABEL, ErrorCode::CodepointOutOfRange),
+        (SURROGATE_ERR_LABEL, ErrorCode::SurrogateCodepoint),
+        (STACK_OVERFLOW_ERR_LABEL, ErrorCode::StackOverflow),
+        (ASSERTION_ERR_LABEL, ErrorCode::AssertionFailed),
+    ];
+
+    let mut statements = vec![];
+    for (label, code) in handlers {
+        statements.push(Statement::Label {
+            name: label.to_string(),
+        });
+        statements.push(Statement::Mov {
+            dest: Operand::Register(Register::RDI),
+            src: Operand::Immediate(code as i64),
+        });
+        statements.extend(compile_raise_error());
+    }
     statements
 }
@@ -1,7 +1,9 @@
 use crate::a86::ast::*;
 use crate::mylang::ast;
-use crate::mylang::data_type::Value;
+use crate::mylang::data_type::{Value, MAX_INT, MIN_INT};
+use crate::mylang::error::CompileError;
 
+use super::case::compile_case;
 use super::function::{compile_function_application, compile_closure};
 use super::pattern_match::compile_match;
 use super::primitive_functions::{compile_prim0, compile_prim1, compile_prim2, compile_prim3};
@@ -23,15 +25,22 @@ pub fn compile_expr(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
-    match expr {
-        ast::Expr::Eof => compile_value(Value::Eof),
+) -> Result<Vec<Statement>, CompileError> {
+    // Mark the start of the code for the source map.
+    let mut statements: Vec<Statement> = compiler
+        .new_source_label(expr.position())
+        .map(|name| Statement::Label { name })
+        .into_iter()
+        .collect();
+
+    let compiled = match expr {
+        ast::Expr::Eof => Ok(compile_value(Value::Eof)),
         ast::Expr::Lit(lit) => compile_literal(lit, &compiler),
 
-        ast::Expr::Prim0(op) => compile_prim0(op),
-        ast::Expr::Prim1(op, expr) => compile_prim1(op, *expr, compiler, env),
-        ast::Expr::Prim2(op, first, second) => compile_prim2(op, *first, *second, compiler, env),
-        ast::Expr::Prim3(op, first, second, third) => {
+        ast::Expr::Prim0(op, _) => Ok(compile_prim0(op)),
+        ast::Expr::Prim1(op, expr, _) => compile_prim1(op, *expr, compiler, env),
+        ast::Expr::Prim2(op, first, second, _) => compile_prim2(op, *first, *second, compiler, env),
+        ast::Expr::Prim3(op, first, second, third, _) => {
             compile_prim3(op, *first, *second, *third, compiler, env)
         }
 
@@ -42,22 +51,35 @@ pub fn compile_expr(
         ast::Expr::If(if_zero) => compile_if_expr(if_zero, compiler, env, is_tail_expr),
         
         ast::Expr::Match(match_expr) => compile_match(match_expr, compiler, env, is_tail_expr),
+        ast::Expr::Case(case) => compile_case(case, compiler, env, is_tail_expr),
 
         ast::Expr::Variable(variable) => compile_variable(variable, compiler, env),
         ast::Expr::Let(let_expr) => compile_let(let_expr, compiler, env, is_tail_expr),
 
         ast::Expr::App(app) => compile_function_application(app, compiler, env, is_tail_expr),
         ast::Expr::Lambda(lambda) => compile_closure(lambda, env),
-    }
+    }?;
+    statements.extend(compiled);
+    Ok(statements)
 }
 
-fn compile_literal(lit: ast::Lit, compiler: &Compiler) -> Vec<Statement> {
+fn compile_literal(lit: ast::Lit, compiler: &Compiler) -> Result<Vec<Statement>, CompileError> {
     match lit {
-        ast::Lit::Int(i) => compile_value(Value::Int(i)),
-        ast::Lit::Bool(b) => compile_value(Value::Boolean(b)),
-        ast::Lit::Char(c) => compile_value(Value::Char(c)),
-        ast::Lit::String(s) => compile_string_literal(&s, compiler),
-        ast::Lit::EmptyList => compile_value(Value::EmptyList),
+        ast::Lit::Int(i) => Ok(compile_value(int_value(i)?)),
+        ast::Lit::Bool(b) => Ok(compile_value(Value::Boolean(b))),
+        ast::Lit::Char(c) => Ok(compile_value(Value::Char(c))),
+        ast::Lit::String(s) => Ok(compile_string_literal(&s, compiler)),
+        ast::Lit::EmptyList => Ok(compile_value(Value::EmptyList)),
+        ast::Lit::Void => Ok(compile_value(Value::Void)),
+    }
+}
+
+/// Returns the integer value, making sure it fits in the tagged representation.
+pub fn int_value(i: i64) -> Result<Value, CompileError> {
+    if (MIN_INT..=MAX_INT).contains(&i) {
+        Ok(Value::Int(i))
+    } else {
+        Err(CompileError::IntegerOutOfRange(i))
     }
 }
 
@@ -74,10 +96,50 @@ fn compile_begin(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
-    let mut statements = compile_expr(first, compiler, env, false);
-    statements.extend(compile_expr(second, compiler, env, is_tail_expr));
-    statements
+) -> Result<Vec<Statement>, CompileError> {
+    let pure = is_pure(&first);
+    let mut statements = compile_expr(first, compiler, env, false)?;
+
+    // The value of the first expression is discarded, so it need not be evaluated at all
+    // unless it has side effects. It is still compiled above to report errors in it.
+    if pure {
+        statements.clear();
+    }
+
+    statements.extend(compile_expr(second, compiler, env, is_tail_expr)?);
+    Ok(statements)
+}
+
+/// Returns true if evaluating the expression is known to have no side effects,
+/// i.e. it neither does I/O, allocates memory, nor raises an error.
+///
+/// This is conservative, so it may return false for some expressions which are actually pure.
+fn is_pure(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Eof | ast::Expr::Lit(_) | ast::Expr::Variable(_) => true,
+        ast::Expr::Prim0(ast::Op0::EofObject, _) => true,
+
+        // Type predicates accept any value.
+        ast::Expr::Prim1(op, expr, _) => {
+            matches!(
+                op,
+                ast::Op1::Identity
+                    | ast::Op1::IsChar
+                    | ast::Op1::IsEof
+                    | ast::Op1::IsBox
+                    | ast::Op1::IsCons
+                    | ast::Op1::IsVector
+                    | ast::Op1::IsString
+            ) && is_pure(expr)
+        }
+
+        ast::Expr::Begin(first, second) => is_pure(first) && is_pure(second),
+        ast::Expr::If(if_expr) => {
+            is_pure(&if_expr.cond) && is_pure(&if_expr.then) && is_pure(&if_expr.els)
+        }
+
+        _ => false,
+    }
 }
 
 fn compile_if_expr(
@@ -85,12 +147,24 @@ fn compile_if_expr(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
+    // With a literal boolean condition, only the branch taken needs to be emitted.
+    // The other one is still compiled, so that errors in it are reported all the same.
+    if let ast::Expr::Lit(ast::Lit::Bool(cond)) = *if_expr.cond {
+        let (taken, dead) = if cond {
+            (*if_expr.then, *if_expr.els)
+        } else {
+            (*if_expr.els, *if_expr.then)
+        };
+        compile_expr(dead, compiler, env, is_tail_expr)?;
+        return compile_expr(taken, compiler, env, is_tail_expr);
+    }
+
     let label_id = compiler.new_label_id();
     let else_label = format!("else_{}", label_id);
     let end_label = format!("end_{}", label_id);
 
-    let mut statements = compile_expr(*if_expr.cond, compiler, env, false);
+    let mut statements = compile_expr(*if_expr.cond, compiler, env, false)?;
     statements.push(Statement::Cmp {
         dest: RAX,
         src: Operand::from(Value::Boolean(false)),
@@ -98,18 +172,18 @@ fn compile_if_expr(
     statements.push(Statement::Je {
         label: else_label.clone(),
     });
-    statements.extend(compile_expr(*if_expr.then, compiler, env, is_tail_expr));
+    statements.extend(compile_expr(*if_expr.then, compiler, env, is_tail_expr)?);
     statements.push(Statement::Jmp {
         label: end_label.clone(),
     });
     statements.push(Statement::Label {
         name: else_label.clone(),
     });
-    statements.extend(compile_expr(*if_expr.els, compiler, env, is_tail_expr));
+    statements.extend(compile_expr(*if_expr.els, compiler, env, is_tail_expr)?);
     statements.push(Statement::Label {
         name: end_label.clone(),
     });
-    statements
+    Ok(statements)
 }
 
 impl From<Value> for Operand {
@@ -1,14 +1,30 @@
 use crate::a86::ast::{Operand, Register, Statement};
+use crate::mylang::data_type::Value;
 
-use super::types::assert_byte;
+use super::types::{assert_byte, assert_natural_number, assert_string};
 
 const RAX: Operand = Operand::Register(Register::RAX);
 const RDI: Operand = Operand::Register(Register::RDI);
-const R15: Operand = Operand::Register(Register::R15);
+const RSI: Operand = Operand::Register(Register::RSI);
+const R8: Operand = Operand::Register(Register::R8);
+const R10: Operand = Operand::Register(Register::R10);
 const RSP: Operand = Operand::Register(Register::RSP);
 
 /// Return the instructions for declaring external functions.
 /// Put the them at the beginning of the program.
+///
+/// The functions take the values in the tagged representation and return one, unless noted otherwise:
+/// * `read_byte()`
+/// * `peek_byte(skip)`: `skip` is a nonnegative integer.
+/// * `write_byte(byte)`: `byte` is an integer in 0..=255.
+/// * `write_string(string)`
+/// * `flush_output()`: writes out the bytes buffered for stdout. The return value is ignored.
+/// * `display(value)`
+/// * `user_error(message)`: `message` is a string. It prints the message to stderr and never returns.
+/// * `raise_error(code, a, b)`: `code` is a raw [super::error] code, and `a` and `b` are raw details. It never returns.
+/// * `assoc(key, list)`, `assq(key, list)`, `member(value, list)`, `memq(value, list)`
+///
+/// `heap` is not a function but the address of the heap memory allocated by the runtime.
 pub fn externals() -> Vec<Statement> {
     vec![
         Statement::Extern {
@@ -20,20 +36,61 @@ pub fn externals() -> Vec<Statement> {
         Statement::Extern {
             name: "write_byte".to_string(),
         },
+        Statement::Extern {
+            name: "write_string".to_string(),
+        },
+        Statement::Extern {
+            name: "flush_output".to_string(),
+        },
+        Statement::Extern {
+            name: "display".to_string(),
+        },
+        Statement::Extern {
+            name: "user_error".to_string(),
+        },
         Statement::Extern {
             name: "raise_error".to_string(),
         },
+        Statement::Extern {
+            name: "assoc".to_string(),
+        },
+        Statement::Extern {
+            name: "assq".to_string(),
+        },
+        Statement::Extern {
+            name: "member".to_string(),
+        },
+        Statement::Extern {
+            name: "memq".to_string(),
+        },
+        Statement::Extern {
+            name: "heap".to_string(),
+        },
     ]
 }
 
+/// Returns instructions which set rax to the next byte from stdin, or the eof value.
+///
+/// The runtime already returns a tagged value, i.e. an integer in 0..=255 or [Value::Eof].
 pub fn compile_read_byte() -> Vec<Statement> {
     call("read_byte".to_string())
 }
 
+/// Returns instructions which set rax to the byte the number of bytes in rax ahead in stdin,
+/// or the eof value, leaving the bytes in stdin.
 pub fn compile_peek_byte() -> Vec<Statement> {
-    call("peek_byte".to_string())
+    let mut statements = assert_natural_number(Register::RAX);
+    statements.push(Statement::Mov {
+        dest: RDI,
+        src: RAX,
+    });
+    statements.extend(call("peek_byte".to_string()));
+    statements
 }
 
+/// Returns instructions which write the byte in rax to stdout.
+///
+/// Like `vector-set!`, it always evaluates to void regardless of what the runtime returns.
 pub fn compile_write_byte() -> Vec<Statement> {
     let mut statements = assert_byte(Register::RAX);
     statements.push(Statement::Mov {
@@ -41,6 +98,97 @@ pub fn compile_write_byte() -> Vec<Statement> {
         src: RAX,
     });
     statements.extend(call("write_byte".to_string()));
+    statements.push(Statement::Mov {
+        dest: RAX,
+        src: Operand::from(Value::Void),
+    });
+    statements
+}
+
+/// Returns instructions which write out the bytes buffered for stdout, and set rax to void.
+///
+/// Call it before waiting for the input in interactive programs, so that the prompt is shown.
+pub fn compile_flush_output() -> Vec<Statement> {
+    let mut statements = call("flush_output".to_string());
+    statements.push(Statement::Mov {
+        dest: RAX,
+        src: Operand::from(Value::Void),
+    });
+    statements
+}
+
+pub fn compile_newline() -> Vec<Statement> {
+    let mut statements = vec![Statement::Mov {
+        dest: RDI,
+        src: Operand::from(Value::Int(10)),
+    }];
+    statements.extend(call("write_byte".to_string()));
+    statements
+}
+
+pub fn compile_write_string() -> Vec<Statement> {
+    let mut statements = assert_string(Register::RAX);
+    statements.push(Statement::Mov {
+        dest: RDI,
+        src: RAX,
+    });
+    statements.extend(call("write_string".to_string()));
+    statements
+}
+
+pub fn compile_display() -> Vec<Statement> {
+    let mut statements = vec![Statement::Mov {
+        dest: RDI,
+        src: RAX,
+    }];
+    statements.extend(call("display".to_string()));
+    statements
+}
+
+/// Returns instructions which set rax to the first pair in the association list in rax
+/// whose car is `equal?` to the key in r8, or false if there is none.
+pub fn compile_assoc() -> Vec<Statement> {
+    call_with_key("assoc".to_string())
+}
+
+/// Like [compile_assoc], but compares the keys by `eq?`.
+pub fn compile_assq() -> Vec<Statement> {
+    call_with_key("assq".to_string())
+}
+
+/// Returns instructions which set rax to the first tail of the list in rax
+/// whose car is `equal?` to the value in r8, or false if there is none.
+pub fn compile_member() -> Vec<Statement> {
+    call_with_key("member".to_string())
+}
+
+/// Like [compile_member], but compares the elements by `eq?`.
+pub fn compile_memq() -> Vec<Statement> {
+    call_with_key("memq".to_string())
+}
+
+/// Returns instructions which call the runtime function with the key in r8 and the list in rax.
+fn call_with_key(label: String) -> Vec<Statement> {
+    let mut statements = vec![
+        Statement::Mov { dest: RDI, src: R8 },
+        Statement::Mov { dest: RSI, src: RAX },
+    ];
+    statements.extend(call(label));
+    statements
+}
+
+/// Returns instructions which raise an error with the string in rax as the message.
+/// The stack is left padded, as the runtime never returns.
+pub fn compile_user_error() -> Vec<Statement> {
+    let mut statements = assert_string(Register::RAX);
+    statements.push(Statement::Mov {
+        dest: RDI,
+        src: RAX,
+    });
+    statements.extend(pad_stack());
+    statements.push(Statement::Call {
+        label: "user_error".to_string(),
+    });
     statements
 }
 
@@ -62,27 +210,36 @@ fn call(label: String) -> Vec<Statement> {
 /// Returns instructions which alligns the stack pointer to a 16-byte boundary.
 /// This must be done before calling an external function.
 /// After the call, the stack must be unaligned using [unpad_stack].
+///
+/// The original stack pointer is saved on the stack itself rather than in a register,
+/// so that it survives the call no matter which registers the callee uses.
+/// This clobbers r10.
 fn pad_stack() -> Vec<Statement> {
     vec![
         Statement::Mov {
-            dest: R15,
+            dest: R10,
             src: RSP,
         },
-        Statement::And {
-            dest: R15,
-            src: Operand::Immediate(0b1000),
-        },
+        // Reserve a word below the original stack pointer, and round down to a 16-byte boundary.
         Statement::Sub {
             dest: RSP,
-            src: R15,
+            src: Operand::Immediate(8),
+        },
+        Statement::And {
+            dest: RSP,
+            src: Operand::Immediate(-16),
+        },
+        Statement::Mov {
+            dest: Operand::Offset(Register::RSP, 0),
+            src: R10,
         },
     ]
 }
 
 /// Returns instructions which undo the stack alignment done by [pad_stack].
 fn unpad_stack() -> Vec<Statement> {
-    vec![Statement::Add {
+    vec![Statement::Mov {
         dest: RSP,
-        src: R15,
+        src: Operand::Offset(Register::RSP, 0),
     }]
 }
@@ -34,27 +34,36 @@
 //! +----------------------+
 //! ```
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 
-use super::{expr::compile_expr, state::Compiler, types::assert_closure, variable::VariablesTable};
+use super::{
+    error::STACK_OVERFLOW_ERR_LABEL, expr::compile_expr, state::Compiler, types::assert_closure,
+    variable::VariablesTable,
+};
 use crate::{
     a86::ast::{Operand, Register, Statement},
     mylang::{
-        ast::{App, Expr, FunctionDefinition, Identifier, If, Lambda, Let, Match, Program},
+        ast::{App, Case, Expr, FunctionDefinition, Identifier, If, Lambda, Let, Match, Program},
         data_type::CLOSURE_TYPE,
+        error::CompileError,
     },
 };
 
 const RAX: Operand = Operand::Register(Register::RAX);
 const RBX: Operand = Operand::Register(Register::RBX);
+const RSP: Operand = Operand::Register(Register::RSP);
 const R8: Operand = Operand::Register(Register::R8);
+const R12: Operand = Operand::Register(Register::R12);
 
-pub fn compile_defines(program: &Program, compiler: &mut Compiler) -> Vec<Statement> {
-    program
-        .function_definitions
-        .iter()
-        .flat_map(|f| compile_define(f.clone(), compiler))
-        .collect()
+pub fn compile_defines(
+    program: &Program,
+    compiler: &mut Compiler,
+) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = vec![];
+    for f in &program.function_definitions {
+        statements.extend(compile_define(f.clone(), compiler)?);
+    }
+    Ok(statements)
 }
 
 pub fn defined_ids(program: &Program) -> Vec<Identifier> {
@@ -68,12 +77,15 @@ pub fn defined_ids(program: &Program) -> Vec<Identifier> {
 fn compile_define(
     function_definition: FunctionDefinition,
     compiler: &mut Compiler,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
     compile_lambda_definition(function_definition.into(), compiler)
 }
 
 /// Returns instructions initializing closures for all functions declared by the `define` keyword.
-pub fn compile_closures_for_defines(program: &Program) -> Vec<Statement> {
+///
+/// Functions capturing nothing use their static closures, so only the others are allocated in the heap.
+/// Every closure is still pushed to the stack, since the defined functions are looked up from there.
+pub fn compile_closures_for_defines(program: &Program) -> Result<Vec<Statement>, CompileError> {
     let definitions = &program.function_definitions;
 
     // We have to create closure values in the following two steps,
@@ -81,19 +93,21 @@ pub fn compile_closures_for_defines(program: &Program) -> Vec<Statement> {
     // We need to know the addresses of all closures before we can initialize them.
     let mut statements = allocate_closures(definitions);
     let env = VariablesTable::new().extended(defined_ids(program));
-    statements.extend(init_closures(definitions, &env));
+    statements.extend(init_closures(definitions, &env)?);
 
-    // Adavance the heap pointer.
+    // Adavance the heap pointer, unless every closure is static.
     let size: usize = definitions
         .iter()
         .map(|f| allocated_heap_size(&f.clone().into()))
         .sum();
-    statements.push(Statement::Add {
-        dest: RBX,
-        src: Operand::Immediate(size as i64),
-    });
+    if size > 0 {
+        statements.push(Statement::Add {
+            dest: RBX,
+            src: Operand::Immediate(size as i64),
+        });
+    }
 
-    statements
+    Ok(statements)
 }
 
 /// Returns instructions allocating heap memory for closures, but not capturing free variables yet.
@@ -105,25 +119,33 @@ fn allocate_closures(definitions: &Vec<FunctionDefinition>) -> Vec<Statement> {
     for definition in definitions {
         let lambda: Lambda = definition.clone().into();
 
-        // Write the address of the label to the heap.
-        statements.push(Statement::Lea {
-            dest: RAX,
-            label: get_label(&lambda),
-        });
-        statements.push(Statement::Mov {
-            dest: Operand::Offset(Register::RBX, offset),
-            src: RAX,
-        });
+        if is_static_closure(&lambda) {
+            statements.push(Statement::Lea {
+                dest: RAX,
+                label: get_static_closure_label(&lambda),
+            });
+        } else {
+            // Write the address of the label to the heap.
+            statements.push(Statement::Lea {
+                dest: RAX,
+                label: get_label(&lambda),
+            });
+            statements.push(Statement::Mov {
+                dest: Operand::Offset(Register::RBX, offset),
+                src: RAX,
+            });
+
+            statements.push(Statement::Mov {
+                dest: RAX,
+                src: RBX,
+            });
+            statements.push(Statement::Add {
+                dest: RAX,
+                src: Operand::Immediate(offset as i64),
+            });
+        }
 
         // Push the address of the closure to the stack.
-        statements.push(Statement::Mov {
-            dest: RAX,
-            src: RBX,
-        });
-        statements.push(Statement::Add {
-            dest: RAX,
-            src: Operand::Immediate(offset as i64),
-        });
         statements.push(Statement::Or {
             dest: RAX,
             src: Operand::Immediate(CLOSURE_TYPE.tag.0 as i64),
@@ -136,7 +158,10 @@ fn allocate_closures(definitions: &Vec<FunctionDefinition>) -> Vec<Statement> {
     statements
 }
 
-fn init_closures(definitions: &Vec<FunctionDefinition>, env: &VariablesTable) -> Vec<Statement> {
+fn init_closures(
+    definitions: &Vec<FunctionDefinition>,
+    env: &VariablesTable,
+) -> Result<Vec<Statement>, CompileError> {
     let mut statements = vec![];
 
     let mut offset = 8;
@@ -144,28 +169,35 @@ fn init_closures(definitions: &Vec<FunctionDefinition>, env: &VariablesTable) ->
         let lambda: Lambda = definition.clone().into();
         let free_vars = free_variables(&Expr::Lambda(lambda.clone()));
 
-        statements.extend(capture_variables(&free_vars, env, offset));
+        statements.extend(capture_variables(&free_vars, env, offset)?);
 
         offset += allocated_heap_size(&lambda);
     }
 
-    statements
+    Ok(statements)
 }
 
 /// Returns instructions defining all lambda expressions in the program.
 ///
 /// It should be put after the main program and invoked by [compile_function_application].
-pub fn compile_lambda_definitions(program: &Program, compiler: &mut Compiler) -> Vec<Statement> {
-    all_lambdas(program)
-        .into_iter()
-        .flat_map(|lambda| compile_lambda_definition(lambda, compiler))
-        .collect()
+pub fn compile_lambda_definitions(
+    program: &Program,
+    compiler: &mut Compiler,
+) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = vec![];
+    for lambda in all_lambdas(program) {
+        statements.extend(compile_lambda_definition(lambda, compiler)?);
+    }
+    Ok(statements)
 }
 
 /// Returns labeled instructions defining the lambda expression.
 ///
 /// It assumes the caller has pushed the return address, closure pointer and arguments to the stack.
-pub fn compile_lambda_definition(lambda: Lambda, compiler: &mut Compiler) -> Vec<Statement> {
+pub fn compile_lambda_definition(
+    lambda: Lambda,
+    compiler: &mut Compiler,
+) -> Result<Vec<Statement>, CompileError> {
     let params = lambda.params.clone();
     let body = lambda.body.clone();
 
@@ -173,6 +205,15 @@ pub fn compile_lambda_definition(lambda: Lambda, compiler: &mut Compiler) -> Vec
         Statement::Label {
             name: get_label(&lambda),
         },
+        // Raise an error rather than crashing when the recursion is too deep.
+        // The addresses are compared as unsigned integers.
+        Statement::Cmp {
+            dest: RSP,
+            src: R12,
+        },
+        Statement::Jb {
+            label: STACK_OVERFLOW_ERR_LABEL.to_string(),
+        },
         // Set rax to the closure pointer.
         Statement::Mov {
             dest: RAX,
@@ -219,7 +260,7 @@ pub fn compile_lambda_definition(lambda: Lambda, compiler: &mut Compiler) -> Vec
         .with_non_var() // Corresponding to the closure pointer.
         .extended(params)
         .extended(free_vars);
-    statements.extend(compile_expr(*body, compiler, &env, true));
+    statements.extend(compile_expr(*body, compiler, &env, true)?);
 
     // Clear the stack.
     statements.push(Statement::Add {
@@ -229,11 +270,25 @@ pub fn compile_lambda_definition(lambda: Lambda, compiler: &mut Compiler) -> Vec
 
     statements.push(Statement::Ret);
 
-    statements
+    Ok(statements)
 }
 
 /// Returns instructions which create a closure data and set rax to its tagged pointer.
-pub fn compile_closure(lambda: Lambda, env: &VariablesTable) -> Vec<Statement> {
+pub fn compile_closure(lambda: Lambda, env: &VariablesTable) -> Result<Vec<Statement>, CompileError> {
+    // A closure capturing nothing is shared by all instances, so just point to the static one.
+    if is_static_closure(&lambda) {
+        return Ok(vec![
+            Statement::Lea {
+                dest: RAX,
+                label: get_static_closure_label(&lambda),
+            },
+            Statement::Or {
+                dest: RAX,
+                src: Operand::Immediate(CLOSURE_TYPE.tag.0 as i64),
+            },
+        ]);
+    }
+
     // A closure is a fixed-size sequence of values in the heap.
 
     // The first value is the address to the label of the lambda expression.
@@ -250,7 +305,7 @@ pub fn compile_closure(lambda: Lambda, env: &VariablesTable) -> Vec<Statement> {
 
     // The rest of the sequence captures free variables in the current environment.
     let free_vars = free_variables(&Expr::Lambda(lambda.clone()));
-    statements.extend(capture_variables(&free_vars, env, 8));
+    statements.extend(capture_variables(&free_vars, env, 8)?);
 
     // Set rax to the tagged pointer.
     statements.push(Statement::Mov {
@@ -268,15 +323,15 @@ pub fn compile_closure(lambda: Lambda, env: &VariablesTable) -> Vec<Statement> {
         src: Operand::Immediate(8 * (free_vars.len() + 1) as i64),
     });
 
-    statements
+    Ok(statements)
 }
 
 /// Returns instructions which copy the given variables to the memory area starting from [rbx + offset].
 fn capture_variables(
-    variables: &HashSet<Identifier>,
+    variables: &BTreeSet<Identifier>,
     env: &VariablesTable,
     offset: usize,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
     let mut statements = vec![];
 
     for (i, variable) in variables.iter().enumerate() {
@@ -284,7 +339,7 @@ fn capture_variables(
         let lexical_address = env
             .position(variable)
             .map(|pos| 8 * pos as i64)
-            .expect(format!("Undefined variable `{}`", variable.0).as_str()); // TODO: Return `Result` type.
+            .ok_or_else(|| CompileError::UndefinedVariable(variable.0.clone()))?;
 
         statements.push(Statement::Mov {
             dest: R8,
@@ -296,15 +351,48 @@ fn capture_variables(
         });
     }
 
-    statements
+    Ok(statements)
 }
 
 /// Returns the size of the heap allocated for closure.
 fn allocated_heap_size(lambda: &Lambda) -> usize {
+    if is_static_closure(lambda) {
+        return 0;
+    }
     // 1 word for the label address, and 1 word for each free variable.
     8 + 8 * free_variables(&Expr::Lambda(lambda.clone())).len()
 }
 
+/// Returns true if the closure of the lambda is put in the data section rather than the heap.
+///
+/// A closure with no free variables holds nothing but the label address,
+/// so a single static object can be shared by all of its instances.
+fn is_static_closure(lambda: &Lambda) -> bool {
+    free_variables(&Expr::Lambda(lambda.clone())).is_empty()
+}
+
+/// Returns pseudo-instructions declaring the static closures of all functions and lambda expressions
+/// which capture nothing. They should be put in the data section.
+pub fn compile_static_closures(program: &Program) -> Vec<Statement> {
+    let defines = program
+        .function_definitions
+        .iter()
+        .map(|f| -> Lambda { f.clone().into() });
+
+    let mut statements = vec![];
+    for lambda in defines.chain(all_lambdas(program)) {
+        if is_static_closure(&lambda) {
+            statements.push(Statement::Label {
+                name: get_static_closure_label(&lambda),
+            });
+            statements.push(Statement::DqLabel {
+                label: get_label(&lambda),
+            });
+        }
+    }
+    statements
+}
+
 /// Returns instrcutions to invoke a function defined by
 /// [compile_function_definition] or [compile_lambda_definition].
 pub fn compile_function_application(
@@ -312,8 +400,8 @@ pub fn compile_function_application(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
-    if is_tail_expr {
+) -> Result<Vec<Statement>, CompileError> {
+    if is_tail_expr && compiler.options().tail_calls {
         compile_function_application_tail(function_application, compiler, env)
     } else {
         compile_function_application_non_tail(function_application, compiler, env)
@@ -324,10 +412,10 @@ fn compile_function_application_non_tail(
     function_application: App,
     compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
     let return_label = format!("function_return_site_{}", compiler.new_label_id());
 
-    let App { function, args } = function_application;
+    let App { function, args, .. } = function_application;
     let n_args = args.len() as i64;
 
     // If you used the `call` instruction, the return address is put at the top of the stack,
@@ -343,14 +431,14 @@ fn compile_function_application_non_tail(
     let mut env = env.with_non_var();
 
     // Push the function to the stack.
-    statements.extend(compile_expr(*function, compiler, &env, false));
+    statements.extend(compile_expr(*function, compiler, &env, false)?);
     statements.extend(assert_closure(Register::RAX));
     statements.push(Statement::Push { src: RAX });
 
     // And then push the arguments to the stack.
     for arg in args {
         env = env.with_non_var();
-        statements.extend(compile_expr(arg, compiler, &env, false));
+        statements.extend(compile_expr(arg, compiler, &env, false)?);
         statements.push(Statement::Push { src: RAX });
     }
 
@@ -371,7 +459,7 @@ fn compile_function_application_non_tail(
     statements.push(Statement::JmpRegister(Register::RAX));
     statements.push(Statement::Label { name: return_label });
 
-    statements
+    Ok(statements)
 }
 
 /// Returns optimized instructions for tail calls.
@@ -379,15 +467,15 @@ fn compile_function_application_tail(
     function_application: App,
     compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
-    let App { function, args } = function_application;
+) -> Result<Vec<Statement>, CompileError> {
+    let App { function, args, .. } = function_application;
     let n_args = args.len();
     let n_env = env.len();
 
     let mut statements = vec![];
 
     // Push the function to the stack.
-    statements.extend(compile_expr(*function, compiler, &env, false));
+    statements.extend(compile_expr(*function, compiler, &env, false)?);
     statements.extend(assert_closure(Register::RAX));
     statements.push(Statement::Push { src: RAX });
 
@@ -395,7 +483,7 @@ fn compile_function_application_tail(
     let mut new_env = env.clone();
     for arg in args {
         new_env = new_env.with_non_var();
-        statements.extend(compile_expr(arg, compiler, &new_env, false));
+        statements.extend(compile_expr(arg, compiler, &new_env, false)?);
         statements.push(Statement::Push { src: RAX });
     }
 
@@ -454,7 +542,7 @@ fn compile_function_application_tail(
 
     statements.push(Statement::JmpRegister(Register::RAX));
 
-    statements
+    Ok(statements)
 }
 
 /// Moves [n_args] top elements on the stack up by [offset] words.
@@ -485,7 +573,29 @@ fn move_args(n_args: usize, offset: usize) -> Vec<Statement> {
 }
 
 fn get_label(lambda: &Lambda) -> String {
-    format!("lambda_{}", lambda.id.0)
+    format!("lambda_{}", mangle(&lambda.id.0))
+}
+
+fn get_static_closure_label(lambda: &Lambda) -> String {
+    format!("closure_{}", mangle(&lambda.id.0))
+}
+
+/// Encodes an identifier into a string which is valid as an assembly label.
+///
+/// Identifiers may contain characters like `-`, `>` or `!` which cannot appear in labels.
+/// Alphanumeric characters are kept as they are, `_` is escaped as `__`,
+/// and any other character is escaped as its hexadecimal codepoint surrounded by `_`,
+/// so that distinct identifiers never collide, e.g. `set-box!` becomes `set_2d_box_21_`.
+fn mangle(identifier: &str) -> String {
+    let mut result = String::new();
+    for c in identifier.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => result.push(c),
+            '_' => result.push_str("__"),
+            _ => result.push_str(&format!("_{:x}_", c as u32)),
+        }
+    }
+    result
 }
 
 /// Returns the list of all lambda expressions in a program.
@@ -502,13 +612,13 @@ fn all_lambdas(program: &Program) -> Vec<Lambda> {
 fn all_lambdas_in_expr(expr: &Expr) -> Vec<Lambda> {
     match expr {
         Expr::Variable(_) => vec![],
-        Expr::Prim1(_, e) => all_lambdas_in_expr(e),
-        Expr::Prim2(_, e1, e2) => {
+        Expr::Prim1(_, e, _) => all_lambdas_in_expr(e),
+        Expr::Prim2(_, e1, e2, _) => {
             let mut result = all_lambdas_in_expr(e1);
             result.extend(all_lambdas_in_expr(e2));
             result
         }
-        Expr::Prim3(_, e1, e2, e3) => {
+        Expr::Prim3(_, e1, e2, e3, _) => {
             let mut result = all_lambdas_in_expr(e1);
             result.extend(all_lambdas_in_expr(e2));
             result.extend(all_lambdas_in_expr(e3));
@@ -519,7 +629,7 @@ fn all_lambdas_in_expr(expr: &Expr) -> Vec<Lambda> {
             result.extend(all_lambdas_in_expr(e2));
             result
         }
-        Expr::App(App { function, args }) => {
+        Expr::App(App { function, args, .. }) => {
             let mut result = all_lambdas_in_expr(function);
             for arg in args {
                 result.extend(all_lambdas_in_expr(arg));
@@ -535,32 +645,51 @@ fn all_lambdas_in_expr(expr: &Expr) -> Vec<Lambda> {
         Expr::Match(Match { expr, arms }) => {
             let mut result = all_lambdas_in_expr(expr);
             for arm in arms {
+                for (_, test) in arm.pattern.predicates() {
+                    result.extend(all_lambdas_in_expr(test));
+                }
                 result.extend(all_lambdas_in_expr(&arm.body));
             }
             result
         }
+        Expr::Case(Case { expr, clauses, els }) => {
+            let mut result = all_lambdas_in_expr(expr);
+            for clause in clauses {
+                result.extend(all_lambdas_in_expr(&clause.body));
+            }
+            result.extend(all_lambdas_in_expr(els));
+            result
+        }
         Expr::Let(Let { binding, body }) => {
             let mut result = all_lambdas_in_expr(&binding.rhs);
             result.extend(all_lambdas_in_expr(&body));
             result
         }
-        Expr::Lambda(lambda) => vec![lambda.clone()],
+        Expr::Lambda(lambda) => {
+            // Lambdas nested in the body need to be defined as well.
+            let mut result = vec![lambda.clone()];
+            result.extend(all_lambdas_in_expr(&lambda.body));
+            result
+        }
 
-        Expr::Lit(_) | Expr::Eof | Expr::Prim0(_) => vec![],
+        Expr::Lit(_) | Expr::Eof | Expr::Prim0(..) => vec![],
     }
 }
 
 /// Returns the set of all free variables in the expression.
-fn free_variables(expr: &Expr) -> HashSet<Identifier> {
+///
+/// The set is ordered, so that a closure captures the variables in the same order
+/// as the body of the lambda expression reads them, and the output is reproducible.
+fn free_variables(expr: &Expr) -> BTreeSet<Identifier> {
     match expr {
-        Expr::Variable(id) => HashSet::from([id.clone()]),
-        Expr::Prim1(_, e) => free_variables(e),
-        Expr::Prim2(_, e1, e2) => {
+        Expr::Variable(id) => BTreeSet::from([id.clone()]),
+        Expr::Prim1(_, e, _) => free_variables(e),
+        Expr::Prim2(_, e1, e2, _) => {
             let mut result = free_variables(e1);
             result.extend(free_variables(e2));
             result
         }
-        Expr::Prim3(_, e1, e2, e3) => {
+        Expr::Prim3(_, e1, e2, e3, _) => {
             let mut result = free_variables(e1);
             result.extend(free_variables(e2));
             result.extend(free_variables(e3));
@@ -571,7 +700,7 @@ fn free_variables(expr: &Expr) -> HashSet<Identifier> {
             result.extend(free_variables(e2));
             result
         }
-        Expr::App(App { function, args }) => {
+        Expr::App(App { function, args, .. }) => {
             let mut result = free_variables(function);
             for arg in args {
                 result.extend(free_variables(arg));
@@ -587,10 +716,27 @@ fn free_variables(expr: &Expr) -> HashSet<Identifier> {
         Expr::Match(Match { expr, arms }) => {
             let mut result = free_variables(expr);
             for arm in arms {
-                result.extend(free_variables(&arm.body));
+                for (variable, test) in arm.pattern.predicates() {
+                    let mut test_variables = free_variables(test);
+                    test_variables.remove(variable);
+                    result.extend(test_variables);
+                }
+                let mut body_variables = free_variables(&arm.body);
+                for variable in arm.pattern.variables() {
+                    body_variables.remove(&variable);
+                }
+                result.extend(body_variables);
             }
             result
         }
+        Expr::Case(Case { expr, clauses, els }) => {
+            let mut result = free_variables(expr);
+            for clause in clauses {
+                result.extend(free_variables(&clause.body));
+            }
+            result.extend(free_variables(els));
+            result
+        }
         Expr::Let(Let { binding, body }) => {
             let mut result = free_variables(&binding.rhs);
             result.extend(free_variables(&body));
@@ -609,6 +755,27 @@ fn free_variables(expr: &Expr) -> HashSet<Identifier> {
             result
         }
 
-        Expr::Lit(_) | Expr::Eof | Expr::Prim0(_) => HashSet::new(),
+        Expr::Lit(_) | Expr::Eof | Expr::Prim0(..) => BTreeSet::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mangle_identifiers() {
+        assert_eq!(mangle("foo1"), "foo1");
+        assert_eq!(mangle("set-box!"), "set_2d_box_21_");
+        assert_eq!(mangle("string->number"), "string_2d__3e_number");
+        assert_ne!(mangle("a-b"), mangle("a_2d_b"));
+        assert_ne!(mangle("a_b"), mangle("a__b"));
+    }
+
+    #[test]
+    fn free_variables_are_sorted() {
+        let program = crate::mylang::parse("(lambda (y) (+ z (+ a (+ y (+ m b)))))").unwrap();
+        let free_vars: Vec<String> = free_variables(&program.expr).into_iter().map(|id| id.0).collect();
+        assert_eq!(free_vars, vec!["a", "b", "m", "z"]);
     }
 }
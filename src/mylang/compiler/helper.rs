@@ -37,3 +37,19 @@ pub fn if_less_than() -> Vec<Statement> {
         Statement::Cmovl { dest: RAX, src: R9 },
     ]
 }
+
+/// Returns instructions which sets rax to true if the comparison flag is greater.
+/// This clobbers r9.
+pub fn if_greater() -> Vec<Statement> {
+    vec![
+        Statement::Mov {
+            dest: RAX,
+            src: Operand::Immediate(Value::Boolean(false).encode()),
+        },
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Immediate(Value::Boolean(true).encode()),
+        },
+        Statement::Cmovg { dest: RAX, src: R9 },
+    ]
+}
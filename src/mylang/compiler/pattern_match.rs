@@ -3,11 +3,13 @@ use crate::{
     mylang::{
         ast::{self, Identifier},
         data_type::{Value, BOX_TYPE, CONS_TYPE},
+        error::CompileError,
     },
 };
 
 use super::{
-    error::ERR_LABEL, expr::compile_expr, state::Compiler, string::compare_strings,
+    error::ERR_LABEL,
+    expr::{compile_expr, int_value}, state::Compiler, string::compare_strings,
     variable::VariablesTable,
 };
 
@@ -21,10 +23,14 @@ pub fn compile_match(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
-    let mut statements = compile_expr(*match_expr.expr, compiler, env, false);
+) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = compile_expr(*match_expr.expr, compiler, env, false)?;
     let done_label = format!("done_{}", compiler.new_label_id());
 
+    // Stash the value to match on the stack,
+    // since the pattern matching of each arm overwrites rax with the parts of the value.
+    statements.push(Statement::Push { src: RAX });
+
     for arm in match_expr.arms {
         statements.extend(compile_match_arm(
             arm,
@@ -32,7 +38,7 @@ pub fn compile_match(
             env,
             is_tail_expr,
             &done_label,
-        ));
+        )?);
     }
 
     // In case of no match, raise an error.
@@ -42,7 +48,7 @@ pub fn compile_match(
 
     statements.push(Statement::Label { name: done_label });
 
-    statements
+    Ok(statements)
 }
 
 fn compile_match_arm(
@@ -51,16 +57,27 @@ fn compile_match_arm(
     env: &VariablesTable,
     is_tail_expr: bool,
     done_label: &str,
-) -> Vec<Statement> {
-    let next_label = format!("next_{}", compiler.new_label_id());
-    let CompiledPattern {
-        mut statements,
-        env: bound_env,
-    } = compile_pattern(arm.pattern, compiler, env, &next_label);
+) -> Result<Vec<Statement>, CompileError> {
+    let scrutinee_env = env.with_non_var();
+    let next = NextArm {
+        label: format!("next_{}", compiler.new_label_id()),
+        env_len: scrutinee_env.len(),
+    };
+
+    // Restore the value to match from the stash.
+    let mut statements = vec![Statement::Mov {
+        dest: RAX,
+        src: Operand::Offset(Register::RSP, 0),
+    }];
+
+    let pattern = compile_pattern(arm.pattern, compiler, &scrutinee_env, &next)?;
+    statements.extend(pattern.statements);
+    let bound_env = pattern.env;
 
-    statements.extend(compile_expr(*arm.body, compiler, &bound_env, is_tail_expr));
+    statements.extend(compile_expr(*arm.body, compiler, &bound_env, is_tail_expr)?);
 
-    // Clear the variables bound in the match scope before finishing.
+    // Clear the variables bound in the match scope, as well as the stashed value, before finishing.
+    // A tail call in the body never returns here, since it clears them along with the rest of `bound_env`.
     statements.push(Statement::Add {
         dest: RSP,
         src: Operand::Immediate(8 * (bound_env.len() - env.len()) as i64),
@@ -70,10 +87,33 @@ fn compile_match_arm(
     });
 
     statements.push(Statement::Label {
-        name: next_label.to_string(),
+        name: next.label,
     });
 
-    statements
+    Ok(statements)
+}
+
+/// Where to jump when the pattern of an arm does not match.
+struct NextArm {
+    label: String,
+
+    /// The length of the environment outside the arm.
+    /// The variables pushed by the pattern matching beyond this must be cleared before jumping.
+    env_len: usize,
+}
+
+/// Returns instructions which clear the variables pushed by the pattern matching so far,
+/// and jump to the next arm.
+fn jump_to_next_arm(env: &VariablesTable, next: &NextArm) -> Vec<Statement> {
+    vec![
+        Statement::Add {
+            dest: RSP,
+            src: Operand::Immediate(8 * (env.len() - next.env_len) as i64),
+        },
+        Statement::Jmp {
+            label: next.label.clone(),
+        },
+    ]
 }
 
 struct CompiledPattern {
@@ -95,17 +135,20 @@ fn compile_pattern(
     pattern: ast::Pattern,
     compiler: &mut Compiler,
     env: &VariablesTable,
-    next_label: &str,
-) -> CompiledPattern {
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
     match pattern {
-        ast::Pattern::Wildcard => compile_wildcard_pattern(env),
-        ast::Pattern::Variable(identifier) => compile_variable_pattern(&identifier, env),
-        ast::Pattern::Lit(lit) => compile_literal_pattern(lit, compiler, env, next_label),
+        ast::Pattern::Wildcard => Ok(compile_wildcard_pattern(env)),
+        ast::Pattern::Variable(identifier) => Ok(compile_variable_pattern(&identifier, env)),
+        ast::Pattern::Lit(lit) => compile_literal_pattern(lit, compiler, env, next),
 
-        ast::Pattern::Cons(car, cdr) => compile_cons_pattern(*car, *cdr, compiler, env, next_label),
-        ast::Pattern::Box(pattern) => compile_box_pattern(*pattern, compiler, env, next_label),
+        ast::Pattern::Cons(car, cdr) => compile_cons_pattern(*car, *cdr, compiler, env, next),
+        ast::Pattern::Box(pattern) => compile_box_pattern(*pattern, compiler, env, next),
         ast::Pattern::And(left, right) => {
-            compile_and_pattern(*left, *right, compiler, env, next_label)
+            compile_and_pattern(*left, *right, compiler, env, next)
+        }
+        ast::Pattern::Predicate(variable, test) => {
+            compile_predicate_pattern(&variable, *test, compiler, env, next)
         }
     }
 }
@@ -124,56 +167,51 @@ fn compile_literal_pattern(
     lit: ast::Lit,
     compiler: &mut Compiler,
     env: &VariablesTable,
-    next_label: &str,
-) -> CompiledPattern {
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
     let matched_label = format!("matched_{}", compiler.new_label_id());
 
-    let mut statements = compare_literal(lit, compiler);
+    let mut statements = compare_literal(lit, compiler)?;
     statements.push(Statement::Je {
         label: matched_label.clone(),
     });
 
     // No match, clear the stack and jump to the next pattern.
-    statements.push(Statement::Add {
-        dest: RSP,
-        src: Operand::Immediate(8 * env.len() as i64),
-    });
-    statements.push(Statement::Jmp {
-        label: next_label.to_string(),
-    });
+    statements.extend(jump_to_next_arm(env, next));
 
     // Matched
     statements.push(Statement::Label {
         name: matched_label.clone(),
     });
 
-    CompiledPattern::new(statements, env.clone())
+    Ok(CompiledPattern::new(statements, env.clone()))
 }
 
-/// Returns instructions which sets the comparison flag to true iff
-/// value in rax equals to the given literal.
-fn compare_literal(lit: ast::Lit, compiler: &mut Compiler) -> Vec<Statement> {
+/// Returns instructions which set the comparison flag to true iff the value in rax equals to the literal.
+pub fn compare_literal(lit: ast::Lit, compiler: &mut Compiler) -> Result<Vec<Statement>, CompileError> {
     fn cmp(operand: Operand) -> Vec<Statement> {
         vec![Statement::Cmp {
             dest: RAX,
             src: operand,
         }]
     }
-    match lit {
-        ast::Lit::Int(i) => cmp(Operand::from(Value::Int(i))),
+    let statements = match lit {
+        ast::Lit::Int(i) => cmp(Operand::from(int_value(i)?)),
         ast::Lit::Bool(b) => cmp(Operand::from(Value::Boolean(b))),
         ast::Lit::Char(c) => cmp(Operand::from(Value::Char(c))),
         ast::Lit::EmptyList => cmp(Operand::from(Value::EmptyList)),
+        ast::Lit::Void => cmp(Operand::from(Value::Void)),
         ast::Lit::String(s) => compare_strings(&s, compiler),
-    }
+    };
+    Ok(statements)
 }
 
 fn compile_box_pattern(
     pattern: ast::Pattern,
     compiler: &mut Compiler,
     env: &VariablesTable,
-    next_label: &str,
-) -> CompiledPattern {
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
     let matched_label = format!("matched_{}", compiler.new_label_id());
 
     let mut statements = vec![
@@ -193,13 +231,7 @@ fn compile_box_pattern(
     ];
 
     // No match, clear the stack and jump to the next pattern.
-    statements.push(Statement::Add {
-        dest: RSP,
-        src: Operand::Immediate(8 * env.len() as i64),
-    });
-    statements.push(Statement::Jmp {
-        label: next_label.to_string(),
-    });
+    statements.extend(jump_to_next_arm(env, next));
 
     // Matched, unbox it for subpattern matching.
     statements.push(Statement::Label {
@@ -214,10 +246,10 @@ fn compile_box_pattern(
         src: Operand::Offset(Register::RAX, 0),
     });
 
-    let subpattern = compile_pattern(pattern, compiler, env, next_label);
+    let subpattern = compile_pattern(pattern, compiler, env, next)?;
     statements.extend(subpattern.statements);
 
-    CompiledPattern::new(statements, subpattern.env)
+    Ok(CompiledPattern::new(statements, subpattern.env))
 }
 
 fn compile_cons_pattern(
@@ -225,8 +257,8 @@ fn compile_cons_pattern(
     cdr: ast::Pattern,
     compiler: &mut Compiler,
     env: &VariablesTable,
-    next_label: &str,
-) -> CompiledPattern {
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
     let matched_label = format!("matched_{}", compiler.new_label_id());
 
     let mut statements = vec![
@@ -246,13 +278,7 @@ fn compile_cons_pattern(
     ];
 
     // No match, clear the stack and jump to the next pattern.
-    statements.push(Statement::Add {
-        dest: RSP,
-        src: Operand::Immediate(8 * env.len() as i64),
-    });
-    statements.push(Statement::Jmp {
-        label: next_label.to_string(),
-    });
+    statements.extend(jump_to_next_arm(env, next));
 
     // Matched, cast it to the raw pointer address.
     statements.push(Statement::Label {
@@ -276,7 +302,7 @@ fn compile_cons_pattern(
         dest: RAX,
         src: Operand::Offset(Register::RAX, 8),
     });
-    let car_result = compile_pattern(car, compiler, env, next_label);
+    let car_result = compile_pattern(car, compiler, env, next)?;
     statements.extend(car_result.statements);
 
     // Check if cdr matches the pattern.
@@ -285,10 +311,45 @@ fn compile_cons_pattern(
         // Make sure to account for the newly pushed variables by the car pattern matching.
         src: Operand::Offset(Register::RSP, 8 * (car_result.env.len() - env.len()) as i64),
     });
-    let cdr_result = compile_pattern(cdr, compiler, &car_result.env, next_label);
+    let cdr_result = compile_pattern(cdr, compiler, &car_result.env, next)?;
     statements.extend(cdr_result.statements);
 
-    CompiledPattern::new(statements, cdr_result.env)
+    Ok(CompiledPattern::new(statements, cdr_result.env))
+}
+
+fn compile_predicate_pattern(
+    variable: &Identifier,
+    test: ast::Expr,
+    compiler: &mut Compiler,
+    env: &VariablesTable,
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
+    let matched_label = format!("matched_{}", compiler.new_label_id());
+
+    // Bind the value in rax to the variable for the test.
+    let mut statements = vec![Statement::Push { src: RAX }];
+
+    // The test is in the scope outside the pattern, so the variables bound so far are hidden.
+    let test_env = env.with_vars_hidden_after(next.env_len).with_var(variable);
+    statements.extend(compile_expr(test, compiler, &test_env, false)?);
+    statements.push(Statement::Cmp {
+        dest: RAX,
+        src: Operand::from(Value::Boolean(false)),
+    });
+    statements.push(Statement::Jne {
+        label: matched_label.clone(),
+    });
+
+    // No match, clear the stack and jump to the next pattern.
+    statements.extend(jump_to_next_arm(&env.with_non_var(), next));
+
+    // Matched, restore the value in rax.
+    statements.push(Statement::Label {
+        name: matched_label,
+    });
+    statements.push(Statement::Pop { dest: RAX });
+
+    Ok(CompiledPattern::new(statements, env.clone()))
 }
 
 fn compile_and_pattern(
@@ -296,8 +357,8 @@ fn compile_and_pattern(
     right: ast::Pattern,
     compiler: &mut Compiler,
     env: &VariablesTable,
-    next_label: &str,
-) -> CompiledPattern {
+    next: &NextArm,
+) -> Result<CompiledPattern, CompileError> {
     // Stash the value in rax to the stack.
     let mut statements = vec![
         Statement::Push { src: RAX },
@@ -305,7 +366,7 @@ fn compile_and_pattern(
     let env = &env.with_non_var();
 
     // Check if the left pattern matches.
-    let left_result = compile_pattern(left, compiler, env, next_label);
+    let left_result = compile_pattern(left, compiler, env, next)?;
     statements.extend(left_result.statements);
     
     // Check if the right pattern matches.
@@ -314,8 +375,8 @@ fn compile_and_pattern(
         // Make sure to account for the newly pushed variables by the left pattern matching.
         src: Operand::Offset(Register::RSP, 8 * (left_result.env.len() - env.len()) as i64),
     });
-    let right_result = compile_pattern(right, compiler, &left_result.env, next_label);
+    let right_result = compile_pattern(right, compiler, &left_result.env, next)?;
     statements.extend(right_result.statements);
 
-    CompiledPattern::new(statements, right_result.env)
+    Ok(CompiledPattern::new(statements, right_result.env))
 }
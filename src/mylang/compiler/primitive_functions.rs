@@ -1,11 +1,15 @@
 use crate::a86::ast::*;
 use crate::mylang::ast;
-use crate::mylang::data_type::{BOX_TYPE, CHAR_TYPE, CONS_TYPE, STRING_TYPE, VECTOR_TYPE};
+use crate::mylang::data_type::{
+    Value, BOX_TYPE, CHAR_TYPE, CONS_TYPE, STRING_TYPE, VECTOR_TYPE,
+};
+use crate::mylang::error::CompileError;
 
 use super::arithmetic::*;
 use super::box_type::*;
 use super::cons::*;
 use super::expr::compile_expr;
+use super::error::ASSERTION_ERR_LABEL;
 use super::external_call::*;
 use super::helper::if_equal;
 use super::state::Compiler;
@@ -20,7 +24,12 @@ const R8: Operand = Operand::Register(Register::R8);
 pub fn compile_prim0(op: ast::Op0) -> Vec<Statement> {
     match op {
         ast::Op0::ReadByte => compile_read_byte(),
-        ast::Op0::PeekByte => compile_peek_byte(),
+        ast::Op0::Newline => compile_newline(),
+        ast::Op0::FlushOutput => compile_flush_output(),
+        ast::Op0::EofObject => vec![Statement::Mov {
+            dest: RAX,
+            src: Operand::from(Value::Eof),
+        }],
     }
 }
 
@@ -29,10 +38,10 @@ pub fn compile_prim1(
     expr: ast::Expr,
     compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
-    let mut statements = compile_expr(expr, compiler, env, false);
-    statements.extend(compile_op1(op));
-    statements
+) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = compile_expr(expr, compiler, env, false)?;
+    statements.extend(compile_op1(op, compiler));
+    Ok(statements)
 }
 
 pub fn compile_prim2(
@@ -41,17 +50,26 @@ pub fn compile_prim2(
     second: ast::Expr,
     compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
-    let mut statements = compile_expr(first, compiler, env, false);
+) -> Result<Vec<Statement>, CompileError> {
+    // Dividing by a literal power of two needs neither the divisor in a register nor `idiv`.
+    if let (ast::Op2::Modulo, ast::Expr::Lit(ast::Lit::Int(divisor))) = (&op, &second) {
+        if let Some(modulo) = compile_modulo_by_power_of_two(*divisor) {
+            let mut statements = compile_expr(first, compiler, env, false)?;
+            statements.extend(modulo);
+            return Ok(statements);
+        }
+    }
+
+    let mut statements = compile_expr(first, compiler, env, false)?;
     statements.push(Statement::Push {
         src: Operand::Register(Register::RAX),
     });
-    statements.extend(compile_expr(second, compiler, &env.with_non_var(), false));
+    statements.extend(compile_expr(second, compiler, &env.with_non_var(), false)?);
     statements.push(Statement::Pop {
         dest: Operand::Register(Register::R8),
     });
     statements.extend(compile_op2(op, compiler));
-    statements
+    Ok(statements)
 }
 
 pub fn compile_prim3(
@@ -61,33 +79,39 @@ pub fn compile_prim3(
     third: ast::Expr,
     compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
-    let mut statements = compile_expr(first, compiler, env, false);
+) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = compile_expr(first, compiler, env, false)?;
 
     statements.push(Statement::Push {
         src: Operand::Register(Register::RAX),
     });
     let env = &env.with_non_var();
 
-    statements.extend(compile_expr(second, compiler, env, false));
+    statements.extend(compile_expr(second, compiler, env, false)?);
 
     statements.push(Statement::Push {
         src: Operand::Register(Register::RAX),
     });
     let env = &env.with_non_var();
 
-    statements.extend(compile_expr(third, compiler, env, false));
+    statements.extend(compile_expr(third, compiler, env, false)?);
     statements.extend(compile_op3(op, compiler));
-    statements
+    Ok(statements)
 }
 
 /// Returns instructions which apply the given unary operator to the value in rax.
-fn compile_op1(op: ast::Op1) -> Vec<Statement> {
+fn compile_op1(op: ast::Op1, compiler: &mut Compiler) -> Vec<Statement> {
     match op {
+        ast::Op1::Identity => vec![],
+
         ast::Op1::Add1 => compile_add1(),
         ast::Op1::Sub1 => compile_sub1(),
+        ast::Op1::Negate => compile_negate(),
+        ast::Op1::BitNot => compile_bit_not(),
 
         ast::Op1::IsZero => compile_is_zero(),
+        ast::Op1::IsPositive => compile_is_positive(),
+        ast::Op1::IsNegative => compile_is_negative(),
 
         ast::Op1::IsChar => is_type(&CHAR_TYPE),
         ast::Op1::IsBox => is_type(&BOX_TYPE),
@@ -98,15 +122,28 @@ fn compile_op1(op: ast::Op1) -> Vec<Statement> {
         ast::Op1::IsEof => is_eof(),
 
         ast::Op1::CharToInt => char_to_int(),
-        ast::Op1::IntToChar => int_to_char(),
+        ast::Op1::IntToChar => int_to_char(compiler),
+        ast::Op1::CharNext => shift_char(1, compiler),
+        ast::Op1::CharPrev => shift_char(-1, compiler),
 
+        ast::Op1::PeekByte => compile_peek_byte(),
         ast::Op1::WriteByte => compile_write_byte(),
+        ast::Op1::WriteString => compile_write_string(),
+        ast::Op1::Display => compile_display(),
+        ast::Op1::Assert => compile_assert(),
+        ast::Op1::Error => compile_user_error(),
 
         ast::Op1::Box => compile_box(),
-        ast::Op1::Unbox => compile_unbox(),
+        ast::Op1::Unbox => compile_unbox(compiler),
+
+        ast::Op1::Car => compile_car(compiler),
+        ast::Op1::Cdr => compile_cdr(compiler),
 
-        ast::Op1::Car => compile_car(),
-        ast::Op1::Cdr => compile_cdr(),
+        ast::Op1::StringCopy => compile_string_copy(compiler),
+        ast::Op1::StringLength => compile_string_length(compiler),
+        ast::Op1::StringUtf8Length => compile_string_utf8_length(compiler),
+        ast::Op1::StringUpcase => compile_string_upcase(compiler),
+        ast::Op1::StringDowncase => compile_string_downcase(compiler),
     }
 }
 
@@ -116,18 +153,28 @@ fn compile_op2(op: ast::Op2, compiler: &mut Compiler) -> Vec<Statement> {
     match op {
         ast::Op2::Add => compile_add(),
         ast::Op2::Sub => compile_sub(),
+        ast::Op2::Mul => compile_mul(),
+        ast::Op2::Modulo => compile_modulo(compiler),
         ast::Op2::IntEq => compile_int_equal(),
         ast::Op2::LessThan => compile_less_than(),
+        ast::Op2::CharEq => compile_char_equal(),
+        ast::Op2::CharLessThan => compile_char_less_than(),
+        ast::Op2::CharGreaterThan => compile_char_greater_than(),
         
         ast::Op2::Eq => compile_eq(),
 
         ast::Op2::Cons => compile_cons(),
+        ast::Op2::Assoc => compile_assoc(),
+        ast::Op2::Assq => compile_assq(),
+        ast::Op2::Member => compile_member(),
+        ast::Op2::Memq => compile_memq(),
 
         ast::Op2::MakeVector => compile_make_vector(compiler),
         ast::Op2::VectorRef => compile_vector_ref(compiler),
 
         ast::Op2::MakeString => compile_make_string(compiler),
         ast::Op2::StringRef => compile_string_ref(compiler),
+        ast::Op2::StringEq => compile_string_equal(compiler),
     }
 }
 
@@ -139,9 +186,28 @@ fn compile_op2(op: ast::Op2, compiler: &mut Compiler) -> Vec<Statement> {
 fn compile_op3(op: ast::Op3, compiler: &mut Compiler) -> Vec<Statement> {
     match op {
         ast::Op3::VectorSet => compile_vector_set(compiler),
+        ast::Op3::StringSet => compile_string_set(compiler),
+        ast::Op3::Substring => compile_substring(compiler),
     }
 }
 
+/// Returns instructions which raise an assertion error if rax is false, and set rax to void otherwise.
+fn compile_assert() -> Vec<Statement> {
+    vec![
+        Statement::Cmp {
+            dest: RAX,
+            src: Operand::from(Value::Boolean(false)),
+        },
+        Statement::Je {
+            label: ASSERTION_ERR_LABEL.to_string(),
+        },
+        Statement::Mov {
+            dest: RAX,
+            src: Operand::from(Value::Void),
+        },
+    ]
+}
+
 fn compile_eq() -> Vec<Statement> {
     let mut statements = vec![];
     statements.push(Statement::Cmp { dest: RAX, src: R8 });
@@ -1,13 +1,27 @@
 use std::collections::{HashMap, HashSet};
 
+use crate::a86::ast::Statement;
+use crate::mylang::document::Position;
+
+use super::CompileOptions;
+
 pub struct Compiler {
     last_label_id: usize,
     string_literals: HashMap<String, Label>,
+    options: CompileOptions,
+    /// The labels put before the code of the expressions, with their positions in the source code.
+    /// `None` unless the source map is requested.
+    source_labels: Option<Vec<(String, Position)>>,
+    /// The jump tables of the `case` expressions, which are put in the data section.
+    jump_tables: Vec<Statement>,
 }
 
 impl Compiler {
-    pub fn new(string_literals: HashSet<String>) -> Compiler {
+    pub fn new(string_literals: HashSet<String>, options: CompileOptions) -> Compiler {
         Compiler {
+            options,
+            source_labels: None,
+            jump_tables: vec![],
             last_label_id: string_literals.len(),
             string_literals: string_literals
                 .into_iter()
@@ -29,6 +43,41 @@ impl Compiler {
     pub fn string_literals(&self) -> &HashMap<String, Label> {
         &self.string_literals
     }
+
+    pub fn options(&self) -> &CompileOptions {
+        &self.options
+    }
+
+    /// Makes [Compiler::new_source_label] put labels, so that the source map can be built from them.
+    pub fn enable_source_labels(&mut self) {
+        self.source_labels = Some(vec![]);
+    }
+
+    /// Returns a new label to put before the code of an expression at the position,
+    /// or `None` if the position is unknown or the source map is not requested.
+    ///
+    /// The labels are numbered apart from the others, so that they do not change the rest of the code.
+    pub fn new_source_label(&mut self, position: Option<&Position>) -> Option<String> {
+        let source_labels = self.source_labels.as_mut()?;
+        let label = format!("source_{}", source_labels.len());
+        source_labels.push((label.clone(), position?.clone()));
+        Some(label)
+    }
+
+    pub fn source_labels(&self) -> &[(String, Position)] {
+        self.source_labels.as_deref().unwrap_or_default()
+    }
+
+    /// Declares a table of the addresses of the labels under the given label.
+    pub fn add_jump_table(&mut self, label: String, targets: Vec<String>) {
+        self.jump_tables.push(Statement::Label { name: label });
+        self.jump_tables
+            .extend(targets.into_iter().map(|label| Statement::DqLabel { label }));
+    }
+
+    pub fn jump_tables(&self) -> &[Statement] {
+        &self.jump_tables
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -1,9 +1,13 @@
-use crate::a86::ast::Statement;
+use crate::{a86::ast::Statement, mylang::ast::Program};
 
-use super::{state::Compiler, string::compile_all_string_data};
+use super::{
+    function::compile_static_closures, state::Compiler, string::compile_all_string_data,
+};
 
-pub fn compile_data_section(compiler: &Compiler) -> Vec<Statement> {
+pub fn compile_data_section(program: &Program, compiler: &Compiler) -> Vec<Statement> {
     let mut statements = vec![Statement::Data];
+    statements.extend(compile_static_closures(program));
     statements.extend(compile_all_string_data(compiler));
+    statements.extend(compiler.jump_tables().iter().cloned());
     statements
 }
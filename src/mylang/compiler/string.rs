@@ -1,15 +1,42 @@
-use std::collections::HashSet;
+//! # String layout
+//!
+//! A string is a pointer tagged with the string type, pointing to its length followed by the characters.
+//! Each character is stored as its 32-bit code point without the type tag,
+//! and an odd-length string is padded with 4 bytes to keep the heap aligned to words.
+//!
+//! ```plaintext
+//! +----------------------+
+//! |       length         | <-- pointer
+//! +----------+-----------+
+//! |   c[0]   |   c[1]    |
+//! +----------+-----------+
+//! |   c[2]   | (padding) |
+//! +----------+-----------+
+//! ```
+//!
+//! The empty string is the tag alone without any address, so it takes up no heap memory.
+//!
+//! ## Why not UTF-8
+//!
+//! Storing the characters in UTF-8 would take up a quarter of the space for ASCII text,
+//! and the runtime could write them out as they are.
+//! But then `string-ref` and `string-set!` would have to decode the bytes from the beginning to find the `n`-th character,
+//! and `string-set!` might even have to move the following bytes if the new character has a different width.
+//! With the fixed width code points, both are constant time, and the length is also the number of characters.
+//! The number of bytes in UTF-8 is available through `string-utf8-length` instead.
+
+use std::{collections::HashSet, ops::RangeInclusive};
 
 use crate::{
     a86::ast::{Operand, Register, Statement},
     mylang::{
-        ast::{App, Expr, If, Lambda, Let, Lit, Match, Program},
+        ast::{App, Case, Expr, If, Lambda, Let, Lit, Match, Program},
         data_type::*,
     },
 };
 
 use super::{
-    error::ERR_LABEL,
+    error::BOUNDS_ERR_LABEL,
     state::{Compiler, Label},
     types::*,
 };
@@ -20,6 +47,8 @@ const EAX: Operand = Operand::Register(Register::EAX);
 const R8: Operand = Operand::Register(Register::R8);
 const R9: Operand = Operand::Register(Register::R9);
 const R9D: Operand = Operand::Register(Register::R9D);
+const R10: Operand = Operand::Register(Register::R10);
+const RDX: Operand = Operand::Register(Register::RDX);
 
 /// Returns instructions to initialize a string of the given length with the repeated values,
 /// assuming the length and the value is already given in r8 and rax respectively.
@@ -100,9 +129,9 @@ pub fn compile_make_string(compiler: &mut Compiler) -> Vec<Statement> {
         dest: R8,
         src: Operand::Immediate(1),
     });
-    statements.push(Statement::Cmp {
+    statements.push(Statement::Test {
         dest: R8,
-        src: Operand::Immediate(0),
+        src: R8,
     });
     statements.push(Statement::Jne {
         label: loop_label.clone(),
@@ -131,66 +160,633 @@ pub fn compile_make_string(compiler: &mut Compiler) -> Vec<Statement> {
 
 /// Returns instructions which sets rax to the character in the string at the given index,
 /// assuming the string and the index is already given in r8 and rax respectively.
-pub fn compile_string_ref(_compiler: &mut Compiler) -> Vec<Statement> {
+pub fn compile_string_ref(compiler: &mut Compiler) -> Vec<Statement> {
     let mut statements = assert_string(Register::R8);
-    statements.extend(assert_natural_number(Register::RAX));
+    statements.extend(assert_index(Register::RAX));
+
+    // Cast rax to raw integer representing the index.
+    statements.push(Statement::Sar {
+        dest: RAX,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+
+    statements.extend(string_length(compiler));
+    statements.extend(assert_in_bounds(Register::RAX, Register::R9));
+
+    // Get the element at the given index.
+    // Each element takes up 4 bytes, and the offset 8 is required as the first word is the length.
+    statements.push(Statement::Mov {
+        dest: EAX,
+        src: Operand::ScaledIndex {
+            base: Register::R8,
+            index: Register::RAX,
+            scale: 4,
+            disp: 8,
+        },
+    });
+
+    // Cast rax to the character type.
+    statements.push(Statement::Sal {
+        dest: RAX,
+        src: Operand::Immediate(CHAR_TYPE.shift as i64),
+    });
+    statements.push(Statement::Xor {
+        dest: RAX,
+        src: Operand::Immediate(CHAR_TYPE.tag.0 as i64),
+    });
+
+    statements
+}
+
+/// Returns instructions which mutates the character in the given string at the given index,
+/// which are assumed to be laid out as follows:
+/// * 1st argument (string): Second of the stack
+/// * 2nd argument (index): Topmost of the stack
+/// * 3rd argument (new character): rax
+///
+/// This clobbers r8, r9, r10, rsi, and rdx.
+pub fn compile_string_set(compiler: &mut Compiler) -> Vec<Statement> {
+    // Move the arguments to the registers.
+    // 1st argument (string): r8
+    // 2nd argument (index): r10
+    // 3rd argument (new character): rax
+    let mut statements = vec![
+        Statement::Pop {
+            dest: Operand::Register(Register::R10),
+        },
+        Statement::Pop {
+            dest: Operand::Register(Register::R8),
+        },
+    ];
+    statements.extend(assert_string(Register::R8));
+    statements.extend(assert_index(Register::R10));
+    statements.extend(assert_char(Register::RAX));
+
+    // Cast r10 to raw integer representing the index.
+    statements.push(Statement::Sar {
+        dest: R10,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+
+    statements.extend(string_length(compiler));
+    statements.extend(assert_in_bounds(Register::R10, Register::R9));
+
+    // String literals are put in the static data, which must not be mutated.
+    statements.extend(assert_in_heap(Register::R8));
+
+    // Set the new character without the type tag.
+    statements.push(Statement::Sar {
+        dest: RAX,
+        src: Operand::Immediate(CHAR_TYPE.shift as i64),
+    });
+    statements.push(Statement::Sal {
+        dest: R10,
+        src: Operand::Immediate(2), // Each element takes up 2^2 bytes.
+    });
+    statements.push(Statement::Add { dest: R8, src: R10 });
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::R8, 8), // The offset 8 is required as the first word is the length.
+        src: EAX,
+    });
+
+    // Return void.
+    statements.push(Statement::Mov {
+        dest: RAX,
+        src: Operand::from(Value::Void),
+    });
+    statements
+}
+
+/// Returns instructions which sets rax to the length of the string in rax.
+///
+/// This clobbers r8 and r9.
+pub fn compile_string_length(compiler: &mut Compiler) -> Vec<Statement> {
+    let mut statements = assert_string(Register::RAX);
+    statements.push(Statement::Mov { dest: R8, src: RAX });
+    statements.extend(string_length(compiler));
+    statements.push(Statement::Mov { dest: RAX, src: R9 });
+    statements.push(Statement::Sal {
+        dest: RAX,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+    statements
+}
+
+/// Returns instructions which sets rax to the number of bytes to encode the string in rax in UTF-8.
+///
+/// This clobbers r8, r9, and r10.
+pub fn compile_string_utf8_length(compiler: &mut Compiler) -> Vec<Statement> {
+    let loop_label = format!("loop_{}", compiler.new_label_id());
+    let next_label = format!("next_{}", compiler.new_label_id());
+    let end_label = format!("end_{}", compiler.new_label_id());
+
+    let mut statements = assert_string(Register::RAX);
+    statements.push(Statement::Mov { dest: R8, src: RAX });
+    statements.extend(string_length(compiler));
+    statements.push(Statement::Mov { dest: R10, src: R9 });
+    statements.push(Statement::Mov {
+        dest: RAX,
+        src: Operand::Immediate(0),
+    });
+
+    // Add the width of each character, with r10 counting down the rest.
+    statements.push(Statement::Label {
+        name: loop_label.clone(),
+    });
+    statements.push(Statement::Test {
+        dest: R10,
+        src: R10,
+    });
+    statements.push(Statement::Je {
+        label: end_label.clone(),
+    });
+    statements.push(Statement::Mov {
+        dest: R9D,
+        src: Operand::Offset(Register::R8, 8), // The offset 8 is required as the first word is the length.
+    });
+    statements.push(Statement::Add {
+        dest: RAX,
+        src: Operand::Immediate(1),
+    });
+    // Each of these bounds takes one more byte to encode.
+    for bound in [0x80, 0x800, 0x10000] {
+        statements.push(Statement::Cmp {
+            dest: R9,
+            src: Operand::Immediate(bound),
+        });
+        statements.push(Statement::Jl {
+            label: next_label.clone(),
+        });
+        statements.push(Statement::Add {
+            dest: RAX,
+            src: Operand::Immediate(1),
+        });
+    }
+    statements.push(Statement::Label { name: next_label });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(4), // 4 bytes per character
+    });
+    statements.push(Statement::Sub {
+        dest: R10,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Jmp { label: loop_label });
+
+    statements.push(Statement::Label { name: end_label });
+    statements.push(Statement::Sal {
+        dest: RAX,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+    statements
+}
+
+/// Returns instructions which sets rax to a new string with the characters in the given range of a string,
+/// which are assumed to be laid out as follows:
+/// * 1st argument (string): Second of the stack
+/// * 2nd argument (start index): Topmost of the stack
+/// * 3rd argument (end index): rax
+///
+/// This clobbers r8, r9, r10, rsi, and rdx.
+pub fn compile_substring(compiler: &mut Compiler) -> Vec<Statement> {
+    let loop_label = format!("loop_{}", compiler.new_label_id());
+    let empty_label = format!("empty_{}", compiler.new_label_id());
+    let end_label = format!("end_{}", compiler.new_label_id());
+
+    // Move the arguments to the registers.
+    // 1st argument (string): r8
+    // 2nd argument (start index): r10
+    // 3rd argument (end index): rax
+    let mut statements = vec![
+        Statement::Pop {
+            dest: Operand::Register(Register::R10),
+        },
+        Statement::Pop {
+            dest: Operand::Register(Register::R8),
+        },
+    ];
+    statements.extend(assert_string(Register::R8));
+    statements.extend(assert_index(Register::R10));
+    statements.extend(assert_index(Register::RAX));
+
+    // Cast the indices to raw integers.
+    statements.push(Statement::Sar {
+        dest: R10,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+    statements.push(Statement::Sar {
+        dest: RAX,
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+
+    // Make sure `end <= length`, and then `start <= end`.
+    // The error reports the offending index along with the length of the string.
+    statements.extend(string_length(compiler));
+    statements.push(Statement::Mov {
+        dest: Operand::Register(Register::RDX),
+        src: R9,
+    });
+    for (index, limit) in [(RAX, R9), (R10, RAX)] {
+        statements.push(Statement::Mov {
+            dest: Operand::Register(Register::RSI),
+            src: index.clone(),
+        });
+        statements.push(Statement::Cmp {
+            dest: index,
+            src: limit,
+        });
+        statements.push(Statement::Jg {
+            label: BOUNDS_ERR_LABEL.to_string(),
+        });
+    }
+
+    // Set rax to the length of the new string.
+    statements.push(Statement::Sub { dest: RAX, src: R10 });
+    statements.push(Statement::Test {
+        dest: RAX,
+        src: RAX,
+    });
+    statements.push(Statement::Je {
+        label: empty_label.clone(),
+    });
+
+    // Advance r8 by the start index, so that the first character to copy is at the offset 8.
+    statements.push(Statement::Sal {
+        dest: R10,
+        src: Operand::Immediate(2), // Each element takes up 2^2 bytes.
+    });
+    statements.push(Statement::Add { dest: R8, src: R10 });
+
+    // Put the length at the top of the heap, and set rax to the new string.
+    statements.push(Statement::Mov { dest: R10, src: RAX });
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::RBX, 0),
+        src: RAX,
+    });
+    statements.push(Statement::Mov { dest: RAX, src: RBX });
+    statements.push(Statement::Or {
+        dest: RAX,
+        src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
+    });
+    statements.push(Statement::Add {
+        dest: RBX,
+        src: Operand::Immediate(8),
+    });
+
+    // Copy the characters one by one, with r10 counting down the rest.
+    statements.push(Statement::Label {
+        name: loop_label.clone(),
+    });
+    statements.push(Statement::Mov {
+        dest: R9D,
+        src: Operand::Offset(Register::R8, 8),
+    });
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::RBX, 0),
+        src: R9D,
+    });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(4),
+    });
+    statements.push(Statement::Add {
+        dest: RBX,
+        src: Operand::Immediate(4), // 4 bytes per character
+    });
+    statements.push(Statement::Sub {
+        dest: R10,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Test {
+        dest: R10,
+        src: R10,
+    });
+    statements.push(Statement::Jne { label: loop_label });
+
+    // Pad the string to a whole word if the length is odd, keeping the heap aligned.
+    statements.push(Statement::Add {
+        dest: RBX,
+        src: Operand::Immediate(7),
+    });
+    statements.push(Statement::And {
+        dest: RBX,
+        src: Operand::Immediate(-8),
+    });
+    statements.push(Statement::Jmp {
+        label: end_label.clone(),
+    });
 
     // Special case for empty string
+    statements.push(Statement::Label { name: empty_label });
+    statements.push(Statement::Mov {
+        dest: RAX,
+        src: Operand::from(Value::EmptyString),
+    });
+
+    statements.push(Statement::Label { name: end_label });
+    statements
+}
+
+/// Returns instructions which sets rax to a new string with the same characters as the string in rax.
+///
+/// This clobbers r8, r9, and r10.
+pub fn compile_string_copy(compiler: &mut Compiler) -> Vec<Statement> {
+    let loop_label = format!("loop_{}", compiler.new_label_id());
+    let end_label = format!("end_{}", compiler.new_label_id());
+
+    let mut statements = assert_string(Register::RAX);
+
+    // The empty string has no address, so it is returned as it is.
     statements.push(Statement::Cmp {
-        dest: R8,
+        dest: RAX,
         src: Operand::from(Value::EmptyString),
     });
     statements.push(Statement::Je {
-        label: ERR_LABEL.to_string(),
+        label: end_label.clone(),
     });
 
-    // Cast r8 to the raw pointer address of the string.
+    // Set r8 to the raw pointer address of the original string.
+    statements.push(Statement::Mov { dest: R8, src: RAX });
     statements.push(Statement::Xor {
         dest: R8,
         src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
     });
-    // Set r9 to the length of the string.
+
+    // The copy starts at the top of the heap.
+    statements.push(Statement::Mov { dest: RAX, src: RBX });
+    statements.push(Statement::Or {
+        dest: RAX,
+        src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
+    });
+
+    // Copy the length.
     statements.push(Statement::Mov {
         dest: R9,
         src: Operand::Offset(Register::R8, 0),
     });
-    // Cast rax to raw integer representing the index.
-    statements.push(Statement::Sar {
-        dest: RAX,
-        src: Operand::Immediate(INT_TYPE.shift as i64),
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::RBX, 0),
+        src: R9,
+    });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(8),
+    });
+    statements.push(Statement::Add {
+        dest: RBX,
+        src: Operand::Immediate(8),
     });
 
-    // Check if the index is out of bounds. (length - 1 < index)
+    // Copy the characters a word at a time, i.e. two characters at once.
+    // An odd-length string is padded to a whole word, so set r9 to the number of words rounding up.
+    statements.push(Statement::Add {
+        dest: R9,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Sar {
+        dest: R9,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Label {
+        name: loop_label.clone(),
+    });
+    statements.push(Statement::Mov {
+        dest: R10,
+        src: Operand::Offset(Register::R8, 0),
+    });
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::RBX, 0),
+        src: R10,
+    });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(8),
+    });
+    statements.push(Statement::Add {
+        dest: RBX,
+        src: Operand::Immediate(8),
+    });
     statements.push(Statement::Sub {
         dest: R9,
         src: Operand::Immediate(1),
     });
-    statements.push(Statement::Cmp { dest: R9, src: RAX });
-    statements.push(Statement::Jl {
-        label: ERR_LABEL.to_string(),
+    statements.push(Statement::Test {
+        dest: R9,
+        src: R9,
     });
+    statements.push(Statement::Jne { label: loop_label });
 
-    // Get the element at the given index.
-    statements.push(Statement::Sal {
+    statements.push(Statement::Label { name: end_label });
+    statements
+}
+
+/// Returns instructions which sets rax to a new string with the ASCII lowercase letters
+/// in the string in rax converted to uppercase.
+///
+/// This clobbers r8, r9, and r10.
+pub fn compile_string_upcase(compiler: &mut Compiler) -> Vec<Statement> {
+    convert_case('a'..='z', -32, compiler)
+}
+
+/// Returns instructions which sets rax to a new string with the ASCII uppercase letters
+/// in the string in rax converted to lowercase.
+///
+/// This clobbers r8, r9, and r10.
+pub fn compile_string_downcase(compiler: &mut Compiler) -> Vec<Statement> {
+    convert_case('A'..='Z', 32, compiler)
+}
+
+/// Returns instructions which copy the string in rax, and then shift the code points of
+/// the characters of the copy within the given range by the given amount.
+fn convert_case(
+    range: RangeInclusive<char>,
+    amount: i64,
+    compiler: &mut Compiler,
+) -> Vec<Statement> {
+    let loop_label = format!("loop_{}", compiler.new_label_id());
+    let next_label = format!("next_{}", compiler.new_label_id());
+    let end_label = format!("end_{}", compiler.new_label_id());
+
+    let mut statements = compile_string_copy(compiler);
+
+    // The empty string has no characters to convert.
+    statements.push(Statement::Cmp {
         dest: RAX,
-        src: Operand::Immediate(2), // Each element takes up 2^2 bytes.
+        src: Operand::from(Value::EmptyString),
+    });
+    statements.push(Statement::Je {
+        label: end_label.clone(),
+    });
+
+    // Set r8 to the raw pointer address of the copy, and r10 to its length.
+    statements.push(Statement::Mov { dest: R8, src: RAX });
+    statements.push(Statement::Xor {
+        dest: R8,
+        src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
     });
-    statements.push(Statement::Add { dest: R8, src: RAX });
     statements.push(Statement::Mov {
-        dest: EAX,
+        dest: R10,
+        src: Operand::Offset(Register::R8, 0),
+    });
+
+    // Convert each character in place, which is stored without the type tag.
+    statements.push(Statement::Label {
+        name: loop_label.clone(),
+    });
+    statements.push(Statement::Mov {
+        dest: R9D,
         src: Operand::Offset(Register::R8, 8), // The offset 8 is required as the first word is the length.
     });
+    statements.push(Statement::Cmp {
+        dest: R9,
+        src: Operand::Immediate(*range.start() as i64),
+    });
+    statements.push(Statement::Jl {
+        label: next_label.clone(),
+    });
+    statements.push(Statement::Cmp {
+        dest: R9,
+        src: Operand::Immediate(*range.end() as i64),
+    });
+    statements.push(Statement::Jg {
+        label: next_label.clone(),
+    });
+    statements.push(Statement::Add {
+        dest: R9,
+        src: Operand::Immediate(amount),
+    });
+    statements.push(Statement::Mov {
+        dest: Operand::Offset(Register::R8, 8),
+        src: R9D,
+    });
+    statements.push(Statement::Label { name: next_label });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(4), // 4 bytes per character
+    });
+    statements.push(Statement::Sub {
+        dest: R10,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Test {
+        dest: R10,
+        src: R10,
+    });
+    statements.push(Statement::Jne { label: loop_label });
 
-    // Cast rax to the character type.
-    statements.push(Statement::Sal {
+    statements.push(Statement::Label { name: end_label });
+    statements
+}
+
+/// Returns instructions which set r9 to the length of the string in r8,
+/// and cast r8 to the raw pointer address of the string.
+///
+/// The empty string has the length 0 and no address.
+fn string_length(compiler: &mut Compiler) -> Vec<Statement> {
+    let end_label = format!("string_length_{}", compiler.new_label_id());
+
+    vec![
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Immediate(0),
+        },
+        // Special case for empty string
+        Statement::Cmp {
+            dest: R8,
+            src: Operand::from(Value::EmptyString),
+        },
+        Statement::Je {
+            label: end_label.clone(),
+        },
+        // Cast r8 to the raw pointer address of the string.
+        Statement::Xor {
+            dest: R8,
+            src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
+        },
+        // Set r9 to the length of the string.
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Offset(Register::R8, 0),
+        },
+        Statement::Label { name: end_label },
+    ]
+}
+
+/// Returns instructions which sets rax to true if the strings in r8 and rax have the same characters.
+///
+/// This clobbers r8, r9, r10, and rdx.
+pub fn compile_string_equal(compiler: &mut Compiler) -> Vec<Statement> {
+    let loop_label = format!("loop_{}", compiler.new_label_id());
+    let equal_label = format!("string_eq_{}", compiler.new_label_id());
+    let end_label = format!("end_{}", compiler.new_label_id());
+
+    let mut statements = assert_string(Register::RAX);
+    statements.extend(assert_string(Register::R8));
+
+    // Set rdx to the length of the second string, and r10 to its raw pointer address.
+    statements.push(Statement::Mov { dest: R10, src: R8 });
+    statements.push(Statement::Mov { dest: R8, src: RAX });
+    statements.extend(string_length(compiler));
+    statements.push(Statement::Mov { dest: RDX, src: R9 });
+    statements.push(Statement::Mov { dest: RAX, src: R8 });
+    statements.push(Statement::Mov { dest: R8, src: R10 });
+    statements.push(Statement::Mov { dest: R10, src: RAX });
+
+    // Set r9 to the length of the first string, and r8 to its raw pointer address.
+    statements.extend(string_length(compiler));
+
+    statements.push(Statement::Mov {
         dest: RAX,
-        src: Operand::Immediate(CHAR_TYPE.shift as i64),
+        src: Operand::from(Value::Boolean(false)),
     });
-    statements.push(Statement::Xor {
+    statements.push(Statement::Cmp { dest: R9, src: RDX });
+    statements.push(Statement::Jne {
+        label: end_label.clone(),
+    });
+
+    // Compare each character, with rdx counting down the rest.
+    statements.push(Statement::Label {
+        name: loop_label.clone(),
+    });
+    statements.push(Statement::Test {
+        dest: RDX,
+        src: RDX,
+    });
+    statements.push(Statement::Je {
+        label: equal_label.clone(),
+    });
+    statements.push(Statement::Mov {
+        dest: R9D,
+        src: Operand::Offset(Register::R8, 8), // The offset 8 is required as the first word is the length.
+    });
+    statements.push(Statement::Cmp {
+        dest: R9D,
+        src: Operand::Offset(Register::R10, 8),
+    });
+    statements.push(Statement::Jne {
+        label: end_label.clone(),
+    });
+    statements.push(Statement::Add {
+        dest: R8,
+        src: Operand::Immediate(4), // 4 bytes per character
+    });
+    statements.push(Statement::Add {
+        dest: R10,
+        src: Operand::Immediate(4),
+    });
+    statements.push(Statement::Sub {
+        dest: RDX,
+        src: Operand::Immediate(1),
+    });
+    statements.push(Statement::Jmp { label: loop_label });
+
+    statements.push(Statement::Label { name: equal_label });
+    statements.push(Statement::Mov {
         dest: RAX,
-        src: Operand::Immediate(CHAR_TYPE.tag.0 as i64),
+        src: Operand::from(Value::Boolean(true)),
     });
 
+    statements.push(Statement::Label { name: end_label });
     statements
 }
 
@@ -198,6 +794,7 @@ pub fn compile_string_ref(_compiler: &mut Compiler) -> Vec<Statement> {
 /// value in rax equals to the given static string.
 pub fn compare_strings(string: &str, compiler: &mut Compiler) -> Vec<Statement> {
     let neq_label = format!("string_neq_{}", compiler.new_label_id());
+    let length_label = format!("string_length_{}", compiler.new_label_id());
     let jne = Statement::Jne {
         label: neq_label.clone(),
     };
@@ -214,6 +811,18 @@ pub fn compare_strings(string: &str, compiler: &mut Compiler) -> Vec<Statement>
             src: Operand::Immediate(STRING_TYPE.tag.0 as i64),
         },
         jne.clone(),
+        // The empty string has the length 0 and no address.
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Immediate(0),
+        },
+        Statement::Cmp {
+            dest: RAX,
+            src: Operand::from(Value::EmptyString),
+        },
+        Statement::Je {
+            label: length_label.clone(),
+        },
         // Set r8 to the raw pointer address of the string.
         Statement::Mov { dest: R8, src: RAX },
         Statement::Xor {
@@ -225,11 +834,13 @@ pub fn compare_strings(string: &str, compiler: &mut Compiler) -> Vec<Statement>
             dest: R9,
             src: Operand::Offset(Register::R8, 0),
         },
-        // Compare the length.
+        // Compare the length, so that the characters are only read within the bounds.
+        Statement::Label { name: length_label },
         Statement::Cmp {
             dest: R9,
-            src: Operand::Immediate(string.len() as i64),
+            src: Operand::Immediate(string.chars().count() as i64),
         },
+        jne.clone(),
     ];
 
     // Compare each character.
@@ -252,6 +863,14 @@ pub fn compare_strings(string: &str, compiler: &mut Compiler) -> Vec<Statement>
 
 /// Returns instructions which sets rax to the given string literal.
 pub fn compile_string_literal(string: &str, compiler: &Compiler) -> Vec<Statement> {
+    // The empty string is represented without an address, as the one made at runtime is.
+    if string.is_empty() {
+        return vec![Statement::Mov {
+            dest: RAX,
+            src: Operand::from(Value::EmptyString),
+        }];
+    }
+
     let Label(label) = compiler
         .string_literal_label(string)
         .expect(format!("String literal '{}' not found in the table", string).as_str());
@@ -264,6 +883,12 @@ pub fn compile_string_literal(string: &str, compiler: &Compiler) -> Vec<Statemen
 }
 
 /// Returns pseudo-instructions declaring static data for all string literals in the program.
+///
+/// Only equal literals share their data.
+/// A literal cannot point into the data of a longer one it is a prefix or suffix of,
+/// since the length word must come right before the characters:
+/// the prefix would need another length at the same address,
+/// and the 8 bytes before a suffix are the characters of the longer one, or its length.
 pub fn compile_all_string_data(compiler: &Compiler) -> Vec<Statement> {
     let mut statements = vec![];
     for (string, label) in compiler.string_literals() {
@@ -278,10 +903,17 @@ pub fn compile_all_string_data(compiler: &Compiler) -> Vec<Statement> {
 /// Returns pseudo-instructions declaring compile-time static string data,
 /// which should be put in the data section.
 fn compile_string_data(string: &str) -> Vec<Statement> {
+    let length = string.chars().count();
     let mut statements = vec![Statement::Dq {
-        value: string.len() as i64,
+        value: length as i64,
     }];
     statements.extend(string.chars().map(|c| Statement::Dd { value: c as i32 }));
+
+    // Pad an odd-length string with extra 4 bytes like in the heap,
+    // so that the following data stays aligned to 8 bytes.
+    if length % 2 == 1 {
+        statements.push(Statement::Dd { value: 0 });
+    }
     statements
 }
 
@@ -303,13 +935,13 @@ fn string_literals(expr: &Expr) -> HashSet<String> {
         }
 
         // Just recurse into subexpressions.
-        Expr::Prim1(_, e) => string_literals(e),
-        Expr::Prim2(_, e1, e2) => {
+        Expr::Prim1(_, e, _) => string_literals(e),
+        Expr::Prim2(_, e1, e2, _) => {
             let mut result = string_literals(e1);
             result.extend(string_literals(e2));
             result
         }
-        Expr::Prim3(_, e1, e2, e3) => {
+        Expr::Prim3(_, e1, e2, e3, _) => {
             let mut result = string_literals(e1);
             result.extend(string_literals(e2));
             result.extend(string_literals(e3));
@@ -320,7 +952,7 @@ fn string_literals(expr: &Expr) -> HashSet<String> {
             result.extend(string_literals(e2));
             result
         }
-        Expr::App(App { function, args }) => {
+        Expr::App(App { function, args, .. }) => {
             let mut result = string_literals(function);
             for arg in args {
                 result.extend(string_literals(arg));
@@ -336,10 +968,22 @@ fn string_literals(expr: &Expr) -> HashSet<String> {
         Expr::Match(Match { expr, arms }) => {
             let mut result = string_literals(expr);
             for arm in arms {
+                for (_, test) in arm.pattern.predicates() {
+                    result.extend(string_literals(test));
+                }
                 result.extend(string_literals(&arm.body));
             }
             result
         }
+        // The datums are compared character by character, so they need no static data.
+        Expr::Case(Case { expr, clauses, els }) => {
+            let mut result = string_literals(expr);
+            for clause in clauses {
+                result.extend(string_literals(&clause.body));
+            }
+            result.extend(string_literals(els));
+            result
+        }
         Expr::Let(Let { binding, body }) => {
             let mut result = string_literals(&binding.rhs);
             result.extend(string_literals(body));
@@ -351,6 +995,6 @@ fn string_literals(expr: &Expr) -> HashSet<String> {
             body,
         }) => string_literals(body),
 
-        Expr::Lit(_) | Expr::Variable(_) | Expr::Eof | Expr::Prim0(_) => HashSet::new(),
+        Expr::Lit(_) | Expr::Variable(_) | Expr::Eof | Expr::Prim0(..) => HashSet::new(),
     }
 }
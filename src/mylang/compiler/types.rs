@@ -1,28 +1,58 @@
 use crate::{
     a86::ast::{Operand, Register, Statement},
-    mylang::{compiler::error::ERR_LABEL, data_type::*},
+    mylang::{
+        compiler::error::{
+            BOUNDS_ERR_LABEL, CODEPOINT_RANGE_ERR_LABEL, ERR_LABEL, NEGATIVE_INDEX_ERR_LABEL,
+            SURROGATE_ERR_LABEL,
+        },
+        data_type::*,
+    },
 };
 
-use super::helper::if_equal;
+use super::{
+    helper::{if_equal, if_greater, if_less_than},
+    state::Compiler,
+};
 
 const RAX: Operand = Operand::Register(Register::RAX);
+const RBX: Operand = Operand::Register(Register::RBX);
+const R8: Operand = Operand::Register(Register::R8);
 const R9: Operand = Operand::Register(Register::R9);
 
+/// Returns instructions which converts the value in the register from one type to another,
+/// keeping the payload.
+///
+/// The payload is moved with a single shift, and then the leftover bits of the old tag are replaced with the new tag.
 pub fn cast_type(register: Register, from: &UnaryType, to: &UnaryType) -> Vec<Statement> {
-    vec![
-        Statement::Sar {
-            dest: Operand::Register(register.clone()),
-            src: Operand::Immediate(from.shift as i64),
-        },
-        Statement::Sal {
-            dest: Operand::Register(register.clone()),
-            src: Operand::Immediate(to.shift as i64),
-        },
-        Statement::Xor {
-            dest: Operand::Register(register),
-            src: Operand::Immediate(to.tag.0 as i64),
-        },
-    ]
+    let dest = Operand::Register(register);
+    let mut statements = vec![];
+
+    let shifted_tag = if from.shift >= to.shift {
+        let amount = from.shift - to.shift;
+        if amount > 0 {
+            statements.push(Statement::Sar {
+                dest: dest.clone(),
+                src: Operand::Immediate(amount as i64),
+            });
+        }
+        from.tag.0 >> amount
+    } else {
+        let amount = to.shift - from.shift;
+        statements.push(Statement::Sal {
+            dest: dest.clone(),
+            src: Operand::Immediate(amount as i64),
+        });
+        from.tag.0 << amount
+    };
+
+    let tag_diff = shifted_tag ^ to.tag.0;
+    if tag_diff != 0 {
+        statements.push(Statement::Xor {
+            dest,
+            src: Operand::Immediate(tag_diff as i64),
+        });
+    }
+    statements
 }
 
 /// Returns instructions which sets rax to true iff the value in rax is of the given type.
@@ -56,18 +86,80 @@ pub fn char_to_int() -> Vec<Statement> {
     statements
 }
 
-pub fn int_to_char() -> Vec<Statement> {
+pub fn int_to_char(compiler: &mut Compiler) -> Vec<Statement> {
     let mut statements = assert_int(Register::RAX);
-    statements.extend(assert_codepoint());
+    statements.extend(assert_codepoint(compiler));
     statements.extend(cast_type(Register::RAX, &INT_TYPE, &CHAR_TYPE));
     statements
 }
 
+/// Returns instructions which replace the character in rax with the one whose code point is
+/// shifted by the given amount, raising an error if it is not a valid code point.
+pub fn shift_char(amount: i64, compiler: &mut Compiler) -> Vec<Statement> {
+    let mut statements = assert_char(Register::RAX);
+    statements.extend(cast_type(Register::RAX, &CHAR_TYPE, &INT_TYPE));
+    statements.push(Statement::Add {
+        dest: RAX,
+        src: Operand::from(Value::Int(amount)),
+    });
+    statements.extend(assert_codepoint(compiler));
+    statements.extend(cast_type(Register::RAX, &INT_TYPE, &CHAR_TYPE));
+    statements
+}
+
+/// Returns instructions which sets rax to true if r8 and rax are the same character.
+/// It raises an error if rax or r8 is not a character.
+pub fn compile_char_equal() -> Vec<Statement> {
+    let mut statements = compare_chars();
+    statements.extend(if_equal());
+    statements
+}
+
+/// Returns instructions which sets rax to true if the code point of r8 is less than that of rax.
+/// It raises an error if rax or r8 is not a character.
+pub fn compile_char_less_than() -> Vec<Statement> {
+    let mut statements = compare_chars();
+    statements.extend(if_less_than());
+    statements
+}
+
+/// Returns instructions which sets rax to true if the code point of r8 is greater than that of rax.
+/// It raises an error if rax or r8 is not a character.
+pub fn compile_char_greater_than() -> Vec<Statement> {
+    let mut statements = compare_chars();
+    statements.extend(if_greater());
+    statements
+}
+
+/// Returns instructions which compare the characters in r8 and rax by their code points.
+///
+/// Every character has the same tag below the code point,
+/// so the tagged values are ordered in the same way as the code points.
+fn compare_chars() -> Vec<Statement> {
+    let mut statements = assert_char(Register::RAX);
+    statements.extend(assert_char(Register::R8));
+    statements.push(Statement::Cmp { dest: R8, src: RAX });
+    statements
+}
+
 /// Returns instructions which sets raises an error if
 /// the value in the given register is not of the given type.
 ///
 /// This clobbers r9.
 pub fn assert_type(register: Register, type_: &UnaryType) -> Vec<Statement> {
+    // The bits of a type tagged by zero, such as integers, can be tested in place.
+    if type_.tag.0 == 0 {
+        return vec![
+            Statement::Test {
+                dest: Operand::Register(register),
+                src: Operand::Immediate(type_.mask() as i64),
+            },
+            Statement::Jne {
+                label: ERR_LABEL.to_string(),
+            },
+        ];
+    }
+
     vec![
         Statement::Mov {
             dest: R9,
@@ -115,6 +207,52 @@ pub fn assert_closure(register: Register) -> Vec<Statement> {
     assert_type(register, &CLOSURE_TYPE)
 }
 
+/// Returns instructions which raise an error unless the untagged pointer in the register
+/// points to the memory allocated so far, if [CompileOptions::check_heap_pointers](crate::CompileOptions) is enabled.
+/// This clobbers r9.
+pub fn assert_heap_pointer(register: Register, compiler: &Compiler) -> Vec<Statement> {
+    if compiler.options().check_heap_pointers {
+        assert_in_heap(register)
+    } else {
+        vec![]
+    }
+}
+
+/// Returns instructions which raise an error unless the untagged pointer in the register
+/// points to the memory allocated so far, i.e. between the start of the heap and rbx.
+/// It fails for the static data such as string literals.
+/// This clobbers r9.
+pub fn assert_in_heap(register: Register) -> Vec<Statement> {
+    vec![
+        // The runtime stores the start address of the heap in the global variable `heap`.
+        Statement::Lea {
+            dest: R9,
+            label: "heap".to_string(),
+        },
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Offset(Register::R9, 0),
+        },
+        Statement::Cmp {
+            dest: Operand::Register(register.clone()),
+            src: R9,
+        },
+        Statement::Jl {
+            label: ERR_LABEL.to_string(),
+        },
+        Statement::Cmp {
+            dest: Operand::Register(register),
+            src: RBX,
+        },
+        Statement::Jge {
+            label: ERR_LABEL.to_string(),
+        },
+    ]
+}
+
+/// Returns instructions which raise an error unless the value in the given register is a nonnegative integer.
+///
+/// This clobbers r9.
 pub fn assert_natural_number(register: Register) -> Vec<Statement> {
     let mut statements = assert_int(register.clone());
     statements.push(Statement::Cmp {
@@ -127,62 +265,134 @@ pub fn assert_natural_number(register: Register) -> Vec<Statement> {
     statements
 }
 
-fn assert_codepoint() -> Vec<Statement> {
+/// Returns instructions which raise an error unless the value in the given register is a nonnegative integer.
+///
+/// Unlike [assert_natural_number], a negative value is reported as an invalid index.
+/// This clobbers r9 and rsi.
+pub fn assert_index(register: Register) -> Vec<Statement> {
+    let mut statements = assert_int(register.clone());
+    // Pass the raw index to the error handler.
+    statements.push(Statement::Mov {
+        dest: Operand::Register(Register::RSI),
+        src: Operand::Register(register.clone()),
+    });
+    statements.push(Statement::Sar {
+        dest: Operand::Register(Register::RSI),
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+    statements.push(Statement::Cmp {
+        dest: Operand::Register(register),
+        src: Operand::from(Value::Int(0)),
+    });
+    statements.push(Statement::Jl {
+        label: NEGATIVE_INDEX_ERR_LABEL.to_string(),
+    });
+    statements
+}
+
+/// Returns instructions which raise an error unless `index < length`,
+/// where the registers hold the raw integers and the index is known to be nonnegative.
+///
+/// This clobbers the length register, rsi, and rdx.
+pub fn assert_in_bounds(index: Register, length: Register) -> Vec<Statement> {
+    vec![
+        // Pass the index and the length to the error handler.
+        Statement::Mov {
+            dest: Operand::Register(Register::RSI),
+            src: Operand::Register(index.clone()),
+        },
+        Statement::Mov {
+            dest: Operand::Register(Register::RDX),
+            src: Operand::Register(length.clone()),
+        },
+        // Check if the index is out of bounds. (length - 1 < index)
+        Statement::Sub {
+            dest: Operand::Register(length.clone()),
+            src: Operand::Immediate(1),
+        },
+        Statement::Cmp {
+            dest: Operand::Register(length),
+            src: Operand::Register(index),
+        },
+        Statement::Jl {
+            label: BOUNDS_ERR_LABEL.to_string(),
+        },
+    ]
+}
+
+/// Returns instructions which raise an error unless the integer in rax is a Unicode scalar value.
+///
+/// This clobbers r9 and rsi.
+fn assert_codepoint(compiler: &mut Compiler) -> Vec<Statement> {
+    let ok_label = format!("codepoint_ok_{}", compiler.new_label_id());
+
     let mut statements = assert_int(Register::RAX);
 
+    // Pass the raw integer to the error handler.
+    statements.push(Statement::Mov {
+        dest: Operand::Register(Register::RSI),
+        src: RAX,
+    });
+    statements.push(Statement::Sar {
+        dest: Operand::Register(Register::RSI),
+        src: Operand::Immediate(INT_TYPE.shift as i64),
+    });
+
     // Make sure the value is in the range 0..=0x10FFFF
     statements.push(Statement::Cmp {
         dest: RAX,
         src: Operand::from(Value::Int(0)),
     });
     statements.push(Statement::Jl {
-        label: ERR_LABEL.to_string(),
+        label: CODEPOINT_RANGE_ERR_LABEL.to_string(),
     });
     statements.push(Statement::Cmp {
         dest: RAX,
         src: Operand::from(Value::Int(0x10FFFF)),
     });
     statements.push(Statement::Jg {
-        label: ERR_LABEL.to_string(),
+        label: CODEPOINT_RANGE_ERR_LABEL.to_string(),
     });
 
     // except for the range 55296..=57343.
     statements.push(Statement::Cmp {
         dest: RAX,
-        src: Operand::from(Value::Int(55295)),
+        src: Operand::from(Value::Int(55296)),
     });
     statements.push(Statement::Jl {
-        label: "ok".to_string(),
+        label: ok_label.clone(),
     });
     statements.push(Statement::Cmp {
         dest: RAX,
-        src: Operand::from(Value::Int(57344)),
+        src: Operand::from(Value::Int(57343)),
     });
     statements.push(Statement::Jg {
-        label: "ok".to_string(),
+        label: ok_label.clone(),
     });
     statements.push(Statement::Jmp {
-        label: ERR_LABEL.to_string(),
-    });
-    statements.push(Statement::Label {
-        name: "ok".to_string(),
+        label: SURROGATE_ERR_LABEL.to_string(),
     });
+    statements.push(Statement::Label { name: ok_label });
     statements
 }
 
+/// Returns instructions which raise an error unless the value in the given register is an integer in 0..=255.
+///
+/// This clobbers r9.
 pub fn assert_byte(register: Register) -> Vec<Statement> {
-    let mut statements = assert_int(register);
+    let mut statements = assert_int(register.clone());
 
     // Make sure the value is in the range 0..=255
+    // The tagged integers can be compared as they are, since the encoding preserves the order.
     statements.push(Statement::Cmp {
-        dest: R9,
+        dest: Operand::Register(register.clone()),
         src: Operand::from(Value::Int(0)),
     });
     statements.push(Statement::Jl {
         label: ERR_LABEL.to_string(),
     });
     statements.push(Statement::Cmp {
-        dest: R9,
+        dest: Operand::Register(register),
         src: Operand::from(Value::Int(255)),
     });
     statements.push(Statement::Jg {
@@ -191,3 +401,38 @@ pub fn assert_byte(register: Register) -> Vec<Statement> {
 
     statements
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::arithmetic::*;
+    use super::*;
+
+    fn count_shifts(statements: &[Statement]) -> usize {
+        statements
+            .iter()
+            .filter(|statement| matches!(statement, Statement::Sar { .. } | Statement::Sal { .. }))
+            .count()
+    }
+
+    #[test]
+    fn conversions_shift_once() {
+        assert_eq!(count_shifts(&cast_type(Register::RAX, &CHAR_TYPE, &INT_TYPE)), 1);
+        assert_eq!(count_shifts(&cast_type(Register::RAX, &INT_TYPE, &CHAR_TYPE)), 1);
+        assert_eq!(count_shifts(&cast_type(Register::RAX, &INT_TYPE, &INT_TYPE)), 0);
+    }
+
+    #[test]
+    fn arithmetic_does_not_shift() {
+        for statements in [
+            compile_add(),
+            compile_sub(),
+            compile_add1(),
+            compile_sub1(),
+            compile_is_zero(),
+            compile_int_equal(),
+            compile_less_than(),
+        ] {
+            assert_eq!(count_shifts(&statements), 0, "{:?}", statements);
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use crate::{
     a86::ast::{Operand, Register, Statement},
-    mylang::ast::{self, Identifier},
+    mylang::{
+        ast::{self, Identifier},
+        error::CompileError,
+    },
 };
 
 use super::{expr::compile_expr, state::Compiler};
@@ -13,36 +16,38 @@ pub fn compile_let(
     compiler: &mut Compiler,
     env: &VariablesTable,
     is_tail_expr: bool,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
     let ast::Let { binding, body } = expr;
 
-    let mut statements = compile_expr(*binding.rhs, compiler, env, false);
+    let mut statements = compile_expr(*binding.rhs, compiler, env, false)?;
     statements.push(Statement::Push { src: RAX });
 
     let new_env = env.with_var(&binding.lhs);
-    statements.extend(compile_expr(*body, compiler, &new_env, is_tail_expr));
+    statements.extend(compile_expr(*body, compiler, &new_env, is_tail_expr)?);
 
     // Pop the value from the stack and discard it.
+    // A tail call in the body never returns here, but it drops the value by itself,
+    // since it discards the whole environment including the binding.
     statements.push(Statement::Add {
         dest: RSP,
         src: Operand::Immediate(8),
     });
-    statements
+    Ok(statements)
 }
 
 pub fn compile_variable(
     variable: Identifier,
     _compiler: &mut Compiler,
     env: &VariablesTable,
-) -> Vec<Statement> {
+) -> Result<Vec<Statement>, CompileError> {
     let position = env
         .position(&variable)
-        .expect(format!("Undefined variable `{}`", variable.0).as_str()); // TODO: Return `Result` type.
+        .ok_or_else(|| CompileError::UndefinedVariable(variable.0.clone()))?;
     let offset = (position * 8) as i64;
-    vec![Statement::Mov {
+    Ok(vec![Statement::Mov {
         dest: RAX,
         src: Operand::Offset(Register::RSP, offset),
-    }]
+    }])
 }
 
 #[derive(Clone, Debug)]
@@ -76,10 +81,23 @@ impl VariablesTable {
         Self::new_with_vars(&variables)
     }
 
+    /// Returns the table where only the first `len` variables are visible,
+    /// while the rest still take up their slots in the stack.
+    pub fn with_vars_hidden_after(&self, len: usize) -> Self {
+        let mut variables = self.variables.clone();
+        for variable in variables.iter_mut().skip(len) {
+            *variable = None;
+        }
+        Self::new_with_vars(&variables)
+    }
+
+    /// Returns the lexical address of the variable, i.e. the number of words above it in the stack.
+    ///
+    /// If the name is bound more than once, the innermost binding pushed last shadows the others.
     pub fn position(&self, variable: &Identifier) -> Option<usize> {
         self.variables
             .iter()
-            .position(|option| option.as_ref().is_some_and(|v| v == variable))
+            .rposition(|option| option.as_ref().is_some_and(|v| v == variable))
             .map(|i| self.variables.len() - i - 1)
     }
     
@@ -110,4 +128,17 @@ mod tests {
         assert_eq!(env.position(&b), Some(1));
         assert_eq!(env.position(&c), Some(0));
     }
+
+    #[test]
+    fn hidden_variables() {
+        let a = Identifier("a".to_string());
+        let b = Identifier("b".to_string());
+
+        let env = VariablesTable::new().with_var(&a).with_var(&b).with_var(&a);
+        let hidden = env.with_vars_hidden_after(1);
+
+        assert_eq!(hidden.len(), 3);
+        assert_eq!(hidden.position(&a), Some(2));
+        assert_eq!(hidden.position(&b), None);
+    }
 }
@@ -4,9 +4,8 @@ use crate::{
 };
 
 use super::{
-    error::ERR_LABEL,
     state::Compiler,
-    types::{assert_natural_number, assert_vector},
+    types::{assert_in_bounds, assert_index, assert_natural_number, assert_vector},
 };
 
 const RAX: Operand = Operand::Register(Register::RAX);
@@ -102,54 +101,29 @@ pub fn compile_make_vector(compiler: &mut Compiler) -> Vec<Statement> {
 
 /// Returns instructions which sets rax to the element in the vector at the given index,
 /// assuming the vector and the index is already given in r8 and rax respectively.
-pub fn compile_vector_ref(_compiler: &mut Compiler) -> Vec<Statement> {
+pub fn compile_vector_ref(compiler: &mut Compiler) -> Vec<Statement> {
     let mut statements = assert_vector(Register::R8);
-    statements.extend(assert_natural_number(Register::RAX));
+    statements.extend(assert_index(Register::RAX));
 
-    // Special case for empty vector
-    statements.push(Statement::Cmp {
-        dest: R8,
-        src: Operand::from(Value::EmptyVector),
-    });
-    statements.push(Statement::Je {
-        label: ERR_LABEL.to_string(),
-    });
-
-    // Cast r8 to the raw pointer address of the vector.
-    statements.push(Statement::Xor {
-        dest: R8,
-        src: Operand::Immediate(VECTOR_TYPE.tag.0 as i64),
-    });
-    // Set r9 to the length of the vector.
-    statements.push(Statement::Mov {
-        dest: R9,
-        src: Operand::Offset(Register::R8, 0),
-    });
     // Cast rax to raw integer representing the index.
     statements.push(Statement::Sar {
         dest: RAX,
         src: Operand::Immediate(INT_TYPE.shift as i64),
     });
 
-    // Check if the index is out of bounds. (length - 1 < index)
-    statements.push(Statement::Sub {
-        dest: R9,
-        src: Operand::Immediate(1),
-    });
-    statements.push(Statement::Cmp { dest: R9, src: RAX });
-    statements.push(Statement::Jl {
-        label: ERR_LABEL.to_string(),
-    });
+    statements.extend(vector_length(compiler));
+    statements.extend(assert_in_bounds(Register::RAX, Register::R9));
 
     // Get the element at the given index.
-    statements.push(Statement::Sal {
-        dest: RAX,
-        src: Operand::Immediate(3), // Each element takes up 2^3 bytes.
-    });
-    statements.push(Statement::Add { dest: R8, src: RAX });
+    // Each element takes up 8 bytes, and the offset 8 is required as the first word is the length.
     statements.push(Statement::Mov {
         dest: RAX,
-        src: Operand::Offset(Register::R8, 8), // The offset 8 is required as the first word is the length.
+        src: Operand::ScaledIndex {
+            base: Register::R8,
+            index: Register::RAX,
+            scale: 8,
+            disp: 8,
+        },
     });
 
     statements
@@ -162,7 +136,7 @@ pub fn compile_vector_ref(_compiler: &mut Compiler) -> Vec<Statement> {
 /// * 3rd argument (new value): rax
 /// 
 /// This clobbers r8, r9, and r10.
-pub fn compile_vector_set(_compiler: &mut Compiler) -> Vec<Statement> {
+pub fn compile_vector_set(compiler: &mut Compiler) -> Vec<Statement> {
     // Move the arguments to the registers.
     // 1st argument (vector): r8
     // 2nd argument (index): r10
@@ -176,33 +150,16 @@ pub fn compile_vector_set(_compiler: &mut Compiler) -> Vec<Statement> {
         },
     ];
     statements.extend(assert_vector(Register::R8));
-    statements.extend(assert_natural_number(Register::R10));
+    statements.extend(assert_index(Register::R10));
 
-    // Cast r8 to the raw pointer address of the vector.
-    statements.push(Statement::Xor {
-        dest: R8,
-        src: Operand::Immediate(VECTOR_TYPE.tag.0 as i64),
-    });
-    // Set r9 to the length of the vector.
-    statements.push(Statement::Mov {
-        dest: R9,
-        src: Operand::Offset(Register::R8, 0),
-    });
     // Cast r10 to raw integer representing the index.
     statements.push(Statement::Sar {
         dest: R10,
         src: Operand::Immediate(INT_TYPE.shift as i64),
     });
 
-    // Check if the index is out of bounds. (length - 1 < index)
-    statements.push(Statement::Sub {
-        dest: R9,
-        src: Operand::Immediate(1),
-    });
-    statements.push(Statement::Cmp { dest: R9, src: R10 });
-    statements.push(Statement::Jl {
-        label: ERR_LABEL.to_string(),
-    });
+    statements.extend(vector_length(compiler));
+    statements.extend(assert_in_bounds(Register::R10, Register::R9));
 
     // Set the new value.
     statements.push(Statement::Sal {
@@ -222,3 +179,37 @@ pub fn compile_vector_set(_compiler: &mut Compiler) -> Vec<Statement> {
     });
     statements
 }
+
+/// Returns instructions which set r9 to the length of the vector in r8,
+/// and cast r8 to the raw pointer address of the vector.
+///
+/// The empty vector has the length 0 and no address.
+fn vector_length(compiler: &mut Compiler) -> Vec<Statement> {
+    let end_label = format!("vector_length_{}", compiler.new_label_id());
+
+    vec![
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Immediate(0),
+        },
+        // Special case for empty vector
+        Statement::Cmp {
+            dest: R8,
+            src: Operand::from(Value::EmptyVector),
+        },
+        Statement::Je {
+            label: end_label.clone(),
+        },
+        // Cast r8 to the raw pointer address of the vector.
+        Statement::Xor {
+            dest: R8,
+            src: Operand::Immediate(VECTOR_TYPE.tag.0 as i64),
+        },
+        // Set r9 to the length of the vector.
+        Statement::Mov {
+            dest: R9,
+            src: Operand::Offset(Register::R8, 0),
+        },
+        Statement::Label { name: end_label },
+    ]
+}
@@ -1,3 +1,30 @@
+//! Runtime representation of values.
+//!
+//! # Bit layout of values
+//!
+//! ```plaintext
+//! Values are either:
+//! - Immediates: end in #b000
+//! - Pointers
+//!
+//! Pointers are either:
+//! - Boxes:   end in #b001
+//! - Cons:    end in #b010
+//! - Vector:  end in #b011
+//! - String:  end in #b100
+//! - Closure: end in #b101
+//!
+//! Immediates are either
+//! - Integers:   end in  #b0 000
+//! - Characters: end in #b01 000
+//! - True:              #b11 000
+//! - False:           #b1 11 000
+//! - Eof:            #b10 11 000
+//! - Void:           #b11 11 000
+//! - Empty:         #b100 11 000
+//! ```
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int(i64),
     Byte(u8),
@@ -12,8 +39,10 @@ pub enum Value {
     Box(Address),
     Vector(Address),
     String(Address),
+    Closure(Address),
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Address(pub i64);
 
 impl Value {
@@ -28,6 +57,7 @@ impl Value {
             Value::Cons(Address(a)) => CONS_TYPE.encode(a),
             Value::Vector(Address(a)) => VECTOR_TYPE.encode(a),
             Value::String(Address(a)) => STRING_TYPE.encode(a),
+            Value::Closure(Address(a)) => CLOSURE_TYPE.encode(a),
 
             Value::Boolean(true) => TRUE_TYPE.0 as i64,
             Value::Boolean(false) => FALSE_TYPE.0 as i64,
@@ -38,6 +68,50 @@ impl Value {
             Value::EmptyString => STRING_TYPE.tag.0 as i64,
         }
     }
+
+    /// Returns the value represented by the given bits, or `None` if they are not a valid encoding.
+    ///
+    /// Pointers are not dereferenced, so only their type and address are recovered.
+    /// A null vector or string pointer is decoded as the empty vector or string.
+    /// Bytes are encoded as integers, so they are decoded as [Value::Int].
+    pub fn decode(bits: i64) -> Option<Value> {
+        if let Some(a) = BOX_TYPE.decode(bits) {
+            return Some(Value::Box(Address(a)));
+        }
+        if let Some(a) = CONS_TYPE.decode(bits) {
+            return Some(Value::Cons(Address(a)));
+        }
+        if let Some(a) = VECTOR_TYPE.decode(bits) {
+            return Some(match a {
+                0 => Value::EmptyVector,
+                _ => Value::Vector(Address(a)),
+            });
+        }
+        if let Some(a) = STRING_TYPE.decode(bits) {
+            return Some(match a {
+                0 => Value::EmptyString,
+                _ => Value::String(Address(a)),
+            });
+        }
+        if let Some(a) = CLOSURE_TYPE.decode(bits) {
+            return Some(Value::Closure(Address(a)));
+        }
+        if let Some(i) = INT_TYPE.decode(bits) {
+            return Some(Value::Int(i));
+        }
+        if let Some(c) = CHAR_TYPE.decode(bits) {
+            return u32::try_from(c).ok().and_then(char::from_u32).map(Value::Char);
+        }
+
+        match bits as u64 {
+            bits if bits == TRUE_TYPE.0 => Some(Value::Boolean(true)),
+            bits if bits == FALSE_TYPE.0 => Some(Value::Boolean(false)),
+            bits if bits == EOF_TYPE.0 => Some(Value::Eof),
+            bits if bits == VOID_TYPE.0 => Some(Value::Void),
+            bits if bits == EMPTY_TYPE.0 => Some(Value::EmptyList),
+            _ => None,
+        }
+    }
 }
 
 pub struct TypeTag(pub u64);
@@ -52,32 +126,20 @@ impl UnaryType {
         (value << self.shift) + (self.tag.0 as i64)
     }
 
+    /// Returns the payload of the encoded value if it has this type.
+    pub fn decode(&self, bits: i64) -> Option<i64> {
+        if (bits as u64) & self.mask() == self.tag.0 {
+            Some(bits >> self.shift)
+        } else {
+            None
+        }
+    }
+
     pub fn mask(&self) -> u64 {
         (1 << self.shift) - 1
     }
 }
 
-// Bit layout of values
-//
-// Values are either:
-// - Immediates: end in #b000
-// - Pointers
-//
-// Pointers are either:
-// - Boxes:   end in #b001
-// - Cons:    end in #b010
-// - Vector:  end in #b011
-// - String:  end in #b100
-// - Closure: end in #b101
-//
-// Immediates are either
-// - Integers:   end in  #b0 000
-// - Characters: end in #b01 000
-// - True:              #b11 000
-// - False:           #b1 11 000
-// - Eof:            #b10 11 000
-// - Void:           #b11 11 000
-// - Empty:         #b100 11 000
 const IMMEDIATE_SHIFT: u64 = 3;
 
 pub const BOX_TYPE: UnaryType = UnaryType {
@@ -115,8 +177,62 @@ pub const CHAR_TYPE: UnaryType = UnaryType {
     tag: TypeTag(0b01 << IMMEDIATE_SHIFT),
 };
 
+/// The smallest integer representable as a tagged value, which is `-2^59`.
+pub const MIN_INT: i64 = i64::MIN >> INT_TYPE.shift;
+
+/// The largest integer representable as a tagged value, which is `2^59 - 1`.
+pub const MAX_INT: i64 = i64::MAX >> INT_TYPE.shift;
+
 pub const TRUE_TYPE: TypeTag = TypeTag(0b11 << IMMEDIATE_SHIFT);
 pub const FALSE_TYPE: TypeTag = TypeTag(0b111 << IMMEDIATE_SHIFT);
 pub const EOF_TYPE: TypeTag = TypeTag(0b1011 << IMMEDIATE_SHIFT);
 pub const VOID_TYPE: TypeTag = TypeTag(0b1111 << IMMEDIATE_SHIFT);
 pub const EMPTY_TYPE: TypeTag = TypeTag(0b10011 << IMMEDIATE_SHIFT);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_inverse_of_encode() {
+        let values = vec![
+            Value::Int(0),
+            Value::Int(42),
+            Value::Int(-42),
+            Value::Char('a'),
+            Value::Char('λ'),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Eof,
+            Value::Void,
+            Value::EmptyList,
+        ];
+
+        for value in values {
+            assert_eq!(Value::decode(value.clone().encode()), Some(value));
+        }
+    }
+
+    #[test]
+    fn decode_pointer_tags() {
+        let values = vec![
+            Value::Box(Address(1)),
+            Value::Cons(Address(2)),
+            Value::Vector(Address(3)),
+            Value::String(Address(4)),
+            Value::Closure(Address(5)),
+            Value::EmptyVector,
+            Value::EmptyString,
+        ];
+
+        for value in values {
+            assert_eq!(Value::decode(value.clone().encode()), Some(value));
+        }
+    }
+
+    #[test]
+    fn decode_invalid_bits() {
+        assert_eq!(Value::decode(0b110), None);
+        assert_eq!(Value::decode(0b111), None);
+    }
+}
@@ -1,5 +1,53 @@
+use super::data_type::{MAX_INT, MIN_INT};
 use super::document::Position;
 
+/// Errors that can occur while compiling a source program.
+#[derive(Debug, PartialEq)]
+pub enum CompileError {
+    ParserError(ParserError),
+    /// An integer literal which does not fit in the tagged integer representation.
+    IntegerOutOfRange(i64),
+    /// A reference to a variable which is not bound in the scope.
+    UndefinedVariable(String),
+    /// Failure to write the compiled assembly to the output.
+    WriteError(String),
+    /// The compiled program refers to undefined labels or defines a label twice, which is a bug of the compiler.
+    InvalidAssembly(Vec<String>),
+    /// A primitive applied to a literal of the wrong type, which would always fail at runtime.
+    TypeMismatch {
+        msg: String,
+        position: Option<Position>,
+    },
+}
+
+impl From<ParserError> for CompileError {
+    fn from(err: ParserError) -> CompileError {
+        CompileError::ParserError(err)
+    }
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompileError::ParserError(err) => write!(f, "{}", err),
+            CompileError::IntegerOutOfRange(i) => write!(
+                f,
+                "Integer literal {} is out of range. It must be between {} and {}.",
+                i, MIN_INT, MAX_INT
+            ),
+            CompileError::UndefinedVariable(name) => write!(f, "Undefined variable `{}`", name),
+            CompileError::WriteError(msg) => write!(f, "Failed to write the output: {}", msg),
+            CompileError::InvalidAssembly(errors) => {
+                write!(f, "The compiler produced invalid assembly: {}", errors.join(", "))
+            }
+            CompileError::TypeMismatch { msg, position } => match position {
+                Some(position) => write!(f, "Type error at offset {}: {}", position.offset, msg),
+                None => write!(f, "Type error: {}", msg),
+            },
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ParserError {
     InvalidToken(InvalidTokenError),
@@ -37,12 +85,13 @@ impl std::fmt::Display for ParserError {
 
 #[derive(Debug, PartialEq)]
 pub struct InvalidTokenError {
+    pub msg: String,
     pub position: Position,
 }
 
 impl std::fmt::Display for InvalidTokenError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Invalid token at: {:?}", self.position)
+        write!(f, "Invalid token at offset {}: {}", self.position.offset, self.msg)
     }
 }
 
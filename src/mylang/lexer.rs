@@ -4,6 +4,8 @@ use logos::{Lexer, Logos};
 
 #[derive(Logos, Debug, PartialEq)]
 #[logos(skip r"\s+")]
+#[logos(skip r";[^\n]*")] // Line comments
+#[logos(error = LexingError)]
 pub enum TokenKind {
     // TODO: Combined with the current parser implementation, this incorrectly accepts something like `[1 2)`.
     #[regex(r"\(|\[")]
@@ -12,28 +14,66 @@ pub enum TokenKind {
     #[regex(r"\)|\]")]
     ParenClose,
 
-    #[regex(r"-?[0-9]+", |lex| lex.slice().parse().ok(), priority=2)]
+    #[regex(r"-?[0-9]+", parse_integer, priority=2)]
     Integer(i64),
 
-    #[regex(r"[^\s\[\]()0-9][^\s\[\]()]*", |lex| lex.slice().to_string())]
+    /// An identifier, which is any run of characters other than whitespace, brackets, `"` and `;`.
+    /// Thus it may contain characters like `+ - * / < > = ! ?`, e.g. `string->number` or `set-box!`.
+    ///
+    /// It may not start with a digit, so `-1` or `42` is lexed as an integer.
+    /// It may not start with `#` or `"` either, so that malformed literals are reported as invalid tokens.
+    /// Keywords and primitive names like `+` or `let` are ordinary symbols at this level, and reserved by the parser.
+    #[regex(r#"[^\s\[\]()0-9#";][^\s\[\]()";]*"#, |lex| lex.slice().to_string())]
     Symbol(String),
 
-    #[regex(r"#[tf]", parse_bool)]
+    // Match the whole alphabetic run, so that `#true` is not lexed as `#t` followed by `rue`.
+    #[regex(r"#[a-zA-Z]+", parse_bool)]
     Boolean(bool),
 
-    #[regex(r"#\\.", |lex| lex.slice().chars().nth(2).unwrap())]
+    /// A character literal such as `#\a`, or a Unicode escape such as `#\u03BB` or `#\U0001F600`.
+    // The character is read by the callback, since a regex would match only a single byte of a non-ASCII character.
+    #[token("#\\", parse_char)]
     Character(char),
 
     #[regex(r#""(?:[^"]|\\")*""#, strip_first_and_last_char)]
+    #[regex(r#""(?:[^"]|\\")*"#, |_| Err(LexingError::UnterminatedString))]
     String(String),
 }
 
+/// Reasons for the lexer to reject the input.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub enum LexingError {
+    #[default]
+    InvalidToken,
+    UnterminatedString,
+    IntegerOutOfRange,
+    /// A Unicode escape in a character literal which is not a Unicode scalar value.
+    InvalidCodepoint(u32),
+}
+
+impl std::fmt::Display for LexingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LexingError::InvalidToken => write!(f, "Invalid token"),
+            LexingError::UnterminatedString => write!(f, "Unterminated string literal"),
+            LexingError::IntegerOutOfRange => write!(f, "Integer literal out of range"),
+            LexingError::InvalidCodepoint(n) => write!(
+                f,
+                "Invalid character literal: U+{:X} is not a Unicode scalar value",
+                n
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Token {
     pub token: TokenKind,
     pub position: Position,
 }
 
+/// Splits the source code into the tokens with their positions, skipping whitespace and comments,
+/// or returns the first invalid token.
 pub fn tokenize(src: &str) -> Result<Vec<Token>, InvalidTokenError> {
     let results = TokenKind::lexer(src).spanned();
 
@@ -44,19 +84,56 @@ pub fn tokenize(src: &str) -> Result<Vec<Token>, InvalidTokenError> {
                 position: Position::new(span.start),
             }),
 
-            Err(_) => Err(InvalidTokenError {
+            Err(err) => Err(InvalidTokenError {
+                msg: err.to_string(),
                 position: Position { offset: span.start },
             }),
         })
         .collect()
 }
 
-fn parse_bool(lex: &mut Lexer<TokenKind>) -> bool {
+fn parse_integer(lex: &mut Lexer<TokenKind>) -> Result<i64, LexingError> {
+    // The regex guarantees the slice is made of digits, so the parsing fails only on overflow.
+    lex.slice()
+        .parse()
+        .map_err(|_| LexingError::IntegerOutOfRange)
+}
+
+fn parse_bool(lex: &mut Lexer<TokenKind>) -> Option<bool> {
     match lex.slice() {
-        "#t" => true,
-        "#f" => false,
-        _ => panic!("Invalid boolean literal"),
+        "#t" | "#true" => Some(true),
+        "#f" | "#false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses a character literal following `#\`, which is either a single character,
+/// or `u` followed by up to 4 hex digits, or `U` followed by up to 8 hex digits.
+///
+/// `u` or `U` without any hex digits is the character itself.
+fn parse_char(lex: &mut Lexer<TokenKind>) -> Result<char, LexingError> {
+    let c = lex.remainder().chars().next().ok_or(LexingError::InvalidToken)?;
+    lex.bump(c.len_utf8());
+
+    let max_digits = match c {
+        'u' => 4,
+        'U' => 8,
+        _ => return Ok(c),
+    };
+    let digits: String = lex
+        .remainder()
+        .chars()
+        .take(max_digits)
+        .take_while(char::is_ascii_hexdigit)
+        .collect();
+    if digits.is_empty() {
+        return Ok(c);
     }
+    lex.bump(digits.len());
+
+    // The digits are at most 8, so the parsing never overflows.
+    let codepoint = u32::from_str_radix(&digits, 16).expect("hex digits");
+    char::from_u32(codepoint).ok_or(LexingError::InvalidCodepoint(codepoint))
 }
 
 fn strip_first_and_last_char(lex: &mut Lexer<TokenKind>) -> String {
@@ -3,18 +3,60 @@ use super::document::Position;
 use super::error::AstPasringError;
 use super::s_expression::{Atom, Expr, ExprKind, List};
 
+use self::list::{parse_build_vector, parse_filter, parse_for_each, parse_list_form, parse_map};
+
+mod list;
+
 type Result<T> = std::result::Result<T, super::error::AstPasringError>;
 
+/// The variables lexically bound at a point of the program, i.e. function names, parameters,
+/// and variables bound by `let` or `match`.
+///
+/// Symbols are resolved in the following order:
+/// 1. A bound variable, even if it has the same name as a primitive or a keyword,
+///    so `(let ((add1 f)) (add1 2))` applies `f`.
+/// 2. A primitive operator or a keyword like `add1` or `if`.
+/// 3. Otherwise, an unbound variable, which the compiler reports.
+#[derive(Clone, Debug, Default)]
+pub struct Scope {
+    variables: Vec<ast::Identifier>,
+}
+
+impl Scope {
+    fn with_vars<'a>(&self, variables: impl IntoIterator<Item = &'a ast::Identifier>) -> Scope {
+        let mut scope = self.clone();
+        scope.variables.extend(variables.into_iter().cloned());
+        scope
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        self.variables.iter().any(|variable| variable.0 == name)
+    }
+}
+
 pub fn parse(s_expressions: &Vec<Expr>) -> Result<ast::Program> {
     let (last, rest) = s_expressions
         .split_last()
-        .ok_or(err("Empty program.", Position::zero()))?;
+        .ok_or(err(
+            "The program is empty. It must contain at least an expression to evaluate.",
+            Position::zero(),
+        ))?;
+
+    if is_definition(last) {
+        return Err(err(
+            "There is no top-level expression to evaluate. The last form must be an expression, not a definition.",
+            last.position.clone(),
+        ));
+    }
+
+    let function_names: Vec<_> = rest.iter().filter_map(function_name).collect();
+    let scope = &Scope::default().with_vars(&function_names);
 
     let function_definitions = rest
         .iter()
-        .map(|expr| parse_function_definition(expr))
+        .map(|expr| parse_function_definition(expr, scope))
         .collect::<Result<Vec<_>>>()?;
-    let expr = parse_expr(last)?;
+    let expr = parse_expr(last, scope)?;
 
     Ok(ast::Program {
         function_definitions,
@@ -22,27 +64,29 @@ pub fn parse(s_expressions: &Vec<Expr>) -> Result<ast::Program> {
     })
 }
 
-pub fn parse_expr(expr: &Expr) -> Result<ast::Expr> {
+pub fn parse_expr(expr: &Expr, scope: &Scope) -> Result<ast::Expr> {
     match &expr.kind {
-        ExprKind::Atom(atom) => parse_literal(&atom),
-        ExprKind::List(list) => parse_list(&list),
+        ExprKind::Atom(atom) => parse_literal(atom, scope),
+        ExprKind::List(list) => parse_list(list, expr.position.clone(), scope),
     }
 }
 
-fn parse_literal(atom: &Atom) -> Result<ast::Expr> {
+fn parse_literal(atom: &Atom, scope: &Scope) -> Result<ast::Expr> {
     match atom {
         Atom::Integer(n) => Ok(ast::Expr::Lit(ast::Lit::Int(*n))),
         Atom::Boolean(b) => Ok(ast::Expr::Lit(ast::Lit::Bool(*b))),
         Atom::Character(c) => Ok(ast::Expr::Lit(ast::Lit::Char(*c))),
         Atom::String(s) => Ok(ast::Expr::Lit(ast::Lit::String(s.to_owned()))),
         Atom::Symbol(s) => match s.as_str() {
-            "eof" => Ok(ast::Expr::Eof),
+            "eof" if !scope.contains(s) => Ok(ast::Expr::Eof),
             _ => Ok(ast::Expr::Variable(ast::Identifier::new(s))),
         },
     }
 }
 
-fn parse_list(List(elems): &List) -> Result<ast::Expr> {
+/// # Arguments
+/// * `list_position` - The position of the opening parenthesis of the list.
+fn parse_list(List(elems): &List, list_position: Position, scope: &Scope) -> Result<ast::Expr> {
     match elems.as_slice() {
         [] => Ok(ast::Expr::Lit(ast::Lit::EmptyList)),
 
@@ -50,120 +94,609 @@ fn parse_list(List(elems): &List) -> Result<ast::Expr> {
             let position = head.position.clone();
 
             match &head.kind {
-                ExprKind::Atom(Atom::Symbol(s)) => match s.as_str() {
-                    "read-byte" => parse_prim0(ast::Op0::ReadByte, position, rest),
-                    "peek-byte" => parse_prim0(ast::Op0::PeekByte, position, rest),
-
-                    "add1" => parse_prim1(ast::Op1::Add1, position, rest),
-                    "sub1" => parse_prim1(ast::Op1::Sub1, position, rest),
-
-                    "zero?" => parse_prim1(ast::Op1::IsZero, position, rest),
-                    "char?" => parse_prim1(ast::Op1::IsChar, position, rest),
-                    "eof-object?" => parse_prim1(ast::Op1::IsEof, position, rest),
-                    "box?" => parse_prim1(ast::Op1::IsBox, position, rest),
-                    "cons?" => parse_prim1(ast::Op1::IsCons, position, rest),
-                    "vector?" => parse_prim1(ast::Op1::IsVector, position, rest),
-                    "string?" => parse_prim1(ast::Op1::IsString, position, rest),
-
-                    "integer->char" => parse_prim1(ast::Op1::IntToChar, position, rest),
-                    "char->integer" => parse_prim1(ast::Op1::CharToInt, position, rest),
-
-                    "write-byte" => parse_prim1(ast::Op1::WriteByte, position, rest),
-
-                    "box" => parse_prim1(ast::Op1::Box, position, rest),
-                    "unbox" => parse_prim1(ast::Op1::Unbox, position, rest),
-                    "car" => parse_prim1(ast::Op1::Car, position, rest),
-                    "cdr" => parse_prim1(ast::Op1::Cdr, position, rest),
-
-                    "+" => parse_prim2(ast::Op2::Add, position, rest),
-                    "-" => parse_prim2(ast::Op2::Sub, position, rest),
-                    "<" => parse_prim2(ast::Op2::LessThan, position, rest),
-                    "=" => parse_prim2(ast::Op2::IntEq, position, rest),
+                ExprKind::Atom(Atom::Symbol(s)) if !scope.contains(s) => match s.as_str() {
+                    "read-byte" => parse_prim0(ast::Op0::ReadByte, s, position, rest),
+                    "peek-byte" => parse_peek_byte(s, position, rest, scope),
+                    "newline" => parse_prim0(ast::Op0::Newline, s, position, rest),
+                    "eof-object" => parse_prim0(ast::Op0::EofObject, s, position, rest),
+                    "flush-output" => parse_prim0(ast::Op0::FlushOutput, s, position, rest),
+
+                    "identity" => parse_prim1(ast::Op1::Identity, s, position, rest, scope),
+                    "const" => parse_const(rest, position, scope),
+
+                    "add1" => parse_prim1(ast::Op1::Add1, s, position, rest, scope),
+                    "sub1" => parse_prim1(ast::Op1::Sub1, s, position, rest, scope),
+
+                    "zero?" => parse_prim1(ast::Op1::IsZero, s, position, rest, scope),
+                    "positive?" => parse_prim1(ast::Op1::IsPositive, s, position, rest, scope),
+                    "negative?" => parse_prim1(ast::Op1::IsNegative, s, position, rest, scope),
+                    "char?" => parse_prim1(ast::Op1::IsChar, s, position, rest, scope),
+                    "eof-object?" => parse_prim1(ast::Op1::IsEof, s, position, rest, scope),
+                    "box?" => parse_prim1(ast::Op1::IsBox, s, position, rest, scope),
+                    "cons?" => parse_prim1(ast::Op1::IsCons, s, position, rest, scope),
+                    "vector?" => parse_prim1(ast::Op1::IsVector, s, position, rest, scope),
+                    "string?" => parse_prim1(ast::Op1::IsString, s, position, rest, scope),
+
+                    "integer->char" => parse_prim1(ast::Op1::IntToChar, s, position, rest, scope),
+                    "char->integer" => parse_prim1(ast::Op1::CharToInt, s, position, rest, scope),
+                    "char-next" => parse_prim1(ast::Op1::CharNext, s, position, rest, scope),
+                    "char-prev" => parse_prim1(ast::Op1::CharPrev, s, position, rest, scope),
+
+                    "write-byte" => parse_prim1(ast::Op1::WriteByte, s, position, rest, scope),
+                    "write-string" => parse_prim1(ast::Op1::WriteString, s, position, rest, scope),
+                    "display" => parse_prim1(ast::Op1::Display, s, position, rest, scope),
+                    "assert" => parse_prim1(ast::Op1::Assert, s, position, rest, scope),
+                    "error" => parse_prim1(ast::Op1::Error, s, position, rest, scope),
+                    "printf" => parse_printf(rest, position, scope),
+
+                    "box" => parse_prim1(ast::Op1::Box, s, position, rest, scope),
+                    "unbox" => parse_prim1(ast::Op1::Unbox, s, position, rest, scope),
+                    "car" => parse_prim1(ast::Op1::Car, s, position, rest, scope),
+                    "cdr" => parse_prim1(ast::Op1::Cdr, s, position, rest, scope),
+                    "string-copy" => parse_prim1(ast::Op1::StringCopy, s, position, rest, scope),
+                    "string-length" => parse_prim1(ast::Op1::StringLength, s, position, rest, scope),
+                    "string-utf8-length" => parse_prim1(ast::Op1::StringUtf8Length, s, position, rest, scope),
+                    "string-upcase" => parse_prim1(ast::Op1::StringUpcase, s, position, rest, scope),
+                    "string-downcase" => parse_prim1(ast::Op1::StringDowncase, s, position, rest, scope),
+
+                    "+" => parse_arithmetic(ast::Op2::Add, s, position, rest, scope),
+                    "-" => parse_arithmetic(ast::Op2::Sub, s, position, rest, scope),
+                    "*" => parse_arithmetic(ast::Op2::Mul, s, position, rest, scope),
+                    "bitwise-not" => parse_prim1(ast::Op1::BitNot, s, position, rest, scope),
+                    "modulo" => parse_prim2(ast::Op2::Modulo, s, position, rest, scope),
+                    "<" if rest.len() == 2 => parse_prim2(ast::Op2::LessThan, s, position, rest, scope),
+                    "=" if rest.len() == 2 => parse_prim2(ast::Op2::IntEq, s, position, rest, scope),
+                    "<" | "=" | "<=" | ">" | ">=" => parse_comparison(s, position, rest, scope),
+                    "char=?" => parse_prim2(ast::Op2::CharEq, s, position, rest, scope),
+                    "char<?" => parse_prim2(ast::Op2::CharLessThan, s, position, rest, scope),
+                    "char>?" => parse_prim2(ast::Op2::CharGreaterThan, s, position, rest, scope),
                     
-                    "eq?" => parse_prim2(ast::Op2::Eq, position, rest),
+                    "eq?" => parse_prim2(ast::Op2::Eq, s, position, rest, scope),
+
+                    "cons" => parse_prim2(ast::Op2::Cons, s, position, rest, scope),
+                    "cons*" => parse_cons_star(rest, position, scope),
+                    "list" => parse_list_form(rest, position, scope),
+                    "for-each" => parse_for_each(rest, position, scope),
+                    "map" => parse_map(rest, position, scope),
+                    "filter" => parse_filter(rest, position, scope),
+                    "assoc" => parse_prim2(ast::Op2::Assoc, s, position, rest, scope),
+                    "assq" => parse_prim2(ast::Op2::Assq, s, position, rest, scope),
+                    "member" => parse_prim2(ast::Op2::Member, s, position, rest, scope),
+                    "memq" => parse_prim2(ast::Op2::Memq, s, position, rest, scope),
+                    "make-vector" => parse_prim2(ast::Op2::MakeVector, s, position, rest, scope),
+                    "make-string" => parse_make_string(s, position, rest, scope),
+                    "vector-ref" => parse_prim2(ast::Op2::VectorRef, s, position, rest, scope),
+                    "build-vector" => parse_build_vector(rest, position, scope),
+                    "string-ref" => parse_prim2(ast::Op2::StringRef, s, position, rest, scope),
+                    "string=?" => parse_prim2(ast::Op2::StringEq, s, position, rest, scope),
 
-                    "cons" => parse_prim2(ast::Op2::Cons, position, rest),
-                    "make-vector" => parse_prim2(ast::Op2::MakeVector, position, rest),
-                    "make-string" => parse_prim2(ast::Op2::MakeString, position, rest),
-                    "vector-ref" => parse_prim2(ast::Op2::VectorRef, position, rest),
-                    "string-ref" => parse_prim2(ast::Op2::StringRef, position, rest),
+                    "vector-set!" => parse_prim3(ast::Op3::VectorSet, s, position, rest, scope),
+                    "string-set!" => parse_prim3(ast::Op3::StringSet, s, position, rest, scope),
+                    "substring" => parse_substring(s, position, rest, scope),
 
-                    "vector-set!" => parse_prim3(ast::Op3::VectorSet, position, rest),
+                    "values" => parse_values(rest, position, scope),
+                    "call-with-values" => parse_call_with_values(rest, position, scope),
 
-                    "begin" => parse_begin(rest, position),
-                    "if" => parse_if(rest, position),
-                    "let" => parse_let(rest, position),
-                    "match" => parse_match(rest, position),
+                    "begin" => parse_begin(rest, position, scope),
+                    "if" => parse_if(rest, position, scope),
+                    "when" => parse_when(rest, position, true, scope),
+                    "unless" => parse_when(rest, position, false, scope),
+                    "let" => parse_let(rest, position, scope),
+                    "match" => parse_match(rest, position, scope),
+                    "case" => parse_case(rest, position, scope),
 
-                    "lambda" => parse_lambda(rest, position),
+                    "lambda" => parse_lambda(rest, position, scope),
 
-                    _ => parse_function_application(head, rest),
+                    _ => parse_function_application(head, rest, list_position, scope),
                 },
 
-                _ => parse_function_application(head, rest),
+                _ => parse_function_application(head, rest, list_position, scope),
             }
         }
     }
 }
 
-fn parse_prim0<'a>(operator: ast::Op0, _position: Position, args: &[Expr]) -> Result<ast::Expr> {
+fn parse_prim0<'a>(
+    operator: ast::Op0,
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+) -> Result<ast::Expr> {
     match args {
-        [] => Ok(ast::Expr::Prim0(operator)),
+        [] => Ok(ast::Expr::Prim0(operator, Some(position))),
 
         _ => {
-            let msg = format!("The operator '{:?}' takes 0 arguments.", operator);
+            let msg = format!("The operator '{}' takes 0 arguments.", symbol);
             Err(err(msg.as_str(), args[0].position.clone()))
         }
     }
 }
 
-fn parse_prim1<'a>(operator: ast::Op1, position: Position, args: &[Expr]) -> Result<ast::Expr> {
+/// Parses `(peek-byte k)`, or `(peek-byte)` which is the same as `(peek-byte 0)`.
+fn parse_peek_byte(symbol: &str, position: Position, args: &[Expr], scope: &Scope) -> Result<ast::Expr> {
     match args {
-        [arg] => Ok(ast::Expr::Prim1(operator, Box::new(parse_expr(arg)?))),
+        [] => Ok(ast::Expr::Prim1(
+            ast::Op1::PeekByte,
+            Box::new(ast::Expr::Lit(ast::Lit::Int(0))),
+            Some(position),
+        )),
+        _ => parse_prim1(ast::Op1::PeekByte, symbol, position, args, scope),
+    }
+}
+
+/// Parses `(make-string n c)`, or `(make-string n)` which is the same as `(make-string n #\nul)` as in Racket.
+fn parse_make_string(symbol: &str, position: Position, args: &[Expr], scope: &Scope) -> Result<ast::Expr> {
+    match args {
+        [length] => Ok(ast::Expr::Prim2(
+            ast::Op2::MakeString,
+            Box::new(parse_expr(length, scope)?),
+            Box::new(ast::Expr::Lit(ast::Lit::Char('\0'))),
+            Some(position),
+        )),
+        _ => parse_prim2(ast::Op2::MakeString, symbol, position, args, scope),
+    }
+}
+
+fn parse_prim1<'a>(
+    operator: ast::Op1,
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
+    match args {
+        [arg] => Ok(ast::Expr::Prim1(
+            operator,
+            Box::new(parse_expr(arg, scope)?),
+            Some(position),
+        )),
 
         _ => {
-            let msg = format!("The operator '{:?}' takes 1 argument.", operator);
+            let msg = format!("The operator '{}' takes 1 argument.", symbol);
             Err(err(msg.as_str(), position))
         }
     }
 }
 
-fn parse_prim2<'a>(operator: ast::Op2, position: Position, args: &[Expr]) -> Result<ast::Expr> {
+fn parse_prim2<'a>(
+    operator: ast::Op2,
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
     match args {
         [arg_1, arg_2] => Ok(ast::Expr::Prim2(
             operator,
-            Box::new(parse_expr(arg_1)?),
-            Box::new(parse_expr(arg_2)?),
+            Box::new(parse_expr(arg_1, scope)?),
+            Box::new(parse_expr(arg_2, scope)?),
+            Some(position),
         )),
 
         _ => {
-            let msg = format!("The operator '{:?}' takes 2 arguments.", operator);
+            let msg = format!("The operator '{}' takes 2 arguments.", symbol);
             Err(err(msg.as_str(), position))
         }
     }
 }
 
-fn parse_prim3<'a>(operator: ast::Op3, position: Position, args: &[Expr]) -> Result<ast::Expr> {
+/// Parses a variadic arithmetic operation into a left-associative chain of the binary operator,
+/// e.g. `(- a b c)` into `(- (- a b) c)`.
+/// With a single argument, the operator is applied to the identity and the argument, except that `(- a)` negates `a`.
+/// Only `+` and `*` accept no arguments, which results in the identity.
+fn parse_arithmetic(
+    operator: ast::Op2,
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
+    let identity = match operator {
+        ast::Op2::Mul => ast::Expr::Lit(ast::Lit::Int(1)),
+        _ => ast::Expr::Lit(ast::Lit::Int(0)),
+    };
+
+    match args {
+        [] => match operator {
+            ast::Op2::Add | ast::Op2::Mul => Ok(identity),
+            _ => {
+                let msg = format!("The operator '{}' takes at least 1 argument.", symbol);
+                Err(err(msg.as_str(), position))
+            }
+        },
+
+        [arg] => match operator {
+            ast::Op2::Sub => Ok(ast::Expr::Prim1(
+                ast::Op1::Negate,
+                Box::new(parse_expr(arg, scope)?),
+                Some(position),
+            )),
+            _ => Ok(ast::Expr::Prim2(
+                operator,
+                Box::new(identity),
+                Box::new(parse_expr(arg, scope)?),
+                Some(position),
+            )),
+        },
+
+        [first, rest @ ..] => {
+            let mut expr = parse_expr(first, scope)?;
+            for arg in rest {
+                expr = ast::Expr::Prim2(
+                    operator.clone(),
+                    Box::new(expr),
+                    Box::new(parse_expr(arg, scope)?),
+                    Some(position.clone()),
+                );
+            }
+            Ok(expr)
+        }
+    }
+}
+
+/// Parses a chain of integer comparisons such as `(< a b c)`, which holds iff each adjacent pair holds.
+/// The operands are bound to variables first, so that each of them is evaluated exactly once from left to right.
+fn parse_comparison(
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
+    if args.len() < 2 {
+        let msg = format!("The operator '{}' takes at least 2 arguments.", symbol);
+        return Err(err(msg.as_str(), position));
+    }
+
+    let operands: Vec<ast::Identifier> = args
+        .iter()
+        .map(|arg| ast::Identifier::new(format!("__operand_{}", arg.position.offset).as_str()))
+        .collect();
+
+    let compare = |lhs: &ast::Identifier, rhs: &ast::Identifier| {
+        let lhs = Box::new(ast::Expr::Variable(lhs.clone()));
+        let rhs = Box::new(ast::Expr::Variable(rhs.clone()));
+        let less_than =
+            |lhs, rhs| ast::Expr::Prim2(ast::Op2::LessThan, lhs, rhs, Some(position.clone()));
+        let not = |cond| {
+            ast::Expr::If(ast::If {
+                cond: Box::new(cond),
+                then: Box::new(ast::Expr::Lit(ast::Lit::Bool(false))),
+                els: Box::new(ast::Expr::Lit(ast::Lit::Bool(true))),
+            })
+        };
+
+        match symbol {
+            "<" => less_than(lhs, rhs),
+            ">" => less_than(rhs, lhs),
+            "<=" => not(less_than(rhs, lhs)),
+            ">=" => not(less_than(lhs, rhs)),
+            _ => ast::Expr::Prim2(ast::Op2::IntEq, lhs, rhs, Some(position.clone())),
+        }
+    };
+
+    let pairs: Vec<_> = operands.windows(2).collect();
+    let (last, init) = pairs.split_last().unwrap();
+    let mut body = compare(&last[0], &last[1]);
+    for pair in init.iter().rev() {
+        body = ast::Expr::If(ast::If {
+            cond: Box::new(compare(&pair[0], &pair[1])),
+            then: Box::new(body),
+            els: Box::new(ast::Expr::Lit(ast::Lit::Bool(false))),
+        });
+    }
+
+    for (lhs, arg) in operands.into_iter().zip(args).rev() {
+        body = ast::Expr::Let(ast::Let {
+            binding: ast::Binding {
+                lhs,
+                rhs: Box::new(parse_expr(arg, scope)?),
+            },
+            body: Box::new(body),
+        });
+    }
+
+    Ok(body)
+}
+
+/// Parses `(cons* a b ... z)` into nested `cons` expressions `(cons a (cons b ... z))`,
+/// where the last argument becomes the cdr of the last pair.
+fn parse_cons_star(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    match args.split_last() {
+        Some((last, init)) => {
+            let mut expr = parse_expr(last, scope)?;
+            for arg in init.iter().rev() {
+                expr = ast::Expr::Prim2(
+                    ast::Op2::Cons,
+                    Box::new(parse_expr(arg, scope)?),
+                    Box::new(expr),
+                    Some(position.clone()),
+                );
+            }
+            Ok(expr)
+        }
+
+        None => Err(err(
+            "The 'cons*' expression takes at least 1 argument.",
+            position,
+        )),
+    }
+}
+
+/// Parses `(printf fmt args ...)`, where the format is a string literal in which each `~a` is replaced by
+/// the next argument as by `display`.
+/// All arguments are evaluated in order before anything is written, and bound to variables
+/// whose values are written by `display` between the `write-string` of the text,
+/// e.g. `(printf "x=~a!" x)` into `(let ((a x)) (begin (write-string "x=") (begin (display a) (begin (write-string "!") void))))`.
+fn parse_printf(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    let Some((Expr { kind: ExprKind::Atom(Atom::String(format)), .. }, args)) = args.split_first() else {
+        return Err(err(
+            "The 'printf' expression should be of the form `(printf <string literal> <args>...)`",
+            position,
+        ));
+    };
+
+    fn write_text(text: &str, position: &Position, outputs: &mut Vec<ast::Expr>) -> Result<()> {
+        if text.contains('~') {
+            return Err(err("The format of 'printf' only supports `~a`.", position.clone()));
+        }
+        if !text.is_empty() {
+            outputs.push(ast::Expr::Prim1(
+                ast::Op1::WriteString,
+                Box::new(ast::Expr::Lit(ast::Lit::String(text.to_string()))),
+                Some(position.clone()),
+            ));
+        }
+        Ok(())
+    }
+
+    let mut segments = format.split("~a");
+    let mut outputs = vec![];
+    let mut bindings = vec![];
+    write_text(segments.next().unwrap_or_default(), &position, &mut outputs)?;
+    let mut args = args.iter();
+    for (i, text) in segments.enumerate() {
+        let Some(arg) = args.next() else {
+            return Err(err("The format of 'printf' has more `~a` than the arguments.", position));
+        };
+        let variable = ast::Identifier::new(format!("__printf_{}_{}", i, position.offset).as_str());
+        outputs.push(ast::Expr::Prim1(
+            ast::Op1::Display,
+            Box::new(ast::Expr::Variable(variable.clone())),
+            Some(position.clone()),
+        ));
+        bindings.push(ast::Binding {
+            lhs: variable,
+            rhs: Box::new(parse_expr(arg, scope)?),
+        });
+        write_text(text, &position, &mut outputs)?;
+    }
+    if args.next().is_some() {
+        return Err(err("The format of 'printf' has fewer `~a` than the arguments.", position));
+    }
+
+    let body = outputs
+        .into_iter()
+        .rev()
+        .fold(ast::Expr::Lit(ast::Lit::Void), |rest, output| {
+            ast::Expr::Begin(Box::new(output), Box::new(rest))
+        });
+    Ok(bindings.into_iter().rev().fold(body, |body, binding| {
+        ast::Expr::Let(ast::Let {
+            binding,
+            body: Box::new(body),
+        })
+    }))
+}
+
+/// Parses `(const x)` into `(let ((v x)) (lambda (_) v))`,
+/// a function which ignores its argument and always returns the value of `x`.
+fn parse_const(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    match args {
+        [value] => {
+            let variable = ast::Identifier::new(format!("__const_{}", position.offset).as_str());
+            Ok(ast::Expr::Let(ast::Let {
+                binding: ast::Binding {
+                    lhs: variable.clone(),
+                    rhs: Box::new(parse_expr(value, scope)?),
+                },
+                body: Box::new(ast::Expr::Lambda(ast::Lambda {
+                    id: ast::Identifier::new(format!("__const_lambda_{}", position.offset).as_str()),
+                    params: vec![ast::Identifier::new("_")],
+                    body: Box::new(ast::Expr::Variable(variable)),
+                })),
+            }))
+        }
+
+        _ => Err(err("The operator 'const' takes 1 argument.", position)),
+    }
+}
+
+/// Parses `(substring s start end)`, or `(substring s start)` which takes the characters up to the end.
+///
+/// The latter is desugared into `(let ((s' s)) (substring s' start (string-length s')))`,
+/// so that the string is evaluated only once.
+fn parse_substring(
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
+    match args {
+        [string, start] => {
+            let name = ast::Identifier::new(format!("__string_{}", position.offset).as_str());
+            let variable = || Box::new(ast::Expr::Variable(name.clone()));
+
+            let length =
+                ast::Expr::Prim1(ast::Op1::StringLength, variable(), Some(position.clone()));
+            let substring = ast::Expr::Prim3(
+                ast::Op3::Substring,
+                variable(),
+                Box::new(parse_expr(start, scope)?),
+                Box::new(length),
+                Some(position.clone()),
+            );
+
+            Ok(ast::Expr::Let(ast::Let {
+                binding: ast::Binding {
+                    lhs: name.clone(),
+                    rhs: Box::new(parse_expr(string, scope)?),
+                },
+                body: Box::new(substring),
+            }))
+        }
+
+        _ => parse_prim3(ast::Op3::Substring, symbol, position, args, scope),
+    }
+}
+
+/// Parses `(values a b)` into a fresh vector holding the two values,
+/// which is supposed to be unpacked by `call-with-values`.
+fn parse_values(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    match args {
+        [first, second] => {
+            let values = ast::Identifier::new(format!("__values_{}", position.offset).as_str());
+            let variable = || Box::new(ast::Expr::Variable(values.clone()));
+            let int = |i| Box::new(ast::Expr::Lit(ast::Lit::Int(i)));
+
+            let vector = ast::Expr::Prim2(
+                ast::Op2::MakeVector,
+                int(2),
+                Box::new(parse_expr(first, scope)?),
+                Some(position.clone()),
+            );
+            let set_second = ast::Expr::Prim3(
+                ast::Op3::VectorSet,
+                variable(),
+                int(1),
+                Box::new(parse_expr(second, scope)?),
+                Some(position.clone()),
+            );
+
+            Ok(ast::Expr::Let(ast::Let {
+                binding: ast::Binding {
+                    lhs: values.clone(),
+                    rhs: Box::new(vector),
+                },
+                body: Box::new(ast::Expr::Begin(Box::new(set_second), variable())),
+            }))
+        }
+
+        _ => Err(err(
+            "The 'values' expression takes exactly 2 arguments.",
+            position,
+        )),
+    }
+}
+
+/// Parses `(call-with-values producer consumer)`, which calls the producer without arguments
+/// and applies the consumer to the two values packed by `values`.
+///
+/// The consumer is evaluated before the producer is called, as the arguments of an application are.
+fn parse_call_with_values(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    match args {
+        [producer, consumer] => {
+            let values = ast::Identifier::new(format!("__values_{}", position.offset).as_str());
+            let elements: Vec<ast::Identifier> = (0..2)
+                .map(|i| ast::Identifier::new(format!("__value_{}_{}", i, position.offset).as_str()))
+                .collect();
+
+            // A symbol has no effects, so it is applied as a form rather than a value, so that it may be
+            // a primitive like `+`. Any other consumer is bound to a name first.
+            let consumer_binding = match &consumer.kind {
+                ExprKind::Atom(Atom::Symbol(_)) => None,
+                _ => {
+                    let name = ast::Identifier::new(format!("__consumer_{}", position.offset).as_str());
+                    Some((name, parse_expr(consumer, scope)?))
+                }
+            };
+            let function = match &consumer_binding {
+                Some((name, _)) => Expr::symbol(&name.0, position.clone()),
+                None => consumer.clone(),
+            };
+            let consumer_args = elements.iter().map(|e| Expr::symbol(&e.0, position.clone()));
+            let application = Expr::list(
+                std::iter::once(function).chain(consumer_args).collect(),
+                position.clone(),
+            );
+            let mut body_vars = elements.clone();
+            body_vars.extend(consumer_binding.iter().map(|(name, _)| name.clone()));
+            let mut body = parse_expr(&application, &scope.with_vars(&body_vars))?;
+
+            for (i, element) in elements.into_iter().enumerate().rev() {
+                let rhs = ast::Expr::Prim2(
+                    ast::Op2::VectorRef,
+                    Box::new(ast::Expr::Variable(values.clone())),
+                    Box::new(ast::Expr::Lit(ast::Lit::Int(i as i64))),
+                    Some(position.clone()),
+                );
+                body = ast::Expr::Let(ast::Let {
+                    binding: ast::Binding {
+                        lhs: element,
+                        rhs: Box::new(rhs),
+                    },
+                    body: Box::new(body),
+                });
+            }
+
+            let call = ast::Expr::App(ast::App {
+                function: Box::new(parse_expr(producer, scope)?),
+                args: vec![],
+                position: Some(position.clone()),
+            });
+            let expr = ast::Expr::Let(ast::Let {
+                binding: ast::Binding {
+                    lhs: values,
+                    rhs: Box::new(call),
+                },
+                body: Box::new(body),
+            });
+            Ok(match consumer_binding {
+                Some((name, rhs)) => ast::Expr::Let(ast::Let {
+                    binding: ast::Binding {
+                        lhs: name,
+                        rhs: Box::new(rhs),
+                    },
+                    body: Box::new(expr),
+                }),
+                None => expr,
+            })
+        }
+
+        _ => Err(err(
+            "The 'call-with-values' expression takes exactly 2 arguments.",
+            position,
+        )),
+    }
+}
+
+fn parse_prim3<'a>(
+    operator: ast::Op3,
+    symbol: &str,
+    position: Position,
+    args: &[Expr],
+    scope: &Scope,
+) -> Result<ast::Expr> {
     match args {
         [arg_1, arg_2, arg_3] => Ok(ast::Expr::Prim3(
             operator,
-            Box::new(parse_expr(arg_1)?),
-            Box::new(parse_expr(arg_2)?),
-            Box::new(parse_expr(arg_3)?),
+            Box::new(parse_expr(arg_1, scope)?),
+            Box::new(parse_expr(arg_2, scope)?),
+            Box::new(parse_expr(arg_3, scope)?),
+            Some(position),
         )),
 
         _ => {
-            let msg = format!("The operator '{:?}' takes 3 arguments.", operator);
+            let msg = format!("The operator '{}' takes 3 arguments.", symbol);
             Err(err(msg.as_str(), position))
         }
     }
 }
 
-fn parse_begin<'a>(args: &[Expr], position: Position) -> Result<ast::Expr> {
+fn parse_begin<'a>(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
     match args {
         [first, second] => Ok(ast::Expr::Begin(
-            Box::new(parse_expr(first)?),
-            Box::new(parse_expr(second)?),
+            Box::new(parse_expr(first, scope)?),
+            Box::new(parse_expr(second, scope)?),
         )),
 
         _ => {
@@ -173,27 +706,77 @@ fn parse_begin<'a>(args: &[Expr], position: Position) -> Result<ast::Expr> {
     }
 }
 
-fn parse_if<'a>(args: &[Expr], position: Position) -> Result<ast::Expr> {
+fn parse_if<'a>(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
     match args {
         [cond, then, els] => Ok(ast::Expr::If(ast::If {
-            cond: Box::new(parse_expr(cond)?),
-            then: Box::new(parse_expr(then)?),
-            els: Box::new(parse_expr(els)?),
+            cond: Box::new(parse_expr(cond, scope)?),
+            then: Box::new(parse_expr(then, scope)?),
+            els: Box::new(parse_expr(els, scope)?),
+        })),
+
+        // `(if c then)` evaluates to void when the condition is false.
+        [cond, then] => Ok(ast::Expr::If(ast::If {
+            cond: Box::new(parse_expr(cond, scope)?),
+            then: Box::new(parse_expr(then, scope)?),
+            els: Box::new(ast::Expr::Lit(ast::Lit::Void)),
         })),
 
+        _ => Err(err("The 'if' expression takes 2 or 3 arguments.", position)),
+    }
+}
+
+/// Parses `(when <cond> <body> ...)` if `expected` is true, or `(unless <cond> <body> ...)` otherwise.
+/// The body is evaluated in sequence iff the condition is `expected`, and the result is void otherwise.
+fn parse_when(
+    args: &[Expr],
+    position: Position,
+    expected: bool,
+    scope: &Scope,
+) -> Result<ast::Expr> {
+    match args {
+        [cond, body @ ..] if !body.is_empty() => {
+            let body = parse_sequence(body, scope)?;
+            let void = ast::Expr::Lit(ast::Lit::Void);
+            let (then, els) = if expected { (body, void) } else { (void, body) };
+
+            Ok(ast::Expr::If(ast::If {
+                cond: Box::new(parse_expr(cond, scope)?),
+                then: Box::new(then),
+                els: Box::new(els),
+            }))
+        }
+
         _ => {
-            let msg = format!("The 'if' expression takes 3 arguments.");
+            let keyword = if expected { "when" } else { "unless" };
+            let msg = format!(
+                "The '{}' expression should be of the form `({} <cond> <body> ...)`",
+                keyword, keyword
+            );
             Err(err(msg.as_str(), position))
         }
     }
 }
 
-fn parse_let<'a>(args: &[Expr], position: Position) -> Result<ast::Expr> {
+/// Parses non-empty expressions evaluated in order into nested `begin`, resulting in the last value.
+fn parse_sequence(exprs: &[Expr], scope: &Scope) -> Result<ast::Expr> {
+    let (last, init) = exprs.split_last().expect("the sequence must not be empty");
+    let mut expr = parse_expr(last, scope)?;
+    for first in init.iter().rev() {
+        expr = ast::Expr::Begin(Box::new(parse_expr(first, scope)?), Box::new(expr));
+    }
+    Ok(expr)
+}
+
+fn parse_let<'a>(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
     match args {
-        [bindings, body] => Ok(ast::Expr::Let(ast::Let {
-            binding: parse_variable_bindings(bindings)?,
-            body: Box::new(parse_expr(body)?),
-        })),
+        [bindings, body] => {
+            let binding = parse_variable_bindings(bindings, scope)?;
+            let body = parse_expr(body, &scope.with_vars([&binding.lhs]))?;
+            Ok(ast::Expr::Let(ast::Let {
+                binding,
+                body: Box::new(body),
+            }))
+        }
         _ => {
             let msg = format!("`let` expression should be of the form `(let <bindings> <body>)`");
             Err(err(msg.as_str(), position))
@@ -203,10 +786,10 @@ fn parse_let<'a>(args: &[Expr], position: Position) -> Result<ast::Expr> {
 
 /// # Arguments
 /// * `expr` - Should be a s-expression of the form `((<lhs> <rhs>))`.
-fn parse_variable_bindings(expr: &Expr) -> Result<ast::Binding> {
+fn parse_variable_bindings(expr: &Expr, scope: &Scope) -> Result<ast::Binding> {
     if let ExprKind::List(List(elems)) = &expr.kind {
         if let [binding] = elems.as_slice() {
-            return parse_variable_binding(binding);
+            return parse_variable_binding(binding, scope);
         }
     }
     Err(err(
@@ -217,12 +800,12 @@ fn parse_variable_bindings(expr: &Expr) -> Result<ast::Binding> {
 
 /// # Arguments
 /// * `list` - Should be a s-expression of the form `(<lhs> <rhs>)`.
-fn parse_variable_binding(expr: &Expr) -> Result<ast::Binding> {
+fn parse_variable_binding(expr: &Expr, scope: &Scope) -> Result<ast::Binding> {
     if let ExprKind::List(List(elems)) = &expr.kind {
         if let [lhs, rhs] = elems.as_slice() {
             return Ok(ast::Binding {
                 lhs: parse_identifier(lhs)?,
-                rhs: Box::new(parse_expr(rhs)?),
+                rhs: Box::new(parse_expr(rhs, scope)?),
             });
         }
     }
@@ -232,13 +815,13 @@ fn parse_variable_binding(expr: &Expr) -> Result<ast::Binding> {
     ))
 }
 
-fn parse_match(args: &[Expr], position: Position) -> Result<ast::Expr> {
+fn parse_match(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
     match args {
         [expr, arms @ ..] => Ok(ast::Expr::Match(ast::Match {
-            expr: Box::new(parse_expr(expr)?),
+            expr: Box::new(parse_expr(expr, scope)?),
             arms: arms
                 .iter()
-                .map(parse_match_arm)
+                .map(|arm| parse_match_arm(arm, scope))
                 .collect::<Result<Vec<_>>>()?,
         })),
 
@@ -250,25 +833,98 @@ fn parse_match(args: &[Expr], position: Position) -> Result<ast::Expr> {
     }
 }
 
-fn parse_match_arm(expr: &Expr) -> Result<ast::Arm> {
+/// Parses a match arm, whose body may consist of many expressions evaluated in order as in `begin`.
+fn parse_match_arm(expr: &Expr, scope: &Scope) -> Result<ast::Arm> {
     if let ExprKind::List(List(elems)) = &expr.kind {
-        if let [pattern, body] = elems.as_slice() {
-            return Ok(ast::Arm {
-                pattern: parse_pattern(pattern)?,
-                body: Box::new(parse_expr(body)?),
-            });
+        if let [pattern, body @ ..] = elems.as_slice() {
+            if !body.is_empty() {
+                let pattern = parse_pattern(pattern, scope)?;
+                let scope = &scope.with_vars(&pattern.variables());
+                return Ok(ast::Arm {
+                    pattern,
+                    body: Box::new(parse_sequence(body, scope)?),
+                    position: Some(expr.position.clone()),
+                });
+            }
         }
     }
     Err(err(
-        "Match arm should be of the form `(<pattern> <body>)`",
+        "Match arm should be of the form `(<pattern> <body> ...)`",
         expr.position.clone(),
     ))
 }
 
-fn parse_pattern(expr: &Expr) -> Result<ast::Pattern> {
+/// Parses `(case <expr> [(<datum> ...) <body> ...] ... [else <body> ...])`,
+/// where the `else` clause may only come last.
+fn parse_case(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    let Some((expr, clauses)) = args.split_first() else {
+        return Err(err(
+            "The 'case' expression should be of the form `(case <expr> <clauses>...)`",
+            position,
+        ));
+    };
+
+    let mut parsed_clauses = vec![];
+    let mut els = ast::Expr::Lit(ast::Lit::Void);
+    for (i, clause) in clauses.iter().enumerate() {
+        let invalid = || {
+            err(
+                "Case clause should be of the form `((<datum> ...) <body> ...)` or `(else <body> ...)`",
+                clause.position.clone(),
+            )
+        };
+        let ExprKind::List(List(elems)) = &clause.kind else {
+            return Err(invalid());
+        };
+        let [head, body @ ..] = elems.as_slice() else {
+            return Err(invalid());
+        };
+        if body.is_empty() {
+            return Err(invalid());
+        }
+
+        match &head.kind {
+            ExprKind::Atom(Atom::Symbol(s)) if s == "else" => {
+                if i + 1 != clauses.len() {
+                    return Err(err("The 'else' clause must be the last one.", clause.position.clone()));
+                }
+                els = parse_sequence(body, scope)?;
+            }
+            ExprKind::List(List(datums)) => parsed_clauses.push(ast::CaseClause {
+                datums: datums.iter().map(parse_datum).collect::<Result<_>>()?,
+                body: Box::new(parse_sequence(body, scope)?),
+            }),
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(ast::Expr::Case(ast::Case {
+        expr: Box::new(parse_expr(expr, scope)?),
+        clauses: parsed_clauses,
+        els: Box::new(els),
+    }))
+}
+
+/// Parses a datum of a `case` clause, which is a literal as written.
+fn parse_datum(expr: &Expr) -> Result<ast::Lit> {
+    match &expr.kind {
+        ExprKind::Atom(Atom::Integer(n)) => Ok(ast::Lit::Int(*n)),
+        ExprKind::Atom(Atom::Boolean(b)) => Ok(ast::Lit::Bool(*b)),
+        ExprKind::Atom(Atom::Character(c)) => Ok(ast::Lit::Char(*c)),
+        ExprKind::Atom(Atom::String(s)) => Ok(ast::Lit::String(s.to_owned())),
+        ExprKind::List(List(elems)) if elems.is_empty() => Ok(ast::Lit::EmptyList),
+        _ => Err(err(
+            "A datum should be an integer, a boolean, a character, a string, or the empty list.",
+            expr.position.clone(),
+        )),
+    }
+}
+
+/// Parses a pattern, where the scope is used for the expressions in the predicate patterns.
+fn parse_pattern(expr: &Expr, scope: &Scope) -> Result<ast::Pattern> {
     match &expr.kind {
         ExprKind::Atom(atom) => Ok(parse_atom_pattern(atom)),
-        ExprKind::List(List(elems)) => parse_complex_pattern(elems),
+        ExprKind::List(List(elems)) => parse_complex_pattern(elems, scope),
     }
 }
 
@@ -288,7 +944,7 @@ fn parse_atom_pattern(atom: &Atom) -> ast::Pattern {
     }
 }
 
-fn parse_complex_pattern(elems: &[Expr]) -> Result<ast::Pattern> {
+fn parse_complex_pattern(elems: &[Expr], scope: &Scope) -> Result<ast::Pattern> {
     match elems {
         [] => Ok(ast::Pattern::Lit(ast::Lit::EmptyList)),
 
@@ -296,14 +952,17 @@ fn parse_complex_pattern(elems: &[Expr]) -> Result<ast::Pattern> {
             if let ExprKind::Atom(Atom::Symbol(s)) = &head.kind {
                 match (s.as_str(), tail) {
                     ("cons", [car, cdr]) => Ok(ast::Pattern::Cons(
-                        Box::new(parse_pattern(car)?),
-                        Box::new(parse_pattern(cdr)?),
+                        Box::new(parse_pattern(car, scope)?),
+                        Box::new(parse_pattern(cdr, scope)?),
                     )),
-                    ("box", [expr]) => Ok(ast::Pattern::Box(Box::new(parse_pattern(expr)?))),
+                    ("box", [expr]) => Ok(ast::Pattern::Box(Box::new(parse_pattern(expr, scope)?))),
                     ("and", [left, right]) => Ok(ast::Pattern::And(
-                        Box::new(parse_pattern(left)?),
-                        Box::new(parse_pattern(right)?),
+                        Box::new(parse_pattern(left, scope)?),
+                        Box::new(parse_pattern(right, scope)?),
                     )),
+                    ("?", [function, patterns @ ..]) => {
+                        parse_predicate_pattern(function, patterns, head.position.clone(), scope)
+                    }
 
                     _ => Err(err("Invalid pattern syntax.", head.position.clone())),
                 }
@@ -314,26 +973,101 @@ fn parse_complex_pattern(elems: &[Expr]) -> Result<ast::Pattern> {
     }
 }
 
+/// Parses `(? f pattern ...)`, which matches if `(f x)` is not false for the value `x`
+/// and all the patterns match the value.
+///
+/// As with the derived forms over lists, `f` may also name a primitive such as `string?`.
+/// It is evaluated in the scope outside the pattern, every time the value is tested.
+fn parse_predicate_pattern(
+    function: &Expr,
+    patterns: &[Expr],
+    position: Position,
+    scope: &Scope,
+) -> Result<ast::Pattern> {
+    let variable = ast::Identifier::new(format!("__value_{}", position.offset).as_str());
+    let application = Expr::list(
+        vec![
+            function.clone(),
+            Expr::symbol(&variable.0, position.clone()),
+        ],
+        position,
+    );
+    let test = parse_expr(&application, &scope.with_vars([&variable]))?;
+
+    let mut pattern = ast::Pattern::Predicate(variable, Box::new(test));
+    for subpattern in patterns {
+        pattern = ast::Pattern::And(
+            Box::new(pattern),
+            Box::new(parse_pattern(subpattern, scope)?),
+        );
+    }
+    Ok(pattern)
+}
+
 fn parse_function_application<'a>(
     function: &'a Expr,
     arguments: impl IntoIterator<Item = &'a Expr>,
+    application_position: Position,
+    scope: &Scope,
 ) -> Result<ast::Expr> {
+    let parsed_function = parse_expr(function, scope)?;
+
+    if is_never_function(&parsed_function) {
+        return Err(match function.kind {
+            // Likely an extra pair of parentheses around the expression, as in `((add1 1))`.
+            ExprKind::List(_) => err(
+                "Attempted to call the result of an expression as a function, but it never returns a function. Is there an extra pair of parentheses?",
+                application_position,
+            ),
+            ExprKind::Atom(_) => err(
+                "Attempted to call a literal as a function.",
+                function.position.clone(),
+            ),
+        });
+    }
+
     Ok(ast::Expr::App(ast::App {
-        function: Box::new(parse_expr(function)?),
+        function: Box::new(parsed_function),
         args: arguments
             .into_iter()
-            .map(parse_expr)
+            .map(|arg| parse_expr(arg, scope))
             .collect::<Result<Vec<_>>>()?,
+        position: Some(function.position.clone()),
     }))
 }
 
+/// Returns true if the expression definitely does not evaluate to a function.
+/// It is conservative, e.g. `car` may return a function, so `((car x))` is not rejected.
+fn is_never_function(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Eof | ast::Expr::Lit(_) | ast::Expr::Prim0(..) | ast::Expr::Prim3(..) => true,
+        ast::Expr::Prim1(op, ..) => !matches!(
+            op,
+            ast::Op1::Identity | ast::Op1::Car | ast::Op1::Cdr | ast::Op1::Unbox
+        ),
+        ast::Expr::Prim2(op, ..) => !matches!(op, ast::Op2::VectorRef),
+        _ => false,
+    }
+}
+
 /// Parse a function definition of the form: `(define (<name> <param> <param> ...) <body>)`
-fn parse_function_definition(expr: &Expr) -> Result<ast::FunctionDefinition> {
+fn parse_function_definition(expr: &Expr, scope: &Scope) -> Result<ast::FunctionDefinition> {
     if let ExprKind::List(List(elems)) = &expr.kind {
         if let [define, signature, body] = elems.as_slice() {
             parse_define_keyword(define)?;
+
+            // Value definitions are not supported, since their initialization order would matter
+            // unlike functions, which may refer to each other freely.
+            if let ExprKind::Atom(Atom::Symbol(name)) = &signature.kind {
+                let msg = format!(
+                    "Only functions can be defined. Use `let` for the value `{}`, or define it as `(define ({}) ...)`.",
+                    name, name
+                );
+                return Err(err(&msg, signature.position.clone()));
+            }
+
             let signature = parse_function_signature(signature)?;
-            let body = parse_expr(&body)?;
+            let body = parse_expr(body, &scope.with_vars(&signature.params))?;
             return Ok(ast::FunctionDefinition { signature, body });
         }
     }
@@ -343,6 +1077,30 @@ fn parse_function_definition(expr: &Expr) -> Result<ast::FunctionDefinition> {
     ))
 }
 
+/// Returns true if the expression looks like `(define ...)`.
+fn is_definition(expr: &Expr) -> bool {
+    match &expr.kind {
+        ExprKind::List(List(elems)) => matches!(
+            elems.first().map(|head| &head.kind),
+            Some(ExprKind::Atom(Atom::Symbol(s))) if s == "define"
+        ),
+        _ => false,
+    }
+}
+
+/// Returns the name of the function defined by `(define (<name> ...) <body>)`.
+/// Malformed definitions are reported by [parse_function_definition] instead.
+fn function_name(expr: &Expr) -> Option<ast::Identifier> {
+    if let ExprKind::List(List(elems)) = &expr.kind {
+        if let [_, signature, _] = elems.as_slice() {
+            if let ExprKind::List(List(signature)) = &signature.kind {
+                return parse_identifier(signature.first()?).ok();
+            }
+        }
+    }
+    None
+}
+
 fn parse_define_keyword(expr: &Expr) -> Result<()> {
     match &expr.kind {
         ExprKind::Atom(Atom::Symbol(s)) if s == "define" => Ok(()),
@@ -358,10 +1116,7 @@ fn parse_function_signature(expr: &Expr) -> Result<ast::FunctionSignature> {
     if let ExprKind::List(List(elems)) = &expr.kind {
         if let [name, params @ ..] = elems.as_slice() {
             let name = parse_identifier(name)?;
-            let params = params
-                .iter()
-                .map(parse_identifier)
-                .collect::<Result<Vec<_>>>()?;
+            let params = parse_params(params)?;
 
             return Ok(ast::FunctionSignature { name, params });
         }
@@ -382,14 +1137,18 @@ fn parse_identifier(expr: &Expr) -> Result<ast::Identifier> {
     }
 }
 
-fn parse_lambda(args: &[Expr], position: Position) -> Result<ast::Expr> {
+fn parse_lambda(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
     match args {
-        [params, body] => Ok(ast::Expr::Lambda(ast::Lambda {
-            // Unique identifier
-            id: ast::Identifier::new(format!("__lambda_{}", position.offset).as_str()),
-            params: parse_lambda_params(params)?,
-            body: Box::new(parse_expr(body)?),
-        })),
+        [params, body] => {
+            let params = parse_lambda_params(params)?;
+            let body = parse_expr(body, &scope.with_vars(&params))?;
+            Ok(ast::Expr::Lambda(ast::Lambda {
+                // Unique identifier
+                id: ast::Identifier::new(format!("__lambda_{}", position.offset).as_str()),
+                params,
+                body: Box::new(body),
+            }))
+        }
         _ => {
             let msg =
                 format!("Lambda expression should be of the form `(lambda <params> <body>)`");
@@ -400,10 +1159,7 @@ fn parse_lambda(args: &[Expr], position: Position) -> Result<ast::Expr> {
 
 fn parse_lambda_params(expr: &Expr) -> Result<Vec<ast::Identifier>> {
     if let ExprKind::List(List(elems)) = &expr.kind {
-        return elems
-            .iter()
-            .map(parse_identifier)
-            .collect::<Result<Vec<_>>>();
+        return parse_params(elems);
     }
     Err(err(
         "Expected a list of lambda parameters.",
@@ -411,9 +1167,48 @@ fn parse_lambda_params(expr: &Expr) -> Result<Vec<ast::Identifier>> {
     ))
 }
 
+/// Parses the parameters of a function, which must be distinct from each other.
+fn parse_params(exprs: &[Expr]) -> Result<Vec<ast::Identifier>> {
+    let mut params: Vec<ast::Identifier> = vec![];
+    for expr in exprs {
+        let param = parse_identifier(expr)?;
+        if params.contains(&param) {
+            let msg = format!("Duplicate parameter `{}`.", param.0);
+            return Err(err(msg.as_str(), expr.position.clone()));
+        }
+        params.push(param);
+    }
+    Ok(params)
+}
+
 fn err(msg: &str, position: Position) -> AstPasringError {
     AstPasringError {
         msg: msg.to_owned(),
         position,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_of_applications() {
+        let program = crate::mylang::parse("(add1 (car (f 1)))").unwrap();
+
+        let ast::Expr::Prim1(_, arg, position) = program.expr else {
+            panic!("Expected a primitive application");
+        };
+        assert_eq!(position, Some(Position::new(1)));
+
+        let ast::Expr::Prim1(_, arg, position) = *arg else {
+            panic!("Expected a primitive application");
+        };
+        assert_eq!(position, Some(Position::new(7)));
+
+        let ast::Expr::App(app) = *arg else {
+            panic!("Expected a function application");
+        };
+        assert_eq!(app.position, Some(Position::new(12)));
+    }
+}
@@ -0,0 +1,325 @@
+//! Derived forms such as `for-each` over lists and `build-vector`, which are desugared into loops.
+//!
+//! A lambda expression cannot refer to itself, so each loop is a lambda taking itself as the first argument:
+//!
+//! ```racket
+//! (let ((loop (lambda (loop xs) ... (loop loop (cdr xs)) ...)))
+//!   (loop loop <list>))
+//! ```
+
+use crate::mylang::{
+    ast,
+    document::Position,
+    s_expression::{Atom, Expr, ExprKind},
+};
+
+use super::{err, parse_expr, Result, Scope};
+
+/// Parses `(list a b ...)` into `(cons a (cons b ... ()))`.
+pub fn parse_list_form(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    let mut expr = ast::Expr::Lit(ast::Lit::EmptyList);
+    for arg in args.iter().rev() {
+        expr = ast::Expr::Prim2(
+            ast::Op2::Cons,
+            Box::new(parse_expr(arg, scope)?),
+            Box::new(expr),
+            Some(position.clone()),
+        );
+    }
+    Ok(expr)
+}
+
+/// Parses `(for-each f xs)`, which applies `f` to each element of the list in order and returns void.
+pub fn parse_for_each(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    // (begin (f x) (loop loop (cdr xs)))
+    let empty = ast::Expr::Lit(ast::Lit::Void);
+    parse_list_loop("for-each", args, position, scope, empty, |step| {
+        ast::Expr::Begin(Box::new(step.call), Box::new(step.rest))
+    })
+}
+
+/// Parses `(map f xs)`, which returns a new list of `(f x)` for each element `x` of the list.
+///
+/// The result is consed on the way back from the recursion, so that it keeps the order without reversing,
+/// and `f` is applied to the elements in order.
+pub fn parse_map(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    // (cons (f x) (loop loop (cdr xs)))
+    let empty = ast::Expr::Lit(ast::Lit::EmptyList);
+    let cons_position = position.clone();
+    parse_list_loop("map", args, position, scope, empty, |step| {
+        ast::Expr::Prim2(
+            ast::Op2::Cons,
+            Box::new(step.call),
+            Box::new(step.rest),
+            Some(cons_position),
+        )
+    })
+}
+
+/// Parses `(filter f xs)`, which returns a new list of the elements `x` for which `(f x)` is not `#f`,
+/// keeping the order.
+pub fn parse_filter(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    // (if (f x) (cons x (loop loop (cdr xs))) (loop loop (cdr xs)))
+    let empty = ast::Expr::Lit(ast::Lit::EmptyList);
+    let cons_position = position.clone();
+    parse_list_loop("filter", args, position, scope, empty, |step| {
+        ast::Expr::If(ast::If {
+            cond: Box::new(step.call),
+            then: Box::new(ast::Expr::Prim2(
+                ast::Op2::Cons,
+                Box::new(step.element),
+                Box::new(step.rest.clone()),
+                Some(cons_position),
+            )),
+            els: Box::new(step.rest),
+        })
+    })
+}
+
+/// Parses `(build-vector n f)` into a loop which fills a new vector of the length `n`,
+/// setting each element at the index `i` to `(f i)`:
+///
+/// ```racket
+/// (let ((n <n>))
+///   (let ((loop (lambda (loop v i)
+///                 (if (= i n)
+///                     v
+///                     (begin (vector-set! v i (f i))
+///                            (loop loop v (add1 i)))))))
+///     (loop loop (make-vector n 0) 0)))
+/// ```
+pub fn parse_build_vector(args: &[Expr], position: Position, scope: &Scope) -> Result<ast::Expr> {
+    match args {
+        [length, function] => {
+            let names = Names::new(&position);
+            let [lp, n, v, i] = ["loop", "n", "v", "i"].map(|s| names.get(s));
+            let length = parse_expr(length, scope)?;
+            let function = FunctionArgument::new(function, names.get("f"), scope)?;
+
+            let call = function.apply(&[&i], &position, &scope.with_vars([&lp, &n, &v, &i]))?;
+            let set_element = ast::Expr::Prim3(
+                ast::Op3::VectorSet,
+                Box::new(variable(&v)),
+                Box::new(variable(&i)),
+                Box::new(call),
+                Some(position.clone()),
+            );
+            let next = ast::Expr::Prim1(
+                ast::Op1::Add1,
+                Box::new(variable(&i)),
+                Some(position.clone()),
+            );
+            let body = ast::Expr::If(ast::If {
+                cond: Box::new(ast::Expr::Prim2(
+                    ast::Op2::IntEq,
+                    Box::new(variable(&i)),
+                    Box::new(variable(&n)),
+                    Some(position.clone()),
+                )),
+                then: Box::new(variable(&v)),
+                els: Box::new(ast::Expr::Begin(
+                    Box::new(set_element),
+                    Box::new(recur(&lp, vec![variable(&v), next], &position)),
+                )),
+            });
+
+            let vector = ast::Expr::Prim2(
+                ast::Op2::MakeVector,
+                Box::new(variable(&n)),
+                Box::new(ast::Expr::Lit(ast::Lit::Int(0))),
+                Some(position.clone()),
+            );
+            let start = vec![vector, ast::Expr::Lit(ast::Lit::Int(0))];
+            let lp = run_loop(&lp, vec![v, i], body, start, &position);
+            Ok(bind(&n, length, function.bind(lp)))
+        }
+
+        _ => Err(err(
+            "The 'build-vector' expression takes exactly 2 arguments.",
+            position,
+        )),
+    }
+}
+
+/// The expressions given to each step of a loop over a list, in the scope of the current element.
+struct Step {
+    /// The current element `x`.
+    element: ast::Expr,
+
+    /// The application `(f x)` of the function to the current element.
+    call: ast::Expr,
+
+    /// The call `(loop loop (cdr xs))` of the loop over the rest of the list.
+    rest: ast::Expr,
+}
+
+/// Parses `(form f xs)` into a loop over the list, which returns `empty` at the end of the list
+/// and the expression built by `step` for each element:
+///
+/// ```racket
+/// (if (eq? xs ()) empty (let ((x (car xs))) step))
+/// ```
+fn parse_list_loop(
+    form: &str,
+    args: &[Expr],
+    position: Position,
+    scope: &Scope,
+    empty: ast::Expr,
+    step: impl FnOnce(Step) -> ast::Expr,
+) -> Result<ast::Expr> {
+    match args {
+        [function, list] => {
+            let names = Names::new(&position);
+            let [lp, xs, x] = ["loop", "xs", "x"].map(|s| names.get(s));
+            let function = FunctionArgument::new(function, names.get("f"), scope)?;
+
+            let call = function.apply(&[&x], &position, &scope.with_vars([&lp, &xs, &x]))?;
+            let element = step(Step {
+                element: variable(&x),
+                call,
+                rest: recur(&lp, vec![cdr(&xs, &position)], &position),
+            });
+            let body = ast::Expr::If(ast::If {
+                cond: Box::new(is_empty(&xs, &position)),
+                then: Box::new(empty),
+                els: Box::new(bind(&x, car(&xs, &position), element)),
+            });
+
+            let list = parse_expr(list, scope)?;
+            Ok(function.bind(run_loop(&lp, vec![xs], body, vec![list], &position)))
+        }
+
+        _ => {
+            let msg = format!("The '{}' expression takes exactly 2 arguments.", form);
+            Err(err(msg.as_str(), position))
+        }
+    }
+}
+
+/// Generates the names of the variables introduced by a derived form,
+/// which are unique to the position of the form.
+struct Names {
+    offset: usize,
+}
+
+impl Names {
+    fn new(position: &Position) -> Self {
+        Self {
+            offset: position.offset,
+        }
+    }
+
+    fn get(&self, name: &str) -> ast::Identifier {
+        ast::Identifier::new(format!("__{}_{}", name, self.offset).as_str())
+    }
+}
+
+/// A function passed to a derived form, which may be applied many times.
+struct FunctionArgument {
+    /// The head of the forms applying the function.
+    head: Expr,
+
+    /// The variable bound to the function, if it needs to be evaluated beforehand.
+    binding: Option<ast::Binding>,
+}
+
+impl FunctionArgument {
+    /// A symbol is applied as it is, so that it may also name a primitive such as `write-byte`.
+    /// Any other expression is evaluated once and bound to the variable of the given name.
+    fn new(function: &Expr, name: ast::Identifier, scope: &Scope) -> Result<Self> {
+        if let ExprKind::Atom(Atom::Symbol(_)) = function.kind {
+            return Ok(Self {
+                head: function.clone(),
+                binding: None,
+            });
+        }
+
+        Ok(Self {
+            head: Expr::symbol(&name.0, function.position.clone()),
+            binding: Some(ast::Binding {
+                lhs: name,
+                rhs: Box::new(parse_expr(function, scope)?),
+            }),
+        })
+    }
+
+    /// Returns the expression applying the function to the variables.
+    fn apply(
+        &self,
+        args: &[&ast::Identifier],
+        position: &Position,
+        scope: &Scope,
+    ) -> Result<ast::Expr> {
+        let mut elems = vec![self.head.clone()];
+        elems.extend(args.iter().map(|arg| Expr::symbol(&arg.0, position.clone())));
+        parse_expr(&Expr::list(elems, position.clone()), scope)
+    }
+
+    /// Wraps the expression in the binding of the function, if any.
+    fn bind(self, body: ast::Expr) -> ast::Expr {
+        match self.binding {
+            Some(binding) => ast::Expr::Let(ast::Let {
+                binding,
+                body: Box::new(body),
+            }),
+            None => body,
+        }
+    }
+}
+
+/// Returns the expression which runs the loop with the initial arguments.
+/// The body may call the loop itself by [recur].
+fn run_loop(
+    lp: &ast::Identifier,
+    params: Vec<ast::Identifier>,
+    body: ast::Expr,
+    args: Vec<ast::Expr>,
+    position: &Position,
+) -> ast::Expr {
+    let lambda = ast::Expr::Lambda(ast::Lambda {
+        id: ast::Identifier::new(format!("{}_lambda", lp.0).as_str()),
+        params: std::iter::once(lp.clone()).chain(params).collect(),
+        body: Box::new(body),
+    });
+    bind(lp, lambda, recur(lp, args, position))
+}
+
+/// Returns the expression which calls the loop with the arguments.
+fn recur(lp: &ast::Identifier, args: Vec<ast::Expr>, position: &Position) -> ast::Expr {
+    ast::Expr::App(ast::App {
+        function: Box::new(variable(lp)),
+        args: std::iter::once(variable(lp)).chain(args).collect(),
+        position: Some(position.clone()),
+    })
+}
+
+fn bind(lhs: &ast::Identifier, rhs: ast::Expr, body: ast::Expr) -> ast::Expr {
+    ast::Expr::Let(ast::Let {
+        binding: ast::Binding {
+            lhs: lhs.clone(),
+            rhs: Box::new(rhs),
+        },
+        body: Box::new(body),
+    })
+}
+
+fn variable(id: &ast::Identifier) -> ast::Expr {
+    ast::Expr::Variable(id.clone())
+}
+
+fn is_empty(list: &ast::Identifier, position: &Position) -> ast::Expr {
+    ast::Expr::Prim2(
+        ast::Op2::Eq,
+        Box::new(variable(list)),
+        Box::new(ast::Expr::Lit(ast::Lit::EmptyList)),
+        Some(position.clone()),
+    )
+}
+
+fn car(list: &ast::Identifier, position: &Position) -> ast::Expr {
+    ast::Expr::Prim1(ast::Op1::Car, Box::new(variable(list)), Some(position.clone()))
+}
+
+fn cdr(list: &ast::Identifier, position: &Position) -> ast::Expr {
+    ast::Expr::Prim1(ast::Op1::Cdr, Box::new(variable(list)), Some(position.clone()))
+}
@@ -6,22 +6,22 @@ use super::{
     lexer::{Token, TokenKind},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Expr {
     pub kind: ExprKind,
     pub position: Position,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ExprKind {
     Atom(Atom),
     List(List),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct List(pub Vec<Expr>);
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Atom {
     Symbol(String),
     Integer(i64),
@@ -0,0 +1,206 @@
+//! A conservative type check which rejects the primitive applications to literals of the wrong type,
+//! such as `(add1 #\a)` or `(car 5)`, before compiling them.
+//!
+//! Only literal operands are checked, since their types are known without evaluating anything.
+//! Any other operand, e.g. a variable, is left to the checks at runtime.
+//! An application is rejected even in code which is never evaluated, e.g. in a branch of `(if #f ...)`.
+
+use super::ast::{App, Case, Expr, If, Lambda, Let, Lit, Match, Op1, Op2, Op3, Program};
+use super::document::Position;
+use super::error::CompileError;
+
+/// The type an operand of a primitive must have.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expected {
+    Any,
+    Int,
+    Char,
+    String,
+    Box,
+    Cons,
+    Vector,
+    /// Either the empty list or a pair.
+    List,
+}
+
+impl Expected {
+    fn describe(self) -> &'static str {
+        match self {
+            Expected::Any => "any value",
+            Expected::Int => "an integer",
+            Expected::Char => "a character",
+            Expected::String => "a string",
+            Expected::Box => "a box",
+            Expected::Cons => "a pair",
+            Expected::Vector => "a vector",
+            Expected::List => "a list",
+        }
+    }
+
+    /// Returns whether a literal of the kind may be a value of this type.
+    fn accepts(self, lit: &Lit) -> bool {
+        matches!(
+            (self, lit),
+            (Expected::Any, _)
+                | (Expected::Int, Lit::Int(_))
+                | (Expected::Char, Lit::Char(_))
+                | (Expected::String, Lit::String(_))
+                | (Expected::List, Lit::EmptyList)
+        )
+    }
+}
+
+/// Returns an error for the first primitive application to a literal of the wrong type, if any.
+pub fn check(program: &Program) -> Result<(), CompileError> {
+    for definition in &program.function_definitions {
+        check_expr(&definition.body)?;
+    }
+    check_expr(&program.expr)
+}
+
+fn check_expr(expr: &Expr) -> Result<(), CompileError> {
+    match expr {
+        Expr::Eof | Expr::Lit(_) | Expr::Prim0(..) | Expr::Variable(_) => Ok(()),
+        Expr::Prim1(op, e, position) => {
+            check_operands(&[op1_operand(op)], &[e], position)?;
+            check_expr(e)
+        }
+        Expr::Prim2(op, e1, e2, position) => {
+            check_operands(&op2_operands(op), &[e1, e2], position)?;
+            check_expr(e1)?;
+            check_expr(e2)
+        }
+        Expr::Prim3(op, e1, e2, e3, position) => {
+            check_operands(&op3_operands(op), &[e1, e2, e3], position)?;
+            check_expr(e1)?;
+            check_expr(e2)?;
+            check_expr(e3)
+        }
+        Expr::Begin(e1, e2) => {
+            check_expr(e1)?;
+            check_expr(e2)
+        }
+        Expr::Let(Let { binding, body }) => {
+            check_expr(&binding.rhs)?;
+            check_expr(body)
+        }
+        Expr::App(App { function, args, .. }) => {
+            check_expr(function)?;
+            args.iter().try_for_each(check_expr)
+        }
+        Expr::If(If { cond, then, els }) => {
+            check_expr(cond)?;
+            check_expr(then)?;
+            check_expr(els)
+        }
+        Expr::Match(Match { expr, arms }) => {
+            check_expr(expr)?;
+            for arm in arms {
+                for (_, test) in arm.pattern.predicates() {
+                    check_expr(test)?;
+                }
+                check_expr(&arm.body)?;
+            }
+            Ok(())
+        }
+        Expr::Case(Case { expr, clauses, els }) => {
+            check_expr(expr)?;
+            for clause in clauses {
+                check_expr(&clause.body)?;
+            }
+            check_expr(els)
+        }
+        Expr::Lambda(Lambda { body, .. }) => check_expr(body),
+    }
+}
+
+fn check_operands(
+    expected: &[Expected],
+    operands: &[&Expr],
+    position: &Option<Position>,
+) -> Result<(), CompileError> {
+    for (i, (expected, operand)) in expected.iter().zip(operands).enumerate() {
+        let Expr::Lit(lit) = operand else {
+            continue;
+        };
+        if !expected.accepts(lit) {
+            return Err(CompileError::TypeMismatch {
+                msg: format!(
+                    "Expected {} as the argument {}, but got {}.",
+                    expected.describe(),
+                    i + 1,
+                    describe_literal(lit)
+                ),
+                position: position.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn describe_literal(lit: &Lit) -> &'static str {
+    match lit {
+        Lit::Int(_) => "an integer",
+        Lit::Bool(_) => "a boolean",
+        Lit::Char(_) => "a character",
+        Lit::String(_) => "a string",
+        Lit::EmptyList => "the empty list",
+        Lit::Void => "void",
+    }
+}
+
+fn op1_operand(op: &Op1) -> Expected {
+    match op {
+        Op1::Add1
+        | Op1::Sub1
+        | Op1::Negate
+        | Op1::BitNot
+        | Op1::IsZero
+        | Op1::IsPositive
+        | Op1::IsNegative => Expected::Int,
+        Op1::IntToChar | Op1::PeekByte | Op1::WriteByte => Expected::Int,
+        Op1::CharToInt | Op1::CharNext | Op1::CharPrev => Expected::Char,
+        Op1::WriteString | Op1::Error => Expected::String,
+        Op1::StringCopy
+        | Op1::StringLength
+        | Op1::StringUtf8Length
+        | Op1::StringUpcase
+        | Op1::StringDowncase => Expected::String,
+        Op1::Unbox => Expected::Box,
+        Op1::Car | Op1::Cdr => Expected::Cons,
+        Op1::Identity
+        | Op1::IsChar
+        | Op1::IsEof
+        | Op1::IsBox
+        | Op1::IsCons
+        | Op1::IsVector
+        | Op1::IsString
+        | Op1::Display
+        | Op1::Assert
+        | Op1::Box => Expected::Any,
+    }
+}
+
+fn op2_operands(op: &Op2) -> [Expected; 2] {
+    match op {
+        Op2::Add | Op2::Sub | Op2::Mul | Op2::Modulo | Op2::LessThan | Op2::IntEq => {
+            [Expected::Int, Expected::Int]
+        }
+        Op2::CharEq | Op2::CharLessThan | Op2::CharGreaterThan => [Expected::Char, Expected::Char],
+        Op2::StringEq => [Expected::String, Expected::String],
+        Op2::Eq | Op2::Cons => [Expected::Any, Expected::Any],
+        Op2::Assoc | Op2::Assq | Op2::Member | Op2::Memq => [Expected::Any, Expected::List],
+        Op2::MakeVector => [Expected::Int, Expected::Any],
+        Op2::MakeString => [Expected::Int, Expected::Char],
+        Op2::VectorRef => [Expected::Vector, Expected::Int],
+        Op2::StringRef => [Expected::String, Expected::Int],
+    }
+}
+
+fn op3_operands(op: &Op3) -> [Expected; 3] {
+    match op {
+        Op3::VectorSet => [Expected::Vector, Expected::Int, Expected::Any],
+        Op3::StringSet => [Expected::String, Expected::Int, Expected::Char],
+        Op3::Substring => [Expected::String, Expected::Int, Expected::Int],
+    }
+}
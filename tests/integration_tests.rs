@@ -1,4 +1,7 @@
-use compiler_course::{compile, ParserError, Position};
+use compiler_course::{
+    analyze, compile, compile_to_writer, compile_with_options, compile_with_source_map, tokenize,
+    CompileError, CompileOptions, ParserError, Platform, Position, Token, TokenKind, Warning,
+};
 
 #[test]
 fn negative_number() {
@@ -32,6 +35,249 @@ fn minus_operator() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn variadic_arithmetic() {
+    assert_eq!(run("(+)").unwrap(), "0");
+    assert_eq!(run("(+ 5)").unwrap(), "5");
+    assert_eq!(run("(- 5)").unwrap(), "-5");
+    assert_eq!(run("(+ 1 2 3 4)").unwrap(), "10");
+    assert_eq!(run("(- 10 1 2 3)").unwrap(), "4");
+    assert_eq!(run("(+ (identity #t))"), Err(Error::RuntimeError));
+
+    let result = run("(-)");
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert_eq!(err.msg, "The operator '-' takes at least 1 argument.");
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn multiplication() {
+    assert_eq!(run("(* 6 7)").unwrap(), "42");
+    assert_eq!(run("(* -3 4)").unwrap(), "-12");
+    assert_eq!(run("(* -3 -4 2)").unwrap(), "24");
+    assert_eq!(run("(* 5)").unwrap(), "5");
+    assert_eq!(run("(*)").unwrap(), "1");
+    assert_eq!(run("(let ((x 0)) (* x 123))").unwrap(), "0");
+    assert_eq!(run("(* 2 (identity #t))"), Err(Error::RuntimeError));
+
+    // The product out of the range of integers raises an error instead of wrapping around.
+    assert_eq!(run("(* 100000000 1000000000)").unwrap(), "100000000000000000");
+    assert_eq!(run("(* -100000000 1000000000)").unwrap(), "-100000000000000000");
+    assert_eq!(run("(* 1000000000 1000000000)"), Err(Error::RuntimeError));
+    assert_eq!(run("(* -1000000000 1000000000 1000000000)"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn negate_and_bitwise_not() {
+    assert_eq!(run("(- 5)").unwrap(), "-5");
+    assert_eq!(run("(let ((x -3)) (- x))").unwrap(), "3");
+    assert_eq!(run("(- (identity #t))"), Err(Error::RuntimeError));
+
+    assert_eq!(run("(bitwise-not 0)").unwrap(), "-1");
+    assert_eq!(run("(bitwise-not -1)").unwrap(), "0");
+    assert_eq!(run("(bitwise-not 41)").unwrap(), "-42");
+    assert_eq!(run("(bitwise-not (bitwise-not -7))").unwrap(), "-7");
+    assert_eq!(run("(bitwise-not (identity #\\a))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn positive_and_negative() {
+    assert_eq!(run("(positive? 1)").unwrap(), "#t");
+    assert_eq!(run("(positive? 0)").unwrap(), "#f");
+    assert_eq!(run("(positive? -1)").unwrap(), "#f");
+    assert_eq!(run("(negative? -1)").unwrap(), "#t");
+    assert_eq!(run("(negative? 0)").unwrap(), "#f");
+    assert_eq!(run("(negative? 1)").unwrap(), "#f");
+    assert_eq!(run("(positive? (identity #t))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn modulo() {
+    assert_eq!(run("(modulo 7 3)").unwrap(), "1");
+    assert_eq!(run("(modulo -7 3)").unwrap(), "2");
+    assert_eq!(run("(modulo 7 -3)").unwrap(), "-2");
+    assert_eq!(run("(modulo -7 -3)").unwrap(), "-1");
+    assert_eq!(run("(modulo -6 3)").unwrap(), "0");
+    assert_eq!(run("(modulo 1 0)"), Err(Error::RuntimeError));
+    assert_eq!(run("(modulo (identity #\\a) 2)"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn modulo_by_power_of_two() {
+    // A literal power of two takes the fast path, which must agree with the general one.
+    let mut fast = vec![];
+    let mut general = vec![];
+    for dividend in [-17, -16, -9, -8, -1, 0, 1, 7, 8, 17] {
+        for divisor in [1, 2, 8, 1024] {
+            fast.push(format!("(modulo {} {})", dividend, divisor));
+            general.push(format!("(modulo {} (add1 {}))", dividend, divisor - 1));
+        }
+    }
+    let fast = run(&format!("(list {})", fast.join(" "))).unwrap();
+    let general = run(&format!("(list {})", general.join(" "))).unwrap();
+    assert_eq!(fast, general);
+    assert_eq!(run("(modulo -5 4)").unwrap(), "3");
+    assert_eq!(run("(modulo 5 -4)").unwrap(), "-3");
+    assert_eq!(run("(modulo (identity #\\a) 4)"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn less_than() {
+    assert_eq!(run("(< 1 2)").unwrap(), "#t");
+    assert_eq!(run("(< 2 1)").unwrap(), "#f");
+    assert_eq!(run("(< 1 1)").unwrap(), "#f");
+    assert_eq!(run("(< -3 -2)").unwrap(), "#t");
+}
+
+#[test]
+fn comparison_chains() {
+    assert_eq!(run("(< 1 2 3)").unwrap(), "#t");
+    assert_eq!(run("(< 1 3 2)").unwrap(), "#f");
+    assert_eq!(run("(= 2 2 2)").unwrap(), "#t");
+    assert_eq!(run("(= 2 2 3)").unwrap(), "#f");
+    assert_eq!(run("(<= 1 1 2)").unwrap(), "#t");
+    assert_eq!(run("(<= 1 2 1)").unwrap(), "#f");
+    assert_eq!(run("(> 3 2 1)").unwrap(), "#t");
+    assert_eq!(run("(> 3 1 2)").unwrap(), "#f");
+    assert_eq!(run("(>= 2 2 1)").unwrap(), "#t");
+    assert_eq!(run("(>= 2 1 2)").unwrap(), "#f");
+}
+
+#[test]
+fn comparison_chain_evaluates_operands_once() {
+    let input = "(< (begin (write-byte 97) 1) (begin (write-byte 98) 2) (begin (write-byte 99) 0))";
+    assert_eq!(run(input).unwrap(), "abc#f");
+}
+
+#[test]
+fn compile_is_deterministic() {
+    // The closures capture many variables, whose order must not change from one compilation to another.
+    let source = "
+        (let ((a 1)) (let ((b 2)) (let ((c 3)) (let ((d 4)) (let ((e 5))
+          (let ((f (lambda (x) (+ x (+ e (+ d (+ c (+ b a))))))))
+            (let ((g (lambda (y) (- (f y) (+ a (+ c e))))))
+              (g 10))))))))";
+    let asm = compile(source).unwrap();
+    for _ in 0..10 {
+        assert_eq!(compile(source).unwrap(), asm);
+    }
+    assert_eq!(run(source).unwrap(), "16");
+}
+
+#[test]
+fn compile_to_writer_matches_compile() {
+    let source = "(define (f x) (add1 x)) (f \"abc\")";
+    let mut output = Vec::new();
+    compile_to_writer(source, Platform::Linux, &CompileOptions::default(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), compile(source).unwrap());
+
+    let mut output = Vec::new();
+    let options = CompileOptions::default();
+    let result = compile_to_writer("(add1 x)", Platform::Linux, &options, &mut output);
+    assert_eq!(result, Err(CompileError::UndefinedVariable("x".to_string())));
+}
+
+#[test]
+fn source_map() {
+    let source = "(define (f x) (add1 x))\n(car (f 1))";
+    let (asm, source_map) =
+        compile_with_source_map(source, Platform::Linux, &CompileOptions::default()).unwrap();
+    let lines: Vec<&str> = asm.lines().collect();
+
+    // Each entry points to a label on the line, before the code of the form.
+    for (line, _) in &source_map {
+        assert!(lines[line - 1].starts_with("source_"), "{}", lines[line - 1]);
+    }
+    let mut offsets: Vec<usize> = source_map.iter().map(|(_, position)| position.offset).collect();
+    offsets.sort();
+    assert_eq!(offsets, vec![15, 25, 30]);
+
+    // The source map does not change the code besides the labels.
+    let without_labels: Vec<&str> = lines
+        .into_iter()
+        .filter(|line| !line.starts_with("source_"))
+        .collect();
+    assert_eq!(without_labels.join("\n") + "\n", compile(source).unwrap());
+}
+
+#[test]
+fn cli_reports_compile_errors() {
+    let output = run_cli(&[], "(add1 1 2)");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Invalid AST at offset 1: The operator 'add1' takes 1 argument.\n"
+    );
+}
+
+#[test]
+fn cli_options() {
+    let output = run_cli(&["--platform", "macos"], "(add1 41)");
+    assert_eq!(output.status.code(), Some(0));
+    let asm = String::from_utf8(output.stdout).unwrap();
+    assert!(asm.contains("global _entry"), "{}", asm);
+
+    let output = run_cli(&["--emit", "ast"], "(add1 41)");
+    assert_eq!(output.status.code(), Some(0));
+    let ast = String::from_utf8(output.stdout).unwrap();
+    assert!(ast.starts_with("Program {"), "{}", ast);
+
+    let path = std::env::temp_dir().join(format!("cli_options_{}.rkt", std::process::id()));
+    std::fs::write(&path, "(add1 41)").unwrap();
+    let output = run_cli(&[path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(output.stdout, compile("(add1 41)").unwrap().into_bytes());
+
+    let map = std::env::temp_dir().join(format!("cli_options_{}.map", std::process::id()));
+    let output = run_cli(&["--source-map", map.to_str().unwrap()], "(add1 41)");
+    let source_map = std::fs::read_to_string(&map).unwrap();
+    std::fs::remove_file(&map).unwrap();
+    let (asm, expected) =
+        compile_with_source_map("(add1 41)", Platform::Linux, &CompileOptions::default()).unwrap();
+    assert_eq!(output.stdout, asm.into_bytes());
+    assert_eq!(source_map, format!("{} 1\n", expected[0].0));
+
+    let output = run_cli(&["--emit", "bytecode"], "");
+    assert_eq!(output.status.code(), Some(1));
+
+    // The flags change the compilation as the options do.
+    let source = "(define (f x) (if (zero? x) (unbox (box 0)) (f (sub1 x)))) (f 1)";
+    let options = CompileOptions {
+        tail_calls: false,
+        check_heap_pointers: true,
+        peephole: false,
+    };
+    let output = run_cli(&["--no-tail-calls", "--check-heap-pointers", "--no-peephole"], source);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, compile_with_options(source, &options).unwrap().into_bytes());
+    assert_ne!(output.stdout, compile(source).unwrap().into_bytes());
+}
+
+#[test]
+fn calling_non_function() {
+    for (input, position, msg) in [
+        ("(add1 ((add1 1)))", 6, "Is there an extra pair of parentheses?"),
+        ("(let ((x 1)) (42 x))", 14, "Attempted to call a literal as a function."),
+    ] {
+        let result = run(input);
+        match result {
+            Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+                assert_eq!(err.position, Position::new(position));
+                assert!(err.msg.contains(msg), "{}", err.msg);
+            }
+            _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+        }
+    }
+
+    // The result of an expression can be called if it may be a function.
+    assert_eq!(run("((car (cons (lambda (x) (add1 x)) ())) 41)").unwrap(), "42");
+}
+
 #[test]
 fn invalid_syntax() {
     let input = "(add1 42 43)";
@@ -50,6 +296,204 @@ fn invalid_syntax() {
     }
 }
 
+#[test]
+fn comments() {
+    let input = "
+        ; Adds one.
+        (define (f x) (add1 x)) ; This is a comment.
+        (f;no space
+         41)";
+    let result = run(input).unwrap();
+    let expected = "42";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn empty_program() {
+    for input in ["", "  \n\t ", "; nothing here\n;; nor here"] {
+        let result = run(input);
+
+        match result {
+            Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+                assert_eq!(err.position, Position::new(0));
+                assert!(err.msg.contains("The program is empty"), "{}", err.msg);
+            }
+            _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn only_definitions() {
+    let input = "(define (f x) x)\n(define (g x) (f x))";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert_eq!(err.position, Position::new(17));
+            assert!(err.msg.contains("no top-level expression"), "{}", err.msg);
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn wrong_number_of_arguments() {
+    let input = "(add1 (vector-ref (make-vector 1 0)))";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert_eq!(err.position, Position::new(7));
+            assert_eq!(err.msg, "The operator 'vector-ref' takes 2 arguments.");
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn tokenize_source() {
+    let tokens = tokenize("(add1 41) ; comment\n[#\\a \"hi\" #t]").unwrap();
+    let kinds: Vec<(TokenKind, usize)> = tokens
+        .into_iter()
+        .map(|Token { token, position }| (token, position.offset))
+        .collect();
+    let expected = vec![
+        (TokenKind::ParenOpen, 0),
+        (TokenKind::Symbol("add1".to_string()), 1),
+        (TokenKind::Integer(41), 6),
+        (TokenKind::ParenClose, 8),
+        (TokenKind::ParenOpen, 20),
+        (TokenKind::Character('a'), 21),
+        (TokenKind::String("hi".to_string()), 25),
+        (TokenKind::Boolean(true), 30),
+        (TokenKind::ParenClose, 32),
+    ];
+    assert_eq!(kinds, expected);
+
+    // The tokens are not parsed, so unbalanced parentheses are fine.
+    assert_eq!(tokenize(")(").unwrap().len(), 2);
+
+    let err = tokenize("(add1 \"oops").unwrap_err();
+    assert_eq!(err.position, Position::new(6));
+    assert_eq!(err.msg, "Unterminated string literal");
+}
+
+#[test]
+fn truncated_char_literal() {
+    let input = "(char? #\\";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(7));
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn unicode_char_literals() {
+    assert_eq!(run("(char->integer #\\u0041)").unwrap(), "65");
+    assert_eq!(run("(char->integer #\\u3bb)").unwrap(), "955");
+    assert_eq!(run("(char->integer #\\U0001F600)").unwrap(), "128512");
+    assert_eq!(run("(char->integer #\\λ)").unwrap(), "955");
+    assert_eq!(run("#\\λ").unwrap(), "#\\λ");
+
+    // Without hex digits, it is the letter itself.
+    assert_eq!(run("(cons #\\u #\\U)").unwrap(), "'(#\\u . #\\U)");
+
+    for input in ["(char? #\\uD800)", "(char? #\\U110000)"] {
+        match run(input) {
+            Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+                assert_eq!(err.position, Position::new(7));
+            }
+            result => panic!("Expected an invalid token error. Got: {:?}", result),
+        }
+    }
+}
+
+#[test]
+fn bare_hash() {
+    let input = "#";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(0));
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn unterminated_string() {
+    let input = "(string? \"abc";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(9));
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn unterminated_string_in_list() {
+    let input = "(write-byte \"ab)";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(12));
+            assert_eq!(err.msg, "Unterminated string literal");
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn integer_literal_out_of_range() {
+    let input = "(add1 99999999999999999999)";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(6));
+            assert_eq!(err.msg, "Integer literal out of range");
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn integer_literal_bounds() {
+    assert_eq!(run("576460752303423487").unwrap(), "576460752303423487");
+    assert_eq!(run("-576460752303423488").unwrap(), "-576460752303423488");
+    assert_eq!(
+        run("(add1 576460752303423488)"),
+        Err(Error::CompileError(CompileError::IntegerOutOfRange(
+            576460752303423488
+        )))
+    );
+    assert_eq!(
+        run("(match 0 [-576460752303423489 1] [_ 2])"),
+        Err(Error::CompileError(CompileError::IntegerOutOfRange(
+            -576460752303423489
+        )))
+    );
+}
+
+#[test]
+fn identifiers_with_special_characters() {
+    let input = "(define (string->number x) x) (define (set-box! b) (unbox b)) (set-box! (box (string->number 42)))";
+    assert_eq!(run(input).unwrap(), "42");
+
+    let input = "(define (a-b) 1) (define (a_2d_b) 2) (let ((-x (a-b))) (+ -x (a_2d_b)))";
+    assert_eq!(run(input).unwrap(), "3");
+}
+
 #[test]
 fn if_zero() {
     let input = "(if (zero? 0) 42 43)";
@@ -90,6 +534,29 @@ fn if_true() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn if_literal_condition_omits_dead_branch() {
+    let asm = compile("(if #t 42 (write-byte 97))").unwrap();
+    assert!(!asm.contains("call write_byte"), "{}", asm);
+
+    let asm = compile("(if #f (write-byte 97) 43)").unwrap();
+    assert!(!asm.contains("call write_byte"), "{}", asm);
+
+    assert_eq!(run("(if #f (write-byte 97) (write-byte 98))").unwrap(), "b");
+
+    // Errors in the dead branch are still reported.
+    assert_eq!(
+        compile("(if #t 42 x)"),
+        Err(CompileError::UndefinedVariable("x".to_string()))
+    );
+
+    // The branch taken stays in tail position.
+    let input = "
+        (define (count-down x) (if (zero? x) 42 (if #t (count-down (sub1 x)) 0)))
+        (count-down 10000000)";
+    assert_eq!(run(input).unwrap(), "42");
+}
+
 #[test]
 fn if_non_boolean() {
     let input = "(if -1 42 43)";
@@ -98,6 +565,75 @@ fn if_non_boolean() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn boolean_spellings() {
+    assert_eq!(run("#t").unwrap(), "#t");
+    assert_eq!(run("#true").unwrap(), "#t");
+    assert_eq!(run("#f").unwrap(), "#f");
+    assert_eq!(run("#false").unwrap(), "#f");
+    assert_eq!(run("(if #true 1 2)").unwrap(), "1");
+}
+
+#[test]
+fn invalid_boolean_spelling() {
+    let input = "(if #tru 1 2)";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::InvalidToken(err))) => {
+            assert_eq!(err.position, Position::new(4));
+        }
+        _ => panic!("Expected an invalid token error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn if_without_else() {
+    assert_eq!(run("(if #t 42)").unwrap(), "42");
+    assert_eq!(run("(if #f 42)").unwrap(), "");
+    assert_eq!(
+        run("(eq? (if #f 42) (vector-set! (make-vector 1 0) 0 0))").unwrap(),
+        "#t"
+    );
+}
+
+#[test]
+fn when_and_unless() {
+    assert_eq!(run("(when (zero? 0) 42)").unwrap(), "42");
+    assert_eq!(run("(when (zero? 1) 42)").unwrap(), "");
+    assert_eq!(run("(unless (zero? 1) 42)").unwrap(), "42");
+    assert_eq!(run("(unless (zero? 0) 42)").unwrap(), "");
+    assert_eq!(run("(when #t (write-byte 97) (write-byte 98) 42)").unwrap(), "ab42");
+
+    // The value of the taken branch must be usable in arithmetic.
+    assert_eq!(run("(+ 1 (when #t (add1 41)))").unwrap(), "43");
+    assert_eq!(run("(+ (unless #f 2) (when 0 40))").unwrap(), "42");
+
+    // The untaken branch results in void.
+    assert_eq!(run("(eq? (when #f 42) (unless #t 42))").unwrap(), "#t");
+    assert_eq!(run("(add1 (when #f 42))"), Err(Error::RuntimeError));
+
+    let result = run("(when #t)");
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert_eq!(err.position, Position::new(1));
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn if_without_else_tail_call() {
+    let input = "
+        (define (count-down x)
+            (if (< 0 x)
+                (count-down (sub1 x))))
+        (begin (count-down 1000000) 42)";
+    let result = run(input).unwrap();
+    let expected = "42";
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn is_char() {
     let input = "(char? #\\a)";
@@ -146,6 +682,125 @@ fn begin() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn begin_skips_pure_expressions() {
+    assert_eq!(compile("(begin 41 42)"), compile("42"));
+    assert_eq!(
+        compile("(let ((x 1)) (begin (char? x) x))"),
+        compile("(let ((x 1)) x)")
+    );
+    assert_ne!(compile("(begin (add1 (identity #t)) 42)"), compile("42"));
+    assert_eq!(run("(begin (add1 (identity #t)) 42)"), Err(Error::RuntimeError));
+
+    assert_eq!(
+        compile("(begin x 42)"),
+        Err(CompileError::UndefinedVariable("x".to_string()))
+    );
+}
+
+#[test]
+fn write_byte_returns_void() {
+    let input = "(eq? (write-byte 97) (vector-set! (make-vector 1 0) 0 0))";
+    let result = run(input).unwrap();
+    let expected = "a#t";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn flush_output() {
+    let input = "(begin (write-byte 97) (eq? (flush-output) (write-byte 98)))";
+    let result = run(input).unwrap();
+    let expected = "ab#t";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn write_invalid_byte() {
+    assert_eq!(run("(write-byte -1)"), Err(Error::RuntimeError));
+    assert_eq!(run("(write-byte 256)"), Err(Error::RuntimeError));
+    assert_eq!(run("(write-byte 0)").unwrap(), "\0");
+}
+
+#[test]
+fn nested_external_calls() {
+    // Each level of the recursion shifts the stack by a word, so that
+    // the external calls are made with both alignments of the stack pointer.
+    let source = "
+        (define (echo n)
+          (if (zero? n)
+              (write-byte (read-byte))
+              (let ((x (peek-byte)))
+                (begin (display n) (begin (echo (sub1 n)) (write-byte x))))))
+        (echo 5)";
+    let result = run_with_stdin(source, "ab").unwrap();
+    let expected = "54321aaaaaa";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn newline() {
+    let input = "(begin (write-byte 97) (newline))";
+    let result = run(input).unwrap();
+    let expected = "a\n";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn write_string() {
+    assert_eq!(run("(write-string \"hi\")").unwrap(), "hi");
+    assert_eq!(run("(write-string \"\")").unwrap(), "");
+    assert_eq!(run("(write-string \"λ→\")").unwrap(), "λ→");
+    assert_eq!(run("(write-string (identity 42))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn printf() {
+    assert_eq!(run("(printf \"x=~a\n\" 42)").unwrap(), "x=42\n");
+    assert_eq!(
+        run("(let ((s \"hi\")) (printf \"~a, ~a and ~a~a\" s #\\c (cons 1 2) \"!\"))").unwrap(),
+        "hi, c and (1 . 2)!"
+    );
+    assert_eq!(run("(printf \"\")").unwrap(), "");
+    // The result is void.
+    assert_eq!(run("(eq? (printf \"a\") (printf \"~a\" 1))").unwrap(), "a1#t");
+
+    // The arguments are evaluated before anything is written.
+    assert_eq!(run("(printf \"a~a\" (begin (write-byte 98) 1))").unwrap(), "ba1");
+    let output = execute("(printf \"a~a~a\" 1 (car (identity 1)))", "").unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+    // Only the runtime's report of the error is written, without the text before the argument.
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "err\n");
+
+    let cases = [
+        ("(printf 42)", "The 'printf' expression"),
+        ("(printf \"~a ~a\" 1)", "The format of 'printf' has more"),
+        ("(printf \"~a\" 1 2)", "The format of 'printf' has fewer"),
+        ("(printf \"~s\" 1)", "The format of 'printf' only supports"),
+    ];
+    for (input, msg) in cases {
+        match run(input) {
+            Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+                assert!(err.msg.starts_with(msg), "{}: {}", input, err.msg);
+            }
+            result => panic!("Expected an AST parsing error for {}. Got: {:?}", input, result),
+        }
+    }
+}
+
+#[test]
+fn display() {
+    assert_eq!(run("(display 42)").unwrap(), "42");
+    assert_eq!(run("(display #\\a)").unwrap(), "a");
+    assert_eq!(run("(display \"hi\")").unwrap(), "hi");
+    assert_eq!(run("(display #t)").unwrap(), "#t");
+    assert_eq!(
+        run("(display (cons 1 (cons \"a\" (cons #\\b (cons (box 2) ())))))").unwrap(),
+        "(1 a b #&2)"
+    );
+    assert_eq!(run("(display (cons 1 2))").unwrap(), "(1 . 2)");
+    assert_eq!(run("(display (make-vector 2 #\\x))").unwrap(), "#(x x)");
+}
+
 #[test]
 fn read_void() {
     let input = "(read-byte)";
@@ -154,6 +809,14 @@ fn read_void() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn eof_object() {
+    assert_eq!(run("(eof-object? (eof-object))").unwrap(), "#t");
+    assert_eq!(run("(eof-object? eof)").unwrap(), "#t");
+    assert_eq!(run("(eq? eof (eof-object))").unwrap(), "#t");
+    assert_eq!(run("(eof-object)").unwrap(), "#<eof>");
+}
+
 #[test]
 fn echo_back() {
     let source = "(write-byte (read-byte))";
@@ -172,20 +835,91 @@ fn peek_byte() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn peek_byte_with_offset() {
+    let source = "(let ((x (peek-byte 1))) (cons x (cons (read-byte) (peek-byte))))";
+    assert_eq!(run_with_stdin(source, "ab").unwrap(), "'(98 97 . 98)");
+    assert_eq!(run_with_stdin("(eof-object? (peek-byte 2))", "ab").unwrap(), "#t");
+    // The errors are raised before reading stdin, so it is empty lest writing it fail.
+    assert_eq!(run_with_stdin("(peek-byte -1)", ""), Err(Error::RuntimeError));
+    assert_eq!(run_with_stdin("(peek-byte (identity #\\a))", ""), Err(Error::RuntimeError));
+}
+
+#[test]
+fn read_byte_as_integer() {
+    assert_eq!(run_with_stdin("(add1 (read-byte))", "a").unwrap(), "98");
+    assert_eq!(run_with_stdin("(add1 (peek-byte))", "a").unwrap(), "98");
+    assert_eq!(run_with_stdin("(eof-object? (read-byte))", "").unwrap(), "#t");
+
+    // The bytes out of the ASCII range, including 255 which is not EOF.
+    let source = "(let ((x (read-byte))) (let ((y (read-byte))) (cons x (cons y (read-byte)))))";
+    assert_eq!(run_with_stdin(source, [0x80, 0xFF]).unwrap(), "'(128 255 . #<eof>)");
+    assert_eq!(run_with_stdin("(peek-byte)", [0xFF]).unwrap(), "255");
+}
+
 #[test]
 fn add_invalid_type() {
+    // A literal of the wrong type is rejected at compile time.
     let input = "(add1 #\\a)";
     let result = run(input).unwrap_err();
+    assert_eq!(
+        result,
+        Error::CompileError(CompileError::TypeMismatch {
+            msg: "Expected an integer as the argument 1, but got a character.".to_string(),
+            position: Some(Position::new(1)),
+        })
+    );
+
+    // Any other operand is checked at runtime.
+    let input = "(let ((x #\\a)) (add1 x))";
+    let result = run(input).unwrap_err();
     assert_eq!(result, Error::RuntimeError);
 }
 
+#[test]
+fn integer_checks_test_the_tag_bits() {
+    let asm = compile("(let ((x 1)) (add1 x))").unwrap();
+    assert!(asm.contains("\ttest rax, 15\n"), "{}", asm);
+
+    let values = ["#t", "#f", "#\\a", "()", "\"a\"", "(box 1)", "(cons 1 2)", "(make-vector 1 0)"];
+    for value in values {
+        let input = format!("(let ((x {})) (add1 x))", value);
+        assert_eq!(run(&input), Err(Error::RuntimeError), "{}", input);
+    }
+    assert_eq!(run("(let ((x -8)) (modulo (add1 x) (identity 3)))").unwrap(), "2");
+}
+
 #[test]
 fn sub_invalid_type() {
-    let input = "(sub1 #\\a)";
+    let input = "(sub1 (identity #\\a))";
     let result = run(input).unwrap_err();
     assert_eq!(result, Error::RuntimeError);
 }
 
+#[test]
+fn literals_of_wrong_types() {
+    let type_error = |source: &str| match compile(source) {
+        Err(CompileError::TypeMismatch { msg, position }) => (msg, position.unwrap().offset),
+        result => panic!("Expected a type error. Got: {:?}", result),
+    };
+    assert_eq!(
+        type_error("(car 5)"),
+        ("Expected a pair as the argument 1, but got an integer.".to_string(), 1)
+    );
+    assert_eq!(
+        type_error("(let ((v (make-vector 1 0))) (vector-set! v #t 0))"),
+        ("Expected an integer as the argument 2, but got a boolean.".to_string(), 30)
+    );
+    assert_eq!(type_error("(define (f x) (string-length ())) 1").1, 15);
+    assert_eq!(type_error("(if #f (+ 1 2 \"3\") 0)").1, 8);
+
+    // Variables and the other expressions are left to the checks at runtime.
+    assert!(compile("(define (f x) (add1 x)) (f 1)").is_ok());
+    assert!(compile("(car (cons 1 2))").is_ok());
+    assert!(compile("(member 1 ())").is_ok());
+    assert!(compile("(cons #t #\\a)").is_ok());
+}
+
 #[test]
 fn invalid_codepoint_to_char() {
     let input = "(integer->char 99999999)";
@@ -193,6 +927,75 @@ fn invalid_codepoint_to_char() {
     assert_eq!(result, Error::RuntimeError);
 }
 
+#[test]
+fn invalid_codepoint_message() {
+    let input = "(integer->char 55296)";
+    let message = run_error_message(input);
+    assert_eq!(message, "integer->char: 55296 is a surrogate code point\n");
+
+    let input = "(integer->char 99999999)";
+    let message = run_error_message(input);
+    assert_eq!(message, "integer->char: 99999999 is out of the range of Unicode code points\n");
+
+    let input = "(integer->char -1)";
+    let message = run_error_message(input);
+    assert_eq!(message, "integer->char: -1 is out of the range of Unicode code points\n");
+}
+
+#[test]
+fn char_to_int_round_trip() {
+    let codepoints = [
+        0, 1, 0x7F, 0x80, 0x7FF, 0x800, 55295, 57344, 0xFFFF, 0x10000, 0x10FFFE, 0x10FFFF,
+    ];
+    let exprs: Vec<String> = codepoints
+        .iter()
+        .map(|n| format!("(char->integer (integer->char {}))", n))
+        .collect();
+    let input = format!("(list {})", exprs.join(" "));
+    let expected: Vec<String> = codepoints.iter().map(|n| n.to_string()).collect();
+    assert_eq!(run(&input).unwrap(), format!("'({})", expected.join(" ")));
+
+    for n in [55296, 57343] {
+        let message = run_error_message(&format!("(integer->char {})", n));
+        assert_eq!(message, format!("integer->char: {} is a surrogate code point\n", n));
+    }
+    let message = run_error_message("(integer->char 1114112)");
+    assert!(message.contains("out of the range of Unicode code points"), "{}", message);
+}
+
+#[test]
+fn char_next_and_prev() {
+    assert_eq!(run("(char-next #\\a)").unwrap(), "#\\b");
+    assert_eq!(run("(char-prev #\\b)").unwrap(), "#\\a");
+    assert_eq!(run("(char-prev (char-next #\\z))").unwrap(), "#\\z");
+    assert_eq!(run("(char-next (identity 97))"), Err(Error::RuntimeError));
+
+    let message = run_error_message("(char-prev (integer->char 0))");
+    assert!(message.contains("out of the range of Unicode code points"), "{}", message);
+}
+
+#[test]
+fn char_comparisons() {
+    assert_eq!(run("(char=? #\\a #\\a)").unwrap(), "#t");
+    assert_eq!(run("(char=? #\\a #\\b)").unwrap(), "#f");
+    assert_eq!(run("(char<? #\\a #\\b)").unwrap(), "#t");
+    assert_eq!(run("(char<? #\\b #\\a)").unwrap(), "#f");
+    assert_eq!(run("(char<? #\\a #\\a)").unwrap(), "#f");
+    assert_eq!(run("(char>? #\\b #\\a)").unwrap(), "#t");
+    assert_eq!(run("(char>? #\\a #\\b)").unwrap(), "#f");
+    assert_eq!(run("(char<? #\\Z (integer->char 955))").unwrap(), "#t");
+    assert_eq!(run("(char=? #\\a (identity 97))"), Err(Error::RuntimeError));
+    assert_eq!(run("(char<? (identity 97) #\\b)"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn int_to_char_twice() {
+    let input = "(begin (integer->char 97) (integer->char 98))";
+    let result = run(input).unwrap();
+    let expected = "#\\b";
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn let_expression() {
     let input = "(let ((x 42)) x)";
@@ -265,6 +1068,130 @@ fn cons() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn cons_star() {
+    assert_eq!(run("(cons* 1 2 3)").unwrap(), "'(1 2 . 3)");
+    assert_eq!(run("(cdr (cdr (cons* 1 2 3)))").unwrap(), "3");
+    assert_eq!(run("(cons* 1 2 ())").unwrap(), "'(1 2)");
+    assert_eq!(run("(cons* 42)").unwrap(), "42");
+
+    let result = run("(cons*)");
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert!(err.msg.contains("at least 1 argument"), "{}", err.msg);
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn list() {
+    assert_eq!(run("(list 1 2 3)").unwrap(), "'(1 2 3)");
+    assert_eq!(run("(list)").unwrap(), "'()");
+    assert_eq!(run("(list (list 1) #t)").unwrap(), "'((1) #t)");
+}
+
+#[test]
+fn for_each() {
+    assert_eq!(run("(for-each write-byte (list 97 98 99))").unwrap(), "abc");
+    assert_eq!(run("(for-each write-byte ())").unwrap(), "");
+    assert_eq!(
+        run("(let ((k 1)) (for-each (lambda (x) (write-byte (+ x k))) (list 97 98)))").unwrap(),
+        "bc"
+    );
+    assert_eq!(
+        run("(eq? (for-each write-byte (list 97)) (write-byte 98))").unwrap(),
+        "ab#t"
+    );
+
+    // The function is evaluated only once.
+    let input = "(for-each (begin (write-byte 97) (lambda (x) (write-byte x))) (list 98 99))";
+    assert_eq!(run(input).unwrap(), "abc");
+
+    assert_eq!(run("(for-each write-byte (cons 97 98))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn map() {
+    assert_eq!(run("(map (lambda (x) (add1 x)) (list 1 2 3))").unwrap(), "'(2 3 4)");
+    assert_eq!(run("(map add1 (list 1 2 3))").unwrap(), "'(2 3 4)");
+    assert_eq!(run("(map add1 ())").unwrap(), "'()");
+    assert_eq!(
+        run("(let ((k 10)) (map (lambda (x) (+ x k)) (list 1 2)))").unwrap(),
+        "'(11 12)"
+    );
+
+    // The function is applied in order.
+    let input = "(map (lambda (x) (begin (write-byte x) x)) (list 97 98 99))";
+    assert_eq!(run(input).unwrap(), "abc'(97 98 99)");
+
+    assert_eq!(run("(map add1 (cons 1 2))"), Err(Error::RuntimeError));
+    assert_eq!(run("(map 42 (list 1))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn filter() {
+    let input = "(filter (lambda (x) (zero? (modulo x 2))) (list 1 2 3 4))";
+    assert_eq!(run(input).unwrap(), "'(2 4)");
+    assert_eq!(run("(filter zero? (list 0 1 0))").unwrap(), "'(0 0)");
+    assert_eq!(run("(filter zero? ())").unwrap(), "'()");
+
+    // Any value other than #f counts as true.
+    assert_eq!(run("(filter (lambda (x) x) (list 1 #f 0 #t))").unwrap(), "'(1 0 #t)");
+
+    assert_eq!(run("(filter zero? (cons 0 1))"), Err(Error::RuntimeError));
+    assert_eq!(run("(filter #t (list 1))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn assoc() {
+    let alist = "(list (cons 1 #\\a) (cons 2 #\\b) (cons 2 #\\c))";
+    assert_eq!(run(&format!("(assoc 2 {})", alist)).unwrap(), "'(2 . #\\b)");
+    assert_eq!(run(&format!("(assoc 3 {})", alist)).unwrap(), "#f");
+    assert_eq!(run("(assoc 1 ())").unwrap(), "#f");
+
+    // The keys are compared structurally.
+    let input = "(assoc (list 1 \"a\") (list (cons (list 1 (string-copy \"a\")) #t)))";
+    assert_eq!(run(input).unwrap(), "'((1 \"a\") . #t)");
+
+    assert_eq!(run("(assoc 1 (cons 2 3))"), Err(Error::RuntimeError));
+    assert_eq!(run("(assoc 1 (list 1 2))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn assq() {
+    assert_eq!(run("(assq 2 (list (cons 1 #t) (cons 2 #f)))").unwrap(), "'(2 . #f)");
+    assert_eq!(run("(assq #\\z (list (cons #\\a 1)))").unwrap(), "#f");
+
+    // The keys are compared by identity.
+    let input = "(let ((b (box 1))) (assq b (list (cons (box 1) 1) (cons b 2))))";
+    assert_eq!(run(input).unwrap(), "'(#&1 . 2)");
+    assert_eq!(run("(assq (box 1) (list (cons (box 1) 1)))").unwrap(), "#f");
+}
+
+#[test]
+fn member() {
+    assert_eq!(run("(member 2 (list 1 2 3))").unwrap(), "'(2 3)");
+    assert_eq!(run("(member 4 (list 1 2 3))").unwrap(), "#f");
+    assert_eq!(run("(member 1 ())").unwrap(), "#f");
+
+    // The elements are compared structurally.
+    let input = "(member (cons 1 2) (list (box 0) (cons 1 2)))";
+    assert_eq!(run(input).unwrap(), "'((1 . 2))");
+
+    assert_eq!(run("(member 3 (cons 1 2))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn memq() {
+    assert_eq!(run("(memq #\\b (list #\\a #\\b))").unwrap(), "'(#\\b)");
+    assert_eq!(run("(memq (box 1) (list (box 1)))").unwrap(), "#f");
+
+    // The elements are compared by identity.
+    let input = "(let ((b (box 1))) (memq b (list (box 1) b 2)))";
+    assert_eq!(run(input).unwrap(), "'(#&1 2)");
+}
+
 #[test]
 fn car() {
     let input = "(car (cons 42 (cons 43 ())))";
@@ -336,6 +1263,53 @@ fn vector_ref_out_of_bounds() {
     assert_eq!(result, Error::RuntimeError);
 }
 
+#[test]
+fn vector_ref_out_of_bounds_message() {
+    let input = "(vector-ref (make-vector 3 42) 5)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 5, length 3\n");
+
+    let input = "(vector-ref (make-vector 0 42) 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 0, length 0\n");
+
+    // Type errors are not reported as index errors.
+    let input = "(vector-ref (identity 42) 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "");
+}
+
+#[test]
+fn negative_index() {
+    let input = "(vector-ref (make-vector 3 42) -1)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index must be a nonnegative integer: -1\n");
+
+    let input = "(vector-set! (make-vector 3 42) -2 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index must be a nonnegative integer: -2\n");
+
+    let input = "(string-ref \"abc\" -3)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index must be a nonnegative integer: -3\n");
+
+    // A non-integer index is a type error.
+    let input = "(string-ref \"abc\" (identity #\\a))";
+    let message = run_error_message(input);
+    assert_eq!(message, "");
+}
+
+#[test]
+fn vector_set_out_of_bounds() {
+    let input = "(vector-set! (make-vector 2 42) 2 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 2, length 2\n");
+
+    let input = "(vector-set! (make-vector 0 42) 0 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 0, length 0\n");
+}
+
 #[test]
 fn vector_set() {
     let input = "(let ((v (make-vector 3 42))) (begin (vector-set! v 1 43) v))";
@@ -352,6 +1326,28 @@ fn empty_vector() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn build_vector() {
+    assert_eq!(run("(build-vector 3 (lambda (i) (add1 i)))").unwrap(), "'#(1 2 3)");
+    assert_eq!(run("(build-vector 0 (lambda (i) (add1 i)))").unwrap(), "'#()");
+    assert_eq!(
+        run("(let ((k 10)) (build-vector 3 (lambda (i) (+ i k))))").unwrap(),
+        "'#(10 11 12)"
+    );
+
+    // The index function is called in order.
+    assert_eq!(
+        run("(build-vector 3 (lambda (i) (begin (write-byte (+ 97 i)) i)))").unwrap(),
+        "abc'#(0 1 2)"
+    );
+
+    // The function may be the name of a primitive, as in `map`.
+    assert_eq!(run("(build-vector 3 add1)").unwrap(), "'#(1 2 3)");
+
+    assert_eq!(run("(build-vector -1 (lambda (i) i))"), Err(Error::RuntimeError));
+    assert_eq!(run("(build-vector 2 42)"), Err(Error::RuntimeError));
+}
+
 #[test]
 fn make_string() {
     let input = "(make-string 3 #\\a)";
@@ -360,6 +1356,14 @@ fn make_string() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn make_string_default_fill() {
+    // The fill defaults to the null character.
+    assert_eq!(run("(string-ref (make-string 2) 0)").unwrap(), "#\\nul");
+    assert_eq!(run("(string-length (make-string 2))").unwrap(), "2");
+    assert!(run("(make-string)").is_err());
+}
+
 #[test]
 fn is_string() {
     let input = "(string? (make-string 3 #\\a))";
@@ -392,6 +1396,23 @@ fn string_literals_equal() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn identical_string_literals_share_data() {
+    let asm = compile("(begin (write-string \"abc\") (write-string \"abc\"))").unwrap();
+    let definitions: Vec<_> = asm.lines().filter(|line| line.starts_with("string_data_")).collect();
+    assert_eq!(definitions, vec!["string_data_0:"], "{}", asm);
+    assert_eq!(asm.matches("[string_data_0 + 4]").count(), 2, "{}", asm);
+}
+
+#[test]
+fn string_literals_overlapping() {
+    // The prefix and the suffix are stored apart from the longer literal, keeping their own lengths.
+    let input = "(begin (write-string \"hel\") (begin (write-string \"hello\") (write-string \"llo\")))";
+    assert_eq!(run(input).unwrap(), "helhellollo");
+    let input = "(cons (string-length \"hel\") (cons (string-length \"hello\") (string-length \"llo\")))";
+    assert_eq!(run(input).unwrap(), "'(3 5 . 3)");
+}
+
 #[test]
 fn string_ref() {
     let input = "(string-ref \"abc\" 1)";
@@ -401,26 +1422,172 @@ fn string_ref() {
 }
 
 #[test]
-fn string_out_of_bounds() {
-    let input = "(string-ref \"abc\" 3)";
-    let result = run(input).unwrap_err();
-    assert_eq!(result, Error::RuntimeError);
+fn string_out_of_bounds() {
+    let input = "(string-ref \"abc\" 3)";
+    let result = run(input).unwrap_err();
+    assert_eq!(result, Error::RuntimeError);
+}
+
+#[test]
+fn string_out_of_bounds_message() {
+    let input = "(string-ref \"abc\" 3)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 3, length 3\n");
+
+    let input = "(string-ref \"\" 0)";
+    let message = run_error_message(input);
+    assert_eq!(message, "index out of range: index 0, length 0\n");
+}
+
+#[test]
+fn empty_string() {
+    let input = "(make-string 0 #\\a)";
+    let result = run(input).unwrap();
+    let expected = "\"\"";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn empty_string_literal() {
+    let input = "\"\"";
+    let result = run(input).unwrap();
+    let expected = "\"\"";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn odd_length_strings() {
+    assert_eq!(run("(string-ref (make-string 3 #\\z) 2)").unwrap(), "#\\z");
+    assert_eq!(run("(string-ref \"abc\" 2)").unwrap(), "#\\c");
+    assert_eq!(
+        run_error_message("(string-ref (make-string 3 #\\z) 3)"),
+        "index out of range: index 3, length 3\n"
+    );
+
+    // The padding keeps the following string literals aligned.
+    assert_eq!(
+        run("(cons \"a\" (cons \"bcd\" (cons \"ef\" ())))").unwrap(),
+        "'(\"a\" \"bcd\" \"ef\")"
+    );
+}
+
+#[test]
+fn string_patterns() {
+    assert_eq!(run("(match \"ab\" [\"ab\" 1] [_ 2])").unwrap(), "1");
+    assert_eq!(run("(match \"abc\" [\"ab\" 1] [_ 2])").unwrap(), "2");
+    assert_eq!(run("(match \"ab\" [\"abc\" 1] [_ 2])").unwrap(), "2");
+    assert_eq!(run("(match (make-string 0 #\\a) [\"\" 1] [_ 2])").unwrap(), "1");
+    assert_eq!(run("(match (make-string 0 #\\a) [\"a\" 1] [_ 2])").unwrap(), "2");
+    assert_eq!(run("(match (make-string 3 #\\a) [\"aaa\" 1] [_ 2])").unwrap(), "1");
+}
+
+#[test]
+fn string_copy() {
+    let input = "
+        (let ((s (make-string 3 #\\a)))
+            (let ((t (string-copy s)))
+                (begin (string-set! t 2 #\\z) (cons s t))))";
+    assert_eq!(run(input).unwrap(), "'(\"aaa\" . \"aaz\")");
+
+    let input = "(let ((s (string-copy \"hello\"))) (begin (string-set! s 0 #\\j) s))";
+    assert_eq!(run(input).unwrap(), "\"jello\"");
+
+    assert_eq!(run("(string-copy (make-string 0 #\\a))").unwrap(), "\"\"");
+    assert_eq!(run("(string-copy \"\")").unwrap(), "\"\"");
+    assert_eq!(run("(let ((s \"abc\")) (eq? s (string-copy s)))").unwrap(), "#f");
+}
+
+#[test]
+fn string_length() {
+    assert_eq!(run("(string-length \"abc\")").unwrap(), "3");
+    assert_eq!(run("(string-length \"\")").unwrap(), "0");
+    assert_eq!(run("(string-length (make-string 5 #\\a))").unwrap(), "5");
+    assert_eq!(run("(string-length (identity #\\a))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn string_utf8_length() {
+    assert_eq!(run("(string-utf8-length \"abc\")").unwrap(), "3");
+    assert_eq!(run("(string-utf8-length \"\")").unwrap(), "0");
+    assert_eq!(run("(string-utf8-length \"aλ€😀\")").unwrap(), "10");
+    assert_eq!(run("(string-length \"aλ€😀\")").unwrap(), "4");
+    assert_eq!(run("(string-utf8-length (make-string 3 #\\λ))").unwrap(), "6");
+    assert_eq!(run("(string-utf8-length (identity #\\a))"), Err(Error::RuntimeError));
+}
+
+#[test]
+fn substring() {
+    assert_eq!(run("(substring \"hello\" 1 3)").unwrap(), "\"el\"");
+    assert_eq!(run("(substring \"hello\" 0 5)").unwrap(), "\"hello\"");
+    assert_eq!(run("(substring \"hello\" 2 2)").unwrap(), "\"\"");
+    assert_eq!(
+        run_error_message("(substring \"hello\" 1 6)"),
+        "index out of range: index 6, length 5\n"
+    );
+    assert_eq!(
+        run_error_message("(substring \"hello\" 3 2)"),
+        "index out of range: index 3, length 5\n"
+    );
+
+    // The substring does not share the characters with the original string.
+    let input = "
+        (let ((s (make-string 3 #\\a)))
+            (let ((t (substring s 1 2)))
+                (begin (string-set! s 1 #\\b) (cons s t))))";
+    assert_eq!(run(input).unwrap(), "'(\"aba\" . \"a\")");
+}
+
+#[test]
+fn substring_to_end() {
+    assert_eq!(
+        run("(substring \"hello\" 1)").unwrap(),
+        run("(substring \"hello\" 1 5)").unwrap()
+    );
+    assert_eq!(run("(substring \"hello\" 5)").unwrap(), "\"\"");
+    assert_eq!(run("(substring \"\" 0)").unwrap(), "\"\"");
+
+    // The string is evaluated only once.
+    let input = "(substring (begin (write-byte 97) \"hello\") 3)";
+    assert_eq!(run(input).unwrap(), "a\"lo\"");
+}
+
+#[test]
+fn string_equal() {
+    assert_eq!(run("(string=? \"abc\" \"abc\")").unwrap(), "#t");
+    assert_eq!(run("(string=? \"abc\" (string-copy \"abc\"))").unwrap(), "#t");
+    assert_eq!(run("(string=? \"abc\" \"abd\")").unwrap(), "#f");
+    assert_eq!(run("(string=? \"abc\" \"ab\")").unwrap(), "#f");
+    assert_eq!(run("(string=? \"\" (make-string 0 #\\a))").unwrap(), "#t");
+    assert_eq!(run("(string=? \"\" \"a\")").unwrap(), "#f");
+    assert_eq!(run("(string=? \"a\" (identity #\\a))"), Err(Error::RuntimeError));
 }
 
 #[test]
-fn empty_string() {
-    let input = "(make-string 0 #\\a)";
-    let result = run(input).unwrap();
-    let expected = "\"\"";
-    assert_eq!(result, expected);
+fn string_case_conversion() {
+    assert_eq!(run("(string-upcase \"abc\")").unwrap(), "\"ABC\"");
+    assert_eq!(run("(string-upcase \"a1b2-Z\")").unwrap(), "\"A1B2-Z\"");
+    assert_eq!(run("(string-downcase \"Hello, World!\")").unwrap(), "\"hello, world!\"");
+    assert_eq!(run("(string-upcase \"\")").unwrap(), "\"\"");
+    assert_eq!(run("(string-downcase (make-string 3 #\\Q))").unwrap(), "\"qqq\"");
+
+    // The original string is left unchanged.
+    let input = "(let ((s (make-string 2 #\\a))) (cons (string-upcase s) s))";
+    assert_eq!(run(input).unwrap(), "'(\"AA\" . \"aa\")");
+
+    assert_eq!(run("(string-upcase (identity #\\a))"), Err(Error::RuntimeError));
 }
 
 #[test]
-fn empty_string_literal() {
-    let input = "\"\"";
-    let result = run(input).unwrap();
-    let expected = "\"\"";
-    assert_eq!(result, expected);
+fn string_set() {
+    assert_eq!(run("(string-set! (make-string 1 #\\a) 0 #\\b)").unwrap(), "");
+    assert_eq!(
+        run_error_message("(string-set! (make-string 2 #\\a) 2 #\\z)"),
+        "index out of range: index 2, length 2\n"
+    );
+    assert_eq!(run("(string-set! (make-string 2 #\\a) 0 (identity 98))"), Err(Error::RuntimeError));
+
+    // String literals are immutable.
+    assert_eq!(run("(string-set! \"abc\" 0 #\\z)"), Err(Error::RuntimeError));
 }
 
 #[test]
@@ -485,6 +1652,40 @@ fn tail_call() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn tail_call_in_let_body() {
+    // The tail call drops the let binding along with the arguments, so each iteration leaves nothing on the stack.
+    let input = "
+        (define (loop n)
+            (let ((m (sub1 n)))
+                (if (zero? m) 0 (loop m))))
+        (loop 10000000)";
+
+    let result = run(input).unwrap();
+    let expected = "0";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn mutual_tail_calls_across_branches() {
+    // Without proper tail calls, a million nested frames would overflow the stack.
+    let input = "
+        (define (even? x)
+            (if (zero? x)
+                #t
+                (let ((y (sub1 x)))
+                    (begin (add1 y) (odd? y)))))
+        (define (odd? x)
+            (match x
+                [0 #f]
+                [_ (if (zero? (sub1 x)) (even? 0) (even? (sub1 x)))]))
+        (even? 1000000)";
+
+    let result = run(input).unwrap();
+    let expected = "#t";
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn pattern_match_wildcard() {
     let input = "(match 42 [_ #t])";
@@ -555,6 +1756,20 @@ fn pattern_match_with_let() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn pattern_match_fallthrough_with_let() {
+    let input = "
+        (let ((x -30))
+            (match 2
+                [1 x]
+                [2 (+ x 1)]
+                [_ 0]))";
+
+    let result = run(input).unwrap();
+    let expected = "-29";
+    assert_eq!(result, expected);
+}
+
 #[test]
 fn pattern_match_and() {
     let input = "
@@ -566,6 +1781,237 @@ fn pattern_match_and() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn pattern_match_deeply_nested() {
+    let input = "
+        (match (cons (cons 1 (cons 2 3)) 4)
+            [(cons (and a (cons b _)) c) (list a b c)])";
+    assert_eq!(run(input).unwrap(), "'((1 2 . 3) 1 4)");
+
+    let input = "
+        (match (cons 1 (cons 2 (box 3)))
+            [(cons a (and b (cons c (box (and d e))))) (list a b c d e)])";
+    assert_eq!(run(input).unwrap(), "'(1 (2 . #&3) 2 3 3)");
+
+    let input = "
+        (match (cons (cons 1 2) (cons 3 4))
+            [(and (cons (cons a b) _) (cons _ (cons c d))) (list d c b a)])";
+    assert_eq!(run(input).unwrap(), "'(4 3 2 1)");
+
+    let input = "
+        (let ((z 9))
+            (match (cons 1 (box 2))
+                [(cons (and a 1) (box (and b c))) (list a b c z)]))";
+    assert_eq!(run(input).unwrap(), "'(1 2 2 9)");
+}
+
+#[test]
+fn pattern_match_fallthrough_after_partial_match() {
+    // The next arm matches the original value, not the parts left over by the failed arm.
+    let input = "
+        (match (cons 1 2)
+            [(cons 5 _) 0]
+            [(cons a b) (cons b a)])";
+    assert_eq!(run(input).unwrap(), "'(2 . 1)");
+
+    let input = "
+        (match (box (cons 1 2))
+            [(box (cons 3 _)) 0]
+            [(box (cons a b)) b])";
+    assert_eq!(run(input).unwrap(), "2");
+
+    let input = "
+        (match (cons 1 (cons 2 3))
+            [(cons a (cons b (and c 4))) 0]
+            [x x])";
+    assert_eq!(run(input).unwrap(), "'(1 2 . 3)");
+}
+
+#[test]
+fn pattern_match_arm_with_many_bodies() {
+    let input = "
+        (match (cons 104 105)
+            [(cons a b) (write-byte a) (write-byte b) (+ a b)])";
+    assert_eq!(run(input).unwrap(), "hi209");
+
+    // The last expression is in tail position.
+    let input = "
+        (define (f n) (match n [0 0] [k (add1 0) (f (sub1 k))]))
+        (f 100000)";
+    assert_eq!(run(input).unwrap(), "0");
+
+    assert!(matches!(run("(match 1 [x])"), Err(Error::ParserError(_))));
+}
+
+#[test]
+fn unreachable_match_arms() {
+    let input = "(match 1 [1 0] [_ 1] [2 2] [x 3])";
+    let warnings = analyze(input).unwrap();
+    let msg = "This match arm is unreachable, since a previous arm always matches.";
+    assert_eq!(
+        warnings,
+        vec![
+            Warning {
+                msg: msg.to_string(),
+                position: Some(Position::new(21)),
+            },
+            Warning {
+                msg: msg.to_string(),
+                position: Some(Position::new(27)),
+            },
+        ]
+    );
+
+    // The unreachable arms are still compiled.
+    assert_eq!(run(input).unwrap(), "0");
+
+    // An `and` pattern always matches if both of the subpatterns do.
+    assert_eq!(analyze("(match 1 [(and x _) 0] [_ 1])").unwrap().len(), 1);
+    assert_eq!(analyze("(match 1 [(and x 1) 0] [_ 1])").unwrap(), vec![]);
+
+    // The arms nested in functions are also analyzed.
+    let input = "(define (f x) (lambda () (match x [y y] [_ 0]))) 0";
+    assert_eq!(analyze(input).unwrap().len(), 1);
+}
+
+#[test]
+fn pattern_match_predicate() {
+    let input = "
+        (define (f x) (match x [(? string? s) s] [(? (lambda (n) (< n 10)) n) (add1 n)] [_ 0]))
+        (list (f \"a\") (f 5) (f 20))";
+    assert_eq!(run(input).unwrap(), "'(\"a\" 6 0)");
+
+    // Strings made at runtime can be matched against a variable with `string=?`.
+    let input = "
+        (let ((y \"abc\"))
+            (match (string-copy \"abc\")
+                [(? (lambda (x) (string=? x y))) 1]
+                [_ 2]))";
+    assert_eq!(run(input).unwrap(), "1");
+
+    // The test is evaluated in the scope outside the pattern.
+    let input = "(let ((x 1)) (match (cons 2 2) [(cons x (? (lambda (v) (= v x)))) 0] [_ 1]))";
+    assert_eq!(run(input).unwrap(), "1");
+
+    let input = "
+        (define (count-chars l)
+            (match l
+                [(cons (? char?) r) (add1 (count-chars r))]
+                [(cons _ r) (count-chars r)]
+                [_ 0]))
+        (count-chars (list #\\a 1 #\\b))";
+    assert_eq!(run(input).unwrap(), "2");
+}
+
+#[test]
+fn pattern_match_in_lambda() {
+    let input = "(define (f n) (match n [0 0] [k (f (sub1 k))])) (f 10)";
+    assert_eq!(run(input).unwrap(), "0");
+
+    // A variable bound by the pattern shadows the free variable of the same name.
+    let input = "(let ((k 5)) (let ((f (lambda (n) (match n [k (add1 k)])))) (f 1)))";
+    assert_eq!(run(input).unwrap(), "2");
+}
+
+#[test]
+fn tail_call_in_match_arm() {
+    // Each iteration binds `m`, `a` and `b` and stashes the two scrutinees, all of which the tail call must drop.
+    let input = "
+        (define (loop p n)
+            (match n
+                [0 p]
+                [m (match p
+                    [(cons a b) (loop p (sub1 m))])]))
+        (loop (cons 1 2) 1000000)";
+    let result = run(input).unwrap();
+    assert_eq!(result, "'(1 . 2)");
+}
+
+#[test]
+fn case_with_dense_integers() {
+    let definition = "
+        (define (f n)
+            (case n
+                [(0) \"zero\"] [(1) \"one\"] [(2 3) \"two or three\"] [(4) \"four\"] [(5) \"five\"]
+                [(6) \"six\"] [(7) \"seven\"] [(8 2) \"eight\"] [(9 10) \"nine or ten\"]
+                [else \"other\"]))";
+
+    // The keys 0 to 10 are dispatched through a jump table.
+    let asm = compile(&format!("{} (f 0)", definition)).unwrap();
+    assert!(asm.contains("case_table_"), "{}", asm);
+
+    let cases = [
+        ("0", "zero"),
+        ("2", "two or three"),
+        ("3", "two or three"),
+        ("8", "eight"),
+        ("10", "nine or ten"),
+        ("11", "other"),
+        ("-1", "other"),
+        ("#\\a", "other"),
+        ("#f", "other"),
+    ];
+    for (key, expected) in cases {
+        let input = format!("{} (write-string (f {}))", definition, key);
+        assert_eq!(run(&input).unwrap(), expected, "{}", key);
+    }
+}
+
+#[test]
+fn case_with_other_datums() {
+    // The datums other than dense integers are compared one by one.
+    let definition = "
+        (define (f x)
+            (case x [(1 1000) 1] [(#\\a #\\b) 2] [(\"abc\" ()) 3] [(#t) 4] [(1) 6] [else 5]))";
+    let asm = compile(&format!("{} (f 0)", definition)).unwrap();
+    assert!(!asm.contains("case_table_"), "{}", asm);
+
+    let input = format!(
+        "{} (cons (f 1000) (cons (f #\\b) (cons (f \"abc\") (cons (f ()) (cons (f #t) (cons (f 2) (f \"ab\")))))))",
+        definition
+    );
+    assert_eq!(run(&input).unwrap(), "'(1 2 3 3 4 5 . 5)");
+    // The first clause listing the value is taken.
+    assert_eq!(run(&format!("{} (f 1)", definition)).unwrap(), "1");
+
+    // Without the else clause, no match results in void.
+    assert_eq!(run("(case 3 [(1 2) 42])").unwrap(), "");
+    assert_eq!(run("(case 3)").unwrap(), "");
+    // The clauses may have many expressions, evaluated in order as in `begin`.
+    assert_eq!(run("(case (add1 1) [(1 2) (write-byte 97) 42] [else 0])").unwrap(), "a42");
+}
+
+#[test]
+fn case_syntax_errors() {
+    let cases = [
+        ("(case)", "The 'case' expression"),
+        ("(case 1 [else 2] [(1) 3])", "The 'else' clause must be the last one."),
+        ("(case 1 [(1)])", "Case clause should be"),
+        ("(case 1 [1 2])", "Case clause should be"),
+        ("(case 1 [(x) 2])", "A datum should be"),
+    ];
+    for (input, msg) in cases {
+        match run(input) {
+            Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+                assert!(err.msg.starts_with(msg), "{}: {}", input, err.msg);
+            }
+            result => panic!("Expected an AST parsing error for {}. Got: {:?}", input, result),
+        }
+    }
+}
+
+#[test]
+fn tail_call_in_case_clause() {
+    let input = "
+        (define (loop n)
+            (case n
+                [(0) 42]
+                [(1 2 3 4 5) (loop (sub1 n))]
+                [else (loop (- n 5))]))
+        (loop 1000000)";
+    assert_eq!(run(input).unwrap(), "42");
+}
+
 #[test]
 fn lambda_function() {
     let input = "((lambda (x y) (+ x y)) 42 1)";
@@ -586,6 +2032,51 @@ fn lambda_with_free_variable() {
     assert_eq!(result, expected);
 }
 
+#[test]
+fn lambda_with_several_free_variables() {
+    // The variables are bound, captured, and used in different orders,
+    // so that the result changes if the captured values are permuted.
+    let input = "
+        (let ((c 1)) (let ((a 100)) (let ((b 10))
+            ((lambda () (- a (- b c)))))))";
+    assert_eq!(run(input).unwrap(), "91");
+
+    let input = "
+        (let ((z 3)) (let ((x 1)) (let ((y 2))
+            (let ((f (lambda (w) (cons y (cons w (cons z (cons x ()))))))) (f 0)))))";
+    assert_eq!(run(input).unwrap(), "'(2 0 3 1)");
+}
+
+#[test]
+fn value_definition() {
+    let input = "(define x 1) (define (f) x) (f)";
+    let result = run(input);
+
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert_eq!(err.position, Position::new(8));
+            assert!(err.msg.contains("Only functions can be defined"), "{}", err.msg);
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn undefined_variable() {
+    let cases = [
+        ("((lambda (x) (+ x y)) 1)", "y"),
+        ("(let ((x 1)) (+ x y))", "y"),
+        ("(define (f x) (g x)) (f 1)", "g"),
+    ];
+    for (input, variable) in cases {
+        let result = run(input);
+        let expected = Err(Error::CompileError(CompileError::UndefinedVariable(
+            variable.to_string(),
+        )));
+        assert_eq!(result, expected, "{}", input);
+    }
+}
+
 #[test]
 fn lambda_tail_call() {
     let input = "((lambda (x) x) 42)";
@@ -595,16 +2086,302 @@ fn lambda_tail_call() {
     assert_eq!(result, expected);
 }
 
-fn run_with_stdin(source: &str, input: &str) -> Result<String, Error> {
+#[test]
+fn computed_function() {
+    let input = "
+        (define (f x) (add1 x))
+        (define (g x) (sub1 x))
+        (cons ((if #t f g) 10) ((if #f f g) 10))";
+    let result = run(input).unwrap();
+    let expected = "'(11 . 9)";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn duplicate_parameters() {
+    for (input, offset) in [
+        ("(lambda (x y x) x)", 13),
+        ("(define (f x x) x) (f 1 2)", 13),
+    ] {
+        let result = run(input);
+        match result {
+            Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+                assert_eq!(err.msg, "Duplicate parameter `x`.");
+                assert_eq!(err.position, Position::new(offset));
+            }
+            _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+        }
+    }
+
+    assert_eq!(run("((lambda (x y) (- x y)) 3 1)").unwrap(), "2");
+}
+
+#[test]
+fn shadowing_variables() {
+    assert_eq!(run("(let ((x 1)) (let ((x 2)) x))").unwrap(), "2");
+    assert_eq!(run("(let ((x 1)) (cons (let ((x 2)) x) x))").unwrap(), "'(2 . 1)");
+    assert_eq!(run("(let ((x 1)) ((lambda (x) x) 2))").unwrap(), "2");
+    assert_eq!(run("(define (f x) (let ((x (add1 x))) x)) (f 1)").unwrap(), "2");
+    assert_eq!(run("(let ((x 1)) (match 2 [x x]))").unwrap(), "2");
+    assert_eq!(run("(let ((x 1)) (let ((f (lambda () x))) (let ((x 2)) (f))))").unwrap(), "1");
+}
+
+#[test]
+fn nested_lambda() {
+    let input = "(((lambda (x) (lambda (y) (+ x y))) 40) 2)";
+    let result = run(input).unwrap();
+    let expected = "42";
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn higher_order_functions() {
+    assert_eq!(run("(let ((f (lambda (x) (add1 x)))) (f 5))").unwrap(), "6");
+    assert_eq!(
+        run("(define (make-adder n) (lambda (x) (+ x n))) ((make-adder 1) 5)").unwrap(),
+        "6"
+    );
+    assert_eq!(
+        run("(let ((fs (cons (lambda (x) (add1 x)) ()))) ((car fs) 5))").unwrap(),
+        "6"
+    );
+}
+
+#[test]
+fn closures_capturing_nothing() {
+    // Closures without free variables are shared by all of their instances.
+    assert_eq!(run("(define (id) (lambda (x) x)) (eq? (id) (id))").unwrap(), "#t");
+    assert_eq!(
+        run("(define (adder n) (lambda (x) (+ x n))) (eq? (adder 1) (adder 1))").unwrap(),
+        "#f"
+    );
+
+    // They can be mixed with the closures in the heap.
+    let input = "
+        (define (inc x) (add1 x))
+        (define (even? n) (if (zero? n) #t (odd? (sub1 n))))
+        (define (odd? n) (if (zero? n) #f (even? (sub1 n))))
+        (define (count-up n acc) (if (zero? n) acc (count-up (sub1 n) (inc acc))))
+        (let ((twice (lambda (f x) (f (f x)))))
+            (cons (twice inc 0)
+                (cons (even? 10)
+                    (cons (count-up 5 0) ((lambda (x) (+ x 1)) 41)))))";
+    assert_eq!(run(input).unwrap(), "'(2 #t 5 . 42)");
+}
+
+#[test]
+fn identity_and_const() {
+    assert_eq!(run("(identity 42)").unwrap(), "42");
+    assert_eq!(run("((identity (lambda (x) (add1 x))) 41)").unwrap(), "42");
+    assert_eq!(run("((const 42) #t)").unwrap(), "42");
+
+    let input = "
+        (define (map f xs) (if (eq? xs ()) () (cons (f (car xs)) (map f (cdr xs)))))
+        (let ((xs (cons 1 (cons 2 ()))))
+            (map (identity (const #\\a)) xs))";
+    assert_eq!(run(input).unwrap(), "'(#\\a #\\a)");
+
+    // The value is evaluated only once.
+    assert_eq!(
+        run("(let ((f (const (begin (write-byte 97) 1)))) (+ (f 1) (f 2)))").unwrap(),
+        "a2"
+    );
+}
+
+#[test]
+fn multiple_values() {
+    assert_eq!(run("(call-with-values (lambda () (values 1 2)) +)").unwrap(), "3");
+    assert_eq!(
+        run("(call-with-values (lambda () (values 1 2)) (lambda (a b) (cons b a)))").unwrap(),
+        "'(2 . 1)"
+    );
+
+    let input = "
+        (define (split-first xs) (values (car xs) (cdr xs)))
+        (call-with-values (lambda () (split-first (cons 1 (cons 2 ())))) cons)";
+    assert_eq!(run(input).unwrap(), "'(1 2)");
+
+    // The consumer is evaluated before the producer is called.
+    let input = "
+        (call-with-values
+            (lambda () (begin (write-byte 98) (values 1 2)))
+            (begin (write-byte 97) (lambda (a b) (+ a b))))";
+    assert_eq!(run(input).unwrap(), "ab3");
+
+    let result = run("(call-with-values (lambda () (values 1 2 3)) +)");
+    match result {
+        Err(Error::ParserError(ParserError::AstPasringError(err))) => {
+            assert!(err.msg.contains("exactly 2 arguments"), "{}", err.msg);
+        }
+        _ => panic!("Expected an AST parsing error. Got: {:?}", result),
+    }
+}
+
+#[test]
+fn shadowing_primitives() {
+    assert_eq!(run("(let ((add1 (lambda (x) (+ x 10)))) (add1 2))").unwrap(), "12");
+    assert_eq!(run("(define (f car) (car 1)) (f (lambda (x) (sub1 x)))").unwrap(), "0");
+    assert_eq!(run("((lambda (if) (if 1 2 3)) (lambda (a b c) (+ a c)))").unwrap(), "4");
+    assert_eq!(run("(match (lambda (a b) a) [cons (cons 1 2)])").unwrap(), "1");
+    assert_eq!(run("(let ((eof 42)) eof)").unwrap(), "42");
+    assert_eq!(run("(define (display x) (add1 x)) (display 41)").unwrap(), "42");
+
+    // The shadowing is lexical, so the primitive is available outside of the scope.
+    assert_eq!(
+        run("(cons (let ((add1 (lambda (x) x))) (add1 0)) (add1 0))").unwrap(),
+        "'(0 . 1)"
+    );
+}
+
+#[test]
+fn disabling_tail_calls() {
+    let input = "
+        (define (count-down x)
+            (if (zero? x)
+                42
+                (count-down (sub1 x))))
+        (count-down 10000000)";
+
+    let output = execute(input, "").unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "42");
+
+    // Without the tail call optimization, every call grows the stack until it overflows.
+    let options = CompileOptions {
+        tail_calls: false,
+        ..Default::default()
+    };
+    let output = execute_with_options(input, "", &options).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+
+    let input = input.replace("10000000", "10");
+    let output = execute_with_options(&input, "", &options).unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "42");
+}
+
+#[test]
+fn stack_overflow() {
+    let input = "
+        (define (sum n)
+            (if (zero? n)
+                0
+                (+ n (sum (sub1 n)))))
+        (sum 10000000)";
+    assert_eq!(run(input), Err(Error::RuntimeError));
+
+    let message = run_error_message(input);
+    assert!(message.contains("stack overflow"), "{}", message);
+
+    let input = input.replace("10000000", "10000");
+    assert_eq!(run(&input), Ok("50005000".to_string()));
+}
+
+#[test]
+fn assert() {
+    assert_eq!(run("(begin (assert (zero? 0)) (begin (assert 0) 42))"), Ok("42".to_string()));
+    assert_eq!(run("(eq? (assert #t) (write-byte 97))"), Ok("a#t".to_string()));
+    assert_eq!(run("(assert #f)"), Err(Error::RuntimeError));
+
+    let message = run_error_message("(assert (zero? 1))");
+    assert!(message.contains("assertion failed"), "{}", message);
+}
+
+#[test]
+fn user_error() {
+    assert_eq!(run("(error \"boom\")"), Err(Error::RuntimeError));
+    assert_eq!(run("(if #t 1 (error \"boom\"))"), Ok("1".to_string()));
+    assert_eq!(run("(error (identity 1))"), Err(Error::RuntimeError));
+
+    let message = run_error_message("(begin (write-byte 97) (error \"boom λ\"))");
+    assert_eq!(message, "error: boom λ\n");
+    assert_eq!(run_error_message("(error \"\")"), "error: \n");
+}
+
+#[test]
+fn checking_heap_pointers() {
+    let options = CompileOptions {
+        check_heap_pointers: true,
+        ..Default::default()
+    };
+    let input = "
+        (let ((xs (cons (box 1) (cons 2 ()))))
+            (cons (unbox (car xs)) (car (cdr xs))))";
+    assert_ne!(
+        compile_with_options(input, &options),
+        compile_with_options(input, &CompileOptions::default())
+    );
+
+    let output = execute_with_options(input, "", &options).unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "'(1 . 2)");
+
+    let output = execute_with_options("(car (identity 1))", "", &options).unwrap();
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+
+    // A pointer tagged as a pair passes the type check, but it points to the global variable `heap`.
+    // The language cannot make such a pointer, so the literal in the assembly is replaced with it.
+    let input = "(car (identity 12345))";
+    let forge = |asm: String| {
+        let literal = format!("\tmov rax, {}\n", 12345 << 4);
+        assert_eq!(asm.matches(&literal).count(), 1, "{}", asm);
+        asm.replace(&literal, "\tlea rax, heap\n\tadd rax, 2\n")
+    };
+
+    let asm = forge(compile_with_options(input, &options).unwrap());
+    let output = execute_asm(&asm, "");
+    assert_eq!(output.status.code(), Some(1), "{:?}", output);
+
+    let asm = forge(compile_with_options(input, &CompileOptions::default()).unwrap());
+    let output = execute_asm(&asm, "");
+    assert!(output.status.success(), "{:?}", output);
+}
+
+fn run_with_stdin(source: &str, input: impl AsRef<[u8]>) -> Result<String, Error> {
+    let output = execute(source, input)?;
+
+    if output.status.code() == Some(1) {
+        return Err(Error::RuntimeError);
+    }
+
+    if !output.status.success() {
+        panic!("process failed with the output: {:?}", output);
+    }
+
+    let stdout = String::from_utf8(output.stdout).expect("invalid utf8");
+    Ok(stdout)
+}
+
+/// Runs the program which is expected to raise a runtime error, and returns what it writes to stderr.
+fn run_error_message(source: &str) -> String {
+    let output = execute(source, "").unwrap();
+    assert_eq!(output.status.code(), Some(1), "Expected a runtime error. Got: {:?}", output);
+    String::from_utf8(output.stderr).expect("invalid utf8")
+}
+
+/// Compiles and executes the program, returning its exit status and outputs.
+fn execute(source: &str, input: impl AsRef<[u8]>) -> Result<std::process::Output, Error> {
+    execute_with_options(source, input, &CompileOptions::default())
+}
+
+fn execute_with_options(
+    source: &str,
+    input: impl AsRef<[u8]>,
+    options: &CompileOptions,
+) -> Result<std::process::Output, Error> {
+    let asm = compile_with_options(source, options)?;
+    Ok(execute_asm(&asm, input))
+}
+
+/// Assembles, links and executes the assembly, returning its exit status and outputs.
+fn execute_asm(asm: &str, input: impl AsRef<[u8]>) -> std::process::Output {
     use std::io::Write;
     use std::process::{Command, Stdio};
 
-    let asm = compile(source)?;
     println!("{}", asm);
 
-    let asm_filename = format!("out/{}.asm", hash_str(&asm));
-    let asm_output = format!("out/{}.o", hash_str(&asm));
-    let bin = format!("out/{}.run", hash_str(&asm));
+    let asm_filename = format!("out/{}.asm", hash_str(asm));
+    let asm_output = format!("out/{}.o", hash_str(asm));
+    let bin = format!("out/{}.run", hash_str(asm));
 
     println!("{}", asm_filename);
 
@@ -628,6 +2405,7 @@ fn run_with_stdin(source: &str, input: &str) -> Result<String, Error> {
     let mut child = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
         .expect("failed to execute process");
 
@@ -636,21 +2414,36 @@ fn run_with_stdin(source: &str, input: &str) -> Result<String, Error> {
         .as_mut()
         .take()
         .expect("Failed to open stdin")
-        .write_all(input.as_bytes())
+        .write_all(input.as_ref())
+        // The program may exit without reading all of stdin.
+        .or_else(|err| match err.kind() {
+            std::io::ErrorKind::BrokenPipe => Ok(()),
+            _ => Err(err),
+        })
         .expect("Failed to write to stdin");
 
-    let output = child.wait_with_output().expect("failed to execute process");
-
-    if output.status.code() == Some(1) {
-        return Err(Error::RuntimeError);
-    }
+    child.wait_with_output().expect("failed to execute process")
+}
 
-    if !output.status.success() {
-        panic!("process failed with the output: {:?}", output);
-    }
+/// Runs the compiler binary with the given arguments and stdin.
+fn run_cli(args: &[&str], input: &str) -> std::process::Output {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
 
-    let stdout = String::from_utf8(output.stdout).expect("invalid utf8");
-    Ok(stdout)
+    let mut child = Command::new(env!("CARGO_BIN_EXE_compiler-course"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to execute process");
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin")
+        .write_all(input.as_bytes())
+        .expect("Failed to write to stdin");
+    child.wait_with_output().expect("failed to execute process")
 }
 
 fn run(source: &str) -> Result<String, Error> {
@@ -669,6 +2462,7 @@ fn hash_str(str: &str) -> String {
 #[derive(Debug, PartialEq)]
 enum Error {
     ParserError(ParserError),
+    CompileError(CompileError),
     RuntimeError,
 }
 
@@ -677,3 +2471,12 @@ impl From<ParserError> for Error {
         Error::ParserError(err)
     }
 }
+
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        match err {
+            CompileError::ParserError(err) => Error::ParserError(err),
+            err => Error::CompileError(err),
+        }
+    }
+}